@@ -0,0 +1,457 @@
+use std::{
+    io::{ErrorKind, Result as IoResult},
+    net::{self, SocketAddr},
+    rc::Rc,
+    cell::RefCell,
+    sync::Arc,
+    thread,
+    time::Duration
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime,
+    task::LocalSet,
+    time::timeout
+};
+
+use super::{
+    Method,
+    Request,
+    Response,
+    StatusCode,
+    parsed_response::ParsedResponse,
+    server::{ResponseOptions, Refusal, MAX_HEAD_SIZE, LINGER_LIMIT, check_body, expects_continue, refuse}
+};
+
+/// How long a connection may wait before starting its next request, after that it is closed without an answer.
+/// Once a request has started each read gets the server's [timeout](super::Server::set_timeout) instead
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the rest of a refused body is read and thrown away for before closing
+const LINGER_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// The async counterpart of [RequestHandler](super::RequestHandler), for [Server::run_async()](super::Server::run_async).
+/// Handlers get the same [Request] and [Response], but can `.await` while waiting on I/O without holding up a thread.
+/// The response is written to the socket once the handler returns, so [Response::stream()] is buffered until then
+/// and responses that take over the connection (like [EventStream](super::event_stream::EventStream)) aren't supported
+// each worker runs its connections on one thread, so the futures don't have to be Send
+#[allow(async_fn_in_trait)]
+pub trait AsyncRequestHandler {
+    async fn handle(&self, req: &Request<'_>, res: &mut Response) -> IoResult<()> {
+        let result = match req.method() {
+            Method::GET => self.get(req, res).await,
+            Method::POST => self.post(req, res).await,
+            Method::DELETE => self.delete(req, res).await,
+            Method::PUT => self.put(req, res).await,
+            Method::HEAD => self.head(req, res).await,
+            Method::CONNECT => self.connect(req, res).await,
+            Method::OPTIONS => self.options(req, res).await,
+            Method::TRACE => self.trace(req, res).await,
+            Method::PATCH => self.patch(req, res).await,
+        };
+
+        if let Err(e) = &result {
+            eprintln!("Sending response failed with error {}", e);
+        }
+        result
+    }
+
+    async fn handle_bad(&self, res: &mut Response, body: &str) -> IoResult<()> {
+        if let Err(e) = res.send_error(StatusCode::BadRequest, body) {
+            eprintln!("Sending 400 response failed with error {}", e);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The largest body accepted for `req`, like [RequestHandler::max_body_size()](super::RequestHandler::max_body_size)
+    fn max_body_size(&self, _req: &Request<'_>) -> Option<usize> { None }
+
+    async fn get(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+    async fn delete(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+    async fn post(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+    async fn put(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+    async fn head(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+    async fn connect(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+    async fn options(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+    async fn trace(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+    async fn patch(&self, _req: &Request<'_>, res: &mut Response) -> IoResult<()> { res.send_404() }
+}
+
+/// Accepts connections from `listener` on `workers` threads, each with its own single threaded epoll runtime.
+/// Never returns, like [Server::run()](super::Server::run)
+pub(super) fn run<H: AsyncRequestHandler + Send + Sync + 'static>(
+    listener: &net::TcpListener,
+    workers: usize,
+    handler: Arc<H>,
+    options: &ResponseOptions,
+    max_body_size: usize,
+    read_timeout: Option<Duration>
+) -> IoResult<()> {
+    listener.set_nonblocking(true)?;
+
+    // every worker accepts from the same socket, the kernel hands each connection to one of them
+    let mut threads = Vec::new();
+    for i in 1..workers.max(1) {
+        let listener = listener.try_clone()?;
+        let handler = handler.clone();
+        let options = options.clone();
+        threads.push(thread::Builder::new()
+            .name(format!("async-worker-{}", i))
+            .spawn(move || worker(listener, handler, options, max_body_size, read_timeout))?);
+    }
+    let result = worker(listener.try_clone()?, handler, options.clone(), max_body_size, read_timeout);
+    for thread in threads {
+        let _ = thread.join();
+    }
+    result
+}
+
+fn worker<H: AsyncRequestHandler + 'static>(
+    listener: net::TcpListener,
+    handler: Arc<H>,
+    options: ResponseOptions,
+    max_body_size: usize,
+    read_timeout: Option<Duration>
+) -> IoResult<()> {
+    let runtime = runtime::Builder::new_current_thread().enable_all().build()?;
+    let local = LocalSet::new();
+
+    local.block_on(&runtime, async move {
+        let listener = TcpListener::from_std(listener)?;
+        loop {
+            let (stream, remote_addr) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    eprintln!("Failed to establish a connection {}", e);
+                    continue;
+                }
+            };
+
+            let handler = handler.clone();
+            let options = options.clone();
+            tokio::task::spawn_local(async move {
+                if let Err(e) = serve_connection(stream, remote_addr, handler.as_ref(), &options, max_body_size, read_timeout).await {
+                    eprintln!("Something went wrong serving {}: {}", remote_addr, e);
+                }
+            });
+        }
+    })
+}
+
+/// Answers requests on the connection until the client or a response closes it
+async fn serve_connection(
+    mut stream: TcpStream,
+    remote_addr: SocketAddr,
+    handler: &impl AsyncRequestHandler,
+    options: &ResponseOptions,
+    max_body_size: usize,
+    read_timeout: Option<Duration>
+) -> IoResult<()> {
+    // anything read past the end of a request belongs to the next one
+    let mut buffer = Vec::new();
+    loop {
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut response = Response::new(output.clone());
+
+        let mut refused = false;
+        let keep_alive = match read_request(&mut stream, &mut buffer, handler, max_body_size, read_timeout).await {
+            Ok(Some((bytes, None))) => respond(handler, &bytes, remote_addr, &mut response, options).await,
+            Ok(Some((bytes, Some(refusal)))) => {
+                if let Err(e) = refuse(&bytes, &refusal, &mut response, options) {
+                    eprintln!("Something went wrong sending response:{}\n{:?}", e, response);
+                }
+                refused = true;
+                false
+            },
+            // closed between requests, or left idle
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                options.prepare(&mut response, None);
+                if let Err(e) = response.send_error(StatusCode::RequestTimeout, "The request took too long to arrive") {
+                    eprintln!("Something went wrong sending response:{}\n{:?}", e, response);
+                }
+                false
+            },
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                options.prepare(&mut response, None);
+                if let Err(e) = handler.handle_bad(&mut response, &e.to_string()).await {
+                    eprintln!("Something went wrong sending response:{}\n{:?}", e, response);
+                }
+                false
+            },
+            Err(e) => return Err(e)
+        };
+
+        let output = output.take();
+        stream.write_all(&output).await?;
+        // the handler can still end the connection, ex by streaming a body to an HTTP/1.0 client
+        let reusable = keep_alive && ParsedResponse::read_head(&mut &output[..]).is_ok_and(|res| res.keep_alive());
+        if !reusable {
+            stream.shutdown().await?;
+            if refused {
+                linger(&mut stream).await;
+            }
+            return Ok(());
+        }
+    }
+}
+
+/// Hands the request to `handler` like [respond()](super::server::respond), returning whether the connection can be kept open
+async fn respond(
+    handler: &impl AsyncRequestHandler,
+    bytes: &[u8],
+    remote_addr: SocketAddr,
+    response: &mut Response,
+    options: &ResponseOptions
+) -> bool {
+    let (result, keep_alive) = match Request::try_from(bytes) {
+        Ok(mut req) => {
+            req.set_remote_addr(Some(remote_addr));
+            println!("Recieved a request: {:?}", req);
+            options.prepare(response, Some(&req));
+
+            let keep_alive = match req.version() {
+                "HTTP/1.0" => req.headers().has_token("Connection", "keep-alive"),
+                _ => !req.headers().has_token("Connection", "close")
+            };
+            if keep_alive {
+                response.set_header("Connection", "keep-alive");
+            }
+            (handler.handle(&req, response).await, keep_alive)
+        },
+        Err(e) => {
+            eprintln!("Error converting bytes to result: {}", e);
+            options.prepare(response, None);
+            (handler.handle_bad(response, &e.to_string()).await, false)
+        }
+    };
+
+    match result {
+        Ok(()) => keep_alive,
+        Err(e) => {
+            eprintln!("Something went wrong sending response:{}\n{:?}", e, response);
+            false
+        }
+    }
+}
+
+/// Reads the next request, its head and then its body if [check_body()] allows it, like the thread pool does.
+/// None when the connection closed or stayed idle before another request started, a read that takes longer than
+/// `read_timeout` after that fails with [TimedOut](ErrorKind::TimedOut)
+async fn read_request(
+    stream: &mut TcpStream,
+    buffer: &mut Vec<u8>,
+    handler: &impl AsyncRequestHandler,
+    max_body_size: usize,
+    read_timeout: Option<Duration>
+) -> IoResult<Option<(Vec<u8>, Option<Refusal>)>> {
+    let mut chunk = [0; 4096];
+    let head_length = loop {
+        let head_end = buffer.windows(4).position(|w| w == b"\r\n\r\n");
+        if head_end.unwrap_or(buffer.len()) > MAX_HEAD_SIZE {
+            return Err(err!(InvalidData, "Request head too large", "The request line and headers are over {} bytes", MAX_HEAD_SIZE));
+        }
+        if let Some(i) = head_end {
+            break i + 4;
+        }
+
+        let read = match buffer.is_empty() {
+            true => match timeout(IDLE_TIMEOUT, stream.read(&mut chunk)).await {
+                Ok(read) => read?,
+                Err(_) => return Ok(None)
+            },
+            false => read_within(stream, &mut chunk, read_timeout).await?
+        };
+        if read == 0 {
+            return match buffer.is_empty() {
+                true => Ok(None),
+                false => Err(err!(UnexpectedEof, "Request cut short", "The connection closed in the middle of a request"))
+            };
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    // a head that doesn't parse is handed over as is, for respond() to reject
+    let length = match Request::try_from(&buffer[..head_length]) {
+        Ok(req) => {
+            let max_body_size = handler.max_body_size(&req).unwrap_or(max_body_size);
+            match check_body(&req, head_length, max_body_size) {
+                Ok(length) if buffer.len() < length && expects_continue(&req) => {
+                    stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+                    length
+                },
+                Ok(length) => length,
+                Err(refusal) => return Ok(Some((std::mem::take(buffer), Some(refusal))))
+            }
+        },
+        Err(_) => head_length
+    };
+
+    while buffer.len() < length {
+        let read = read_within(stream, &mut chunk, read_timeout).await?;
+        if read == 0 {
+            return Err(err!(UnexpectedEof, "Body cut short", "The connection closed before the whole body was sent"));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let rest = buffer.split_off(length);
+    Ok(Some((std::mem::replace(buffer, rest), None)))
+}
+
+/// Reads into `chunk`, failing with [TimedOut](ErrorKind::TimedOut) if nothing arrives within `limit`
+async fn read_within(stream: &mut TcpStream, chunk: &mut [u8], limit: Option<Duration>) -> IoResult<usize> {
+    match limit {
+        Some(limit) => timeout(limit, stream.read(chunk)).await
+            .unwrap_or_else(|_| Err(err!(TimedOut, "Request timed out", "Nothing arrived for {:?}", limit))),
+        None => stream.read(chunk).await
+    }
+}
+
+/// Reads and drops what is left of a refused body for a moment, so closing doesn't reset the connection before the client reads the response
+async fn linger(stream: &mut TcpStream) {
+    let mut chunk = [0; 4096];
+    let mut drained = 0;
+    while drained < LINGER_LIMIT {
+        match timeout(LINGER_TIMEOUT, stream.read(&mut chunk)).await {
+            Ok(Ok(read)) if read > 0 => drained += read,
+            _ => return
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufReader, Read, Write},
+        time::Instant
+    };
+    use crate::http::{Server, test_client::spawn_async_test_server};
+
+    struct Handler;
+
+    impl AsyncRequestHandler for Handler {
+        async fn get(&self, req: &Request<'_>, res: &mut Response) -> IoResult<()> {
+            if req.path() == "/slow" {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            res.ok(Some(format!("{} {}", req.method(), req.target())))
+        }
+        async fn post(&self, req: &Request<'_>, res: &mut Response) -> IoResult<()> {
+            res.set_body(req.body().unwrap_or(&[]).to_vec()).send()
+        }
+    }
+
+    fn connect(addr: &str) -> BufReader<net::TcpStream> {
+        let stream = net::TcpStream::connect(addr).expect("failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        BufReader::new(stream)
+    }
+
+    fn send(reader: &mut BufReader<net::TcpStream>, request: &str) -> ParsedResponse {
+        reader.get_mut().write_all(request.as_bytes()).unwrap();
+        ParsedResponse::read_from(reader, &Method::GET).expect("failed to read response")
+    }
+
+    #[test]
+    fn keep_alive() {
+        let addr = spawn_async_test_server(Handler);
+        let mut conn = connect(&addr);
+
+        let res = send(&mut conn, "GET /one HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(res.text(), "GET /one");
+        assert_eq!(res.headers.get("Connection"), Some("keep-alive"));
+
+        // pipelined, both requests in one write
+        conn.get_mut().write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloGET /two HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(ParsedResponse::read_from(&mut conn, &Method::POST).unwrap().text(), "hello");
+        assert_eq!(ParsedResponse::read_from(&mut conn, &Method::GET).unwrap().text(), "GET /two");
+
+        let res = send(&mut conn, "GET /last HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert_eq!(res.headers.get("Connection"), Some("close"));
+        let mut rest = Vec::new();
+        assert_eq!(conn.read_to_end(&mut rest).unwrap(), 0);
+
+        let mut conn = connect(&addr);
+        let res = send(&mut conn, "GET /old HTTP/1.0\r\n\r\n");
+        assert_eq!(res.headers.get("Connection"), Some("close"));
+    }
+
+    #[test]
+    fn body_in_pieces() {
+        let mut conn = connect(&spawn_async_test_server(Handler));
+        conn.get_mut().write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        conn.get_mut().write_all(b" world").unwrap();
+        assert_eq!(ParsedResponse::read_from(&mut conn, &Method::POST).unwrap().text(), "hello world");
+    }
+
+    #[test]
+    fn concurrent_connections() {
+        let addr = spawn_async_test_server(Handler);
+        let started = Instant::now();
+        // far more slow requests than there are workers, they wait together instead of in turn
+        let clients: Vec<_> = (0..50).map(|_| {
+            let addr = addr.clone();
+            thread::spawn(move || send(&mut connect(&addr), "GET /slow HTTP/1.1\r\n\r\n").text())
+        }).collect();
+
+        for client in clients {
+            assert_eq!(client.join().unwrap(), "GET /slow");
+        }
+        assert!(started.elapsed() < Duration::from_secs(3), "took {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn body_limits() {
+        let mut server = Server::new(Str!("127.0.0.1"), 0);
+        server.set_max_body_size(10);
+        let addr = server.addr();
+        thread::spawn(move || server.run_async(Arc::new(Handler)));
+
+        let mut conn = connect(&addr);
+        conn.get_mut().write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n").unwrap();
+        assert_eq!(ParsedResponse::read_head(&mut conn).unwrap().status, StatusCode::Continue);
+        conn.get_mut().write_all(b"hello").unwrap();
+        assert_eq!(ParsedResponse::read_from(&mut conn, &Method::POST).unwrap().text(), "hello");
+
+        // the body is never read, so the connection can't be reused
+        let res = send(&mut conn, "POST /echo HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world");
+        assert_eq!(res.status, StatusCode::PayloadTooLarge);
+        let mut rest = Vec::new();
+        assert_eq!(conn.read_to_end(&mut rest).unwrap(), 0);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut server = Server::new(Str!("127.0.0.1"), 0);
+        server.set_timeout(Some(Duration::from_millis(200)));
+        let addr = server.addr();
+        thread::spawn(move || server.run_async(Arc::new(Handler)));
+
+        // each read gets the timeout, not the whole upload
+        let mut conn = connect(&addr);
+        conn.get_mut().write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\n").unwrap();
+        for byte in b"hello" {
+            thread::sleep(Duration::from_millis(100));
+            conn.get_mut().write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(ParsedResponse::read_from(&mut conn, &Method::POST).unwrap().text(), "hello");
+
+        let mut conn = connect(&addr);
+        conn.get_mut().write_all(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel").unwrap();
+        let res = ParsedResponse::read_from(&mut conn, &Method::POST).expect("no 408");
+        assert_eq!(res.status, StatusCode::RequestTimeout);
+    }
+
+    #[test]
+    fn bad_requests() {
+        let addr = spawn_async_test_server(Handler);
+        let res = send(&mut connect(&addr), "NOPE\r\n\r\n");
+        assert_eq!(res.status, StatusCode::BadRequest);
+
+        let res = send(&mut connect(&addr), &format!("GET / HTTP/1.1\r\nX-Big: {}\r\n\r\n", "a".repeat(MAX_HEAD_SIZE)));
+        assert_eq!(res.status, StatusCode::BadRequest);
+    }
+}
@@ -0,0 +1,168 @@
+use std::time::{Duration, SystemTime};
+
+use super::{Headers, date::http_date};
+
+/// Which paths a [CacheRule] applies to
+#[derive(Clone, Debug)]
+pub enum PathPattern {
+    /// Paths starting with this, ex `/assets/`
+    Prefix(String),
+    /// A glob where `*` matches within a path segment, `**` matches across segments, `?` matches one character
+    /// and `[a-f0-9]` matches one character from the class. Globs without a `/` are matched against the file name only,
+    /// so `*.html` matches `/docs/index.html`
+    Glob(String),
+}
+
+impl PathPattern {
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Prefix(prefix) => path.starts_with(prefix.as_str()),
+            Self::Glob(glob) if glob.contains('/') => glob_match(glob.as_bytes(), path.as_bytes()),
+            Self::Glob(glob) => glob_match(glob.as_bytes(), path.rsplit('/').next().unwrap_or(path).as_bytes()),
+        }
+    }
+}
+
+/// Caching headers for the files matching a [PathPattern]
+#[derive(Clone, Debug)]
+pub struct CacheRule {
+    pub pattern: PathPattern,
+    /// Value of the `Cache-Control` header
+    pub cache_control: Option<String>,
+    /// Sets `Expires` to this long after the file is served
+    pub expires: Option<Duration>,
+}
+
+impl CacheRule {
+    pub fn new(pattern: PathPattern, cache_control: &str) -> Self {
+        Self { pattern, cache_control: some_str!(cache_control), expires: None }
+    }
+
+    /// For fingerprinted assets whose contents never change under the same name, ex `app.abc123.js`
+    pub fn immutable(pattern: PathPattern) -> Self {
+        let mut rule = Self::new(pattern, "public, max-age=31536000, immutable");
+        rule.expires = Some(Duration::from_secs(31536000));
+        rule
+    }
+
+    /// For files that must be revalidated every time, like HTML pages that link to fingerprinted assets
+    pub fn no_cache(pattern: PathPattern) -> Self {
+        Self::new(pattern, "no-cache")
+    }
+
+    pub fn apply(&self, headers: &mut Headers) {
+        if let Some(cache_control) = &self.cache_control {
+            headers.set("Cache-Control", cache_control.as_str());
+        }
+        if let Some(expires) = self.expires {
+            headers.set("Expires", http_date(SystemTime::now() + expires));
+        }
+    }
+}
+
+/// Returns the first rule in `rules` that matches `path`
+pub fn find_rule<'a>(rules: &'a [CacheRule], path: &str) -> Option<&'a CacheRule> {
+    rules.iter().find(|rule| rule.pattern.matches(path))
+}
+
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    match glob.first() {
+        None => text.is_empty(),
+        Some(b'*') if glob.get(1) == Some(&b'*') => {
+            let rest = &glob[2..];
+            // `**/` may also match nothing, so `/**/a.js` matches `/a.js`
+            let rest_no_slash = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]) || glob_match(rest_no_slash, &text[i..]))
+        },
+        Some(b'*') => {
+            let rest = &glob[1..];
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        },
+        Some(b'?') => !text.is_empty() && text[0] != b'/' && glob_match(&glob[1..], &text[1..]),
+        Some(b'[') => {
+            let end = match glob.iter().position(|&c| c == b']') {
+                Some(end) => end,
+                None => return text.first() == Some(&b'[') && glob_match(&glob[1..], &text[1..])
+            };
+            match text.first() {
+                Some(&c) if class_contains(&glob[1..end], c) => glob_match(&glob[end + 1..], &text[1..]),
+                _ => false
+            }
+        },
+        Some(&c) => text.first() == Some(&c) && glob_match(&glob[1..], &text[1..]),
+    }
+}
+
+/// Checks a character class body like `a-f0-9_`
+fn class_contains(class: &[u8], c: u8) -> bool {
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == b'-' {
+            if (class[i]..=class[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        let fingerprinted = PathPattern::Glob(Str!("*.[0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f]*.js"));
+        assert!(fingerprinted.matches("/js/app.abc123.js"));
+        assert!(fingerprinted.matches("/app.0123456789.js"));
+        assert!(!fingerprinted.matches("/js/app.js"));
+        assert!(!fingerprinted.matches("/js/jquery.min.js"));
+
+        let html = PathPattern::Glob(Str!("*.html"));
+        assert!(html.matches("/index.html"));
+        assert!(html.matches("/docs/a/b.html"));
+        assert!(!html.matches("/index.htm"));
+
+        let nested = PathPattern::Glob(Str!("/static/**/*.css"));
+        assert!(nested.matches("/static/style.css"));
+        assert!(nested.matches("/static/a/b/style.css"));
+        assert!(!nested.matches("/other/style.css"));
+
+        let single = PathPattern::Glob(Str!("/img/?.png"));
+        assert!(single.matches("/img/a.png"));
+        assert!(!single.matches("/img/ab.png"));
+    }
+
+    #[test]
+    fn first_rule_wins() {
+        let rules = vec![
+            CacheRule::immutable(PathPattern::Prefix(Str!("/assets/"))),
+            CacheRule::no_cache(PathPattern::Glob(Str!("*.html"))),
+        ];
+        let mut headers = Headers::new();
+        find_rule(&rules, "/assets/page.html").expect("no rule").apply(&mut headers);
+        assert_eq!(headers.get("Cache-Control"), Some("public, max-age=31536000, immutable"));
+        assert!(headers.get("Expires").is_some_and(|d| d.ends_with(" GMT")));
+
+        let mut headers = Headers::new();
+        find_rule(&rules, "/page.html").expect("no rule").apply(&mut headers);
+        assert_eq!(headers.get("Cache-Control"), Some("no-cache"));
+        assert_eq!(headers.get("Expires"), None);
+
+        assert!(find_rule(&rules, "/style.css").is_none());
+    }
+}
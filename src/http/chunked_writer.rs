@@ -0,0 +1,146 @@
+use std::{
+    io::{Write, Result as IoResult},
+    rc::Rc,
+    cell::RefCell
+};
+
+use super::Headers;
+
+/*
+EXAMPLE CHUNKED BODY:
+
+6\r\n
+Apples\r\n
+A\r\n
+12 Bananas\r\n
+0\r\n
+X-Count: 2\r\n
+\r\n
+*/
+
+/// Writes a response body as it is produced, returned by [Response::stream()](super::Response::stream).
+/// Every write is one chunk, wrap it in a [BufWriter](std::io::BufWriter) to avoid lots of tiny ones.
+/// The body is ended by [ChunkedWriter::finish()], or when the writer is dropped
+pub struct ChunkedWriter {
+    writer: Rc<RefCell<dyn Write>>,
+    /// False when the body is delimited by closing the connection instead
+    chunked: bool,
+    trailers: Headers,
+    finished: bool,
+}
+
+impl ChunkedWriter {
+    pub(super) fn new(writer: Rc<RefCell<dyn Write>>, chunked: bool) -> Self {
+        Self { writer, chunked, trailers: Headers::new(), finished: false }
+    }
+
+    /// Whether the body is sent in chunks, if not trailers are dropped
+    pub fn is_chunked(&self) -> bool {
+        self.chunked
+    }
+
+    /// Sets a header to send after the body, for values only known once it is written like a checksum
+    pub fn set_trailer(&mut self, name: &str, value: impl Into<String>) -> &mut Self {
+        self.trailers.set(name, value);
+        self
+    }
+
+    /// Writes the last chunk and the trailers
+    pub fn finish(mut self) -> IoResult<()> {
+        self.end()
+    }
+
+    fn end(&mut self) -> IoResult<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let mut writer = self.writer.borrow_mut();
+        if self.chunked {
+            write!(writer, "0\r\n{}\r\n", self.trailers)?;
+        }
+        writer.flush()
+    }
+}
+
+impl Write for ChunkedWriter {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        if self.finished {
+            return Err(err!(BrokenPipe, "Body finished", "Can't write after the body was finished"));
+        }
+        // an empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut writer = self.writer.borrow_mut();
+        if self.chunked {
+            write!(writer, "{:X}\r\n", buf.len())?;
+            writer.write_all(buf)?;
+            writer.write_all(b"\r\n")?;
+        } else {
+            writer.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.writer.borrow_mut().flush()
+    }
+}
+
+/// Ends the body if the handler didn't, errors are ignored since there's no one left to report them to
+impl Drop for ChunkedWriter {
+    fn drop(&mut self) {
+        let _ = self.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufWriter, Result as IoResult};
+    use crate::http::{Request, Response, RequestHandler, StatusCode, test_client::TestClient};
+
+    struct Counter;
+
+    impl RequestHandler for Counter {
+        fn get(&self, _req: &Request, res: &mut Response) -> IoResult<()> {
+            res.set_header("Content-Type", "text/plain").set_header("Trailer", "X-Total");
+            let mut writer = BufWriter::new(res.stream()?);
+            let mut total = 0;
+            for i in 1..=100 {
+                writeln!(writer, "line {}", i)?;
+                total += i;
+            }
+            let mut writer = writer.into_inner().map_err(|e| e.into_error())?;
+            writer.set_trailer("X-Total", total.to_string());
+            writer.finish()
+        }
+    }
+
+    #[test]
+    fn streamed() {
+        let client = TestClient::new(Counter);
+        let res = client.get("/").expect("request failed");
+
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(res.headers.get("Transfer-Encoding"), Some("chunked"));
+        assert!(!res.headers.contains("Content-Length"));
+        assert_eq!(res.text().lines().count(), 100);
+        assert!(res.text().ends_with("line 100\n"));
+        assert_eq!(res.trailers.get("X-Total"), Some("5050"));
+    }
+
+    #[test]
+    fn http_1_0_fallback() {
+        let client = TestClient::new(Counter);
+        let res = client.send_raw(b"GET / HTTP/1.0\r\n\r\n").expect("request failed");
+
+        assert!(!res.headers.contains("Transfer-Encoding"));
+        assert_eq!(res.headers.get("Connection"), Some("close"));
+        assert_eq!(res.text().lines().count(), 100);
+        assert!(res.trailers.is_empty());
+    }
+}
@@ -0,0 +1,496 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, ErrorKind, Write, Result as IoResult},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Mutex,
+    time::Duration
+};
+
+use super::{Headers, Method, StatusCode, parsed_response::ParsedResponse};
+
+type Connection = BufReader<TcpStream>;
+
+/// How sending a request on a connection went
+enum Exchange {
+    Response(ParsedResponse),
+    /// The connection failed before any of the response arrived, like an idle one the server has since closed
+    Closed(io::Error),
+}
+
+/// A blocking HTTP/1.1 client for calling other services, ex from inside a [RequestHandler](super::RequestHandler).
+/// Only `http://` URLs are supported. Connections are kept open and reused when the server allows it
+pub struct Client {
+    timeout: Option<Duration>,
+    max_redirects: usize,
+    keep_alive: bool,
+    max_body_size: usize,
+    /// Idle connections by `host:port`
+    pool: Mutex<HashMap<String, Vec<Connection>>>
+}
+
+/// A request being built by [Client::request()]
+pub struct ClientRequest<'c> {
+    client: &'c Client,
+    method: Method,
+    url: String,
+    headers: Headers,
+    body: Vec<u8>
+}
+
+/// The parts of an `http://host:port/path?query` URL the client needs
+#[derive(Debug, PartialEq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    /// Path and query, always starts with `/`
+    pub target: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> IoResult<Self> {
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| err!(InvalidInput, "Only http:// URLs are supported", "URL: {}", url))?;
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], Str!(&rest[i..])),
+            None => (rest, Str!("/"))
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().map_err(|_| err!(InvalidInput, "Invalid port", "URL: {}", url))?),
+            None => (authority, 80)
+        };
+        if host.is_empty() {
+            return Err(err!(InvalidInput, "URL has no host", "URL: {}", url));
+        }
+        Ok(Self { host: Str!(host), port, target })
+    }
+
+    /// `host:port`, used for connecting and the `Host` header
+    pub fn authority(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Resolves a `Location` header against this URL, it can be absolute or just a path
+    pub fn join(&self, location: &str) -> IoResult<Self> {
+        if location.starts_with("http://") {
+            return Self::parse(location);
+        }
+        if location.starts_with('/') {
+            return Ok(Self { host: self.host.clone(), port: self.port, target: Str!(location) });
+        }
+        let path = self.target.split('?').next().unwrap_or("/");
+        let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        Ok(Self { host: self.host.clone(), port: self.port, target: format!("{}{}", dir, location) })
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    /// A client with a 30 second timeout that doesn't follow redirects and accepts response bodies of up to 64 MiB
+    pub fn new() -> Self {
+        Self {
+            timeout: Some(Duration::from_secs(30)),
+            max_redirects: 0,
+            keep_alive: true,
+            max_body_size: 64 * 1024 * 1024,
+            pool: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Timeout for connecting and for each read and write, [None] or zero waits forever
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        // the socket calls reject a zero timeout
+        self.timeout = timeout.filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Follow up to `max_redirects` redirects, 0 returns the redirect response itself
+    pub fn set_follow_redirects(&mut self, max_redirects: usize) -> &mut Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// If false every request opens a new connection and asks the server to close it
+    pub fn set_keep_alive(&mut self, keep_alive: bool) -> &mut Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Responses with a bigger body fail with [InvalidData](std::io::ErrorKind::InvalidData) instead of being read into memory
+    pub fn set_max_body_size(&mut self, max: usize) -> &mut Self {
+        self.max_body_size = max;
+        self
+    }
+
+    pub fn request(&self, method: Method, url: &str) -> ClientRequest<'_> {
+        ClientRequest { client: self, method, url: Str!(url), headers: Headers::new(), body: Vec::new() }
+    }
+
+    pub fn get(&self, url: &str) -> IoResult<ParsedResponse> {
+        self.request(Method::GET, url).send()
+    }
+
+    pub fn post(&self, url: &str, body: impl Into<Vec<u8>>) -> IoResult<ParsedResponse> {
+        self.request(Method::POST, url).body(body).send()
+    }
+
+    /// Number of idle connections waiting to be reused
+    pub fn idle_connections(&self) -> usize {
+        self.pool.lock().map(|pool| pool.values().map(Vec::len).sum()).unwrap_or(0)
+    }
+
+    fn connect(&self, url: &Url) -> IoResult<Connection> {
+        let mut last_error = err!(NotFound, "Host did not resolve to any address", "Host: {}", url.host);
+        for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+            let stream = match self.timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr)
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.timeout)?;
+                    stream.set_write_timeout(self.timeout)?;
+                    return Ok(BufReader::new(stream));
+                },
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+
+    fn take_idle(&self, authority: &str) -> Option<Connection> {
+        self.pool.lock().ok()?.get_mut(authority)?.pop()
+    }
+
+    fn put_idle(&self, authority: String, connection: Connection) {
+        if let Ok(mut pool) = self.pool.lock() {
+            pool.entry(authority).or_default().push(connection);
+        }
+    }
+
+    /// Sends one request, without following redirects
+    fn send_once(&self, method: Method, url: &Url, headers: &Headers, body: &[u8]) -> IoResult<ParsedResponse> {
+        let mut raw = format!("{} {} HTTP/1.1\r\nHost: {}\r\n{}", method, url.target, url.authority(), headers);
+        if !body.is_empty() || matches!(method, Method::POST | Method::PUT | Method::PATCH) {
+            raw.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        if !self.keep_alive {
+            raw.push_str("Connection: close\r\n");
+        }
+        raw.push_str("\r\n");
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(body);
+
+        let authority = url.authority();
+        // an idle connection may have been closed by the server in the meantime, if so the request goes on a new one.
+        // The server could also have handled it before closing, so only requests that are safe to repeat are sent again
+        if let Some(mut connection) = self.keep_alive.then(|| self.take_idle(&authority)).flatten() {
+            match self.exchange(&mut connection, &raw, method)? {
+                Exchange::Response(response) => {
+                    self.finish(authority, connection, &response);
+                    return Ok(response);
+                },
+                Exchange::Closed(e) if !method.is_idempotent() => return Err(e),
+                Exchange::Closed(_) => {}
+            }
+        }
+
+        let mut connection = self.connect(url)?;
+        match self.exchange(&mut connection, &raw, method)? {
+            Exchange::Response(response) => {
+                self.finish(authority, connection, &response);
+                Ok(response)
+            },
+            Exchange::Closed(e) => Err(e)
+        }
+    }
+
+    fn exchange(&self, connection: &mut Connection, raw: &[u8], method: Method) -> IoResult<Exchange> {
+        let stream = connection.get_mut();
+        if let Err(e) = stream.write_all(raw).and_then(|_| stream.flush()) {
+            return Ok(Exchange::Closed(e));
+        }
+        match connection.fill_buf() {
+            Ok([]) => return Ok(Exchange::Closed(err!(UnexpectedEof, "Connection closed before the response"))),
+            Err(e) if matches!(e.kind(), ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe) => {
+                return Ok(Exchange::Closed(e));
+            },
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+        ParsedResponse::read_limited(connection, &method, self.max_body_size as u64).map(Exchange::Response)
+    }
+
+    /// Puts the connection back in the pool if both sides are fine with reusing it
+    fn finish(&self, authority: String, connection: Connection, response: &ParsedResponse) {
+        if self.keep_alive && response.keep_alive() {
+            self.put_idle(authority, connection);
+        }
+    }
+}
+
+impl<'c> ClientRequest<'c> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.add(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends the request, following redirects if the client is set up to
+    pub fn send(self) -> IoResult<ParsedResponse> {
+        let mut url = Url::parse(&self.url)?;
+        let mut method = self.method;
+        let mut headers = self.headers;
+        let mut body = self.body;
+
+        for _ in 0..=self.client.max_redirects {
+            let response = self.client.send_once(method, &url, &headers, &body)?;
+            let location = match response.headers.get("Location") {
+                Some(location) if response.status.is_redirect() && self.client.max_redirects > 0 => location,
+                _ => return Ok(response)
+            };
+
+            let next = url.join(location)?;
+            // credentials are only for the server they were meant for
+            if next.authority() != url.authority() {
+                for name in ["Authorization", "Proxy-Authorization", "Cookie"] {
+                    headers.remove(name);
+                }
+            }
+            url = next;
+            // 307 and 308 repeat the request as is, the others turn into a GET without the body or the headers describing it
+            if !matches!(response.status, StatusCode::TemporaryRedirect | StatusCode::PermanentRedirect) && method != Method::HEAD {
+                method = Method::GET;
+                body = Vec::new();
+                for name in ["Content-Type", "Content-Length", "Content-Encoding", "Content-Language", "Content-Location", "Transfer-Encoding"] {
+                    headers.remove(name);
+                }
+            }
+        }
+        Err(err!(Other, "Too many redirects", "Last URL: http://{}{}", url.authority(), url.target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, net::TcpListener, thread};
+    use crate::http::{Request, RequestHandler, Response, test_client::spawn_test_server};
+
+    struct Backend;
+
+    impl RequestHandler for Backend {
+        fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            match req.path() {
+                "/hello" => res.ok(some_str!("Hello")),
+                "/redirect" => res.redirect("/hello"),
+                "/loop" => res.redirect("/loop"),
+                "/headers" => res.ok(Some(req.headers().to_string())),
+                // the same server under another name
+                "/away" => {
+                    let port = req.headers().get("Host").and_then(|host| host.rsplit(':').next()).unwrap_or("80");
+                    res.redirect(&format!("http://localhost:{}/headers", port))
+                },
+                _ => res.send_404()
+            }
+        }
+        fn post(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            match req.path() {
+                "/echo" => res.ok(req.body_str().map(String::from)),
+                "/redirect" => res.redirect("/hello"),
+                "/headers" => res.redirect("/headers"),
+                _ => res.send_404()
+            }
+        }
+    }
+
+    fn start_server() -> String {
+        format!("http://{}", spawn_test_server(Backend))
+    }
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(Url::parse("http://localhost:8080/a?b=1").unwrap(), Url { host: Str!("localhost"), port: 8080, target: Str!("/a?b=1") });
+        assert_eq!(Url::parse("http://example.com").unwrap(), Url { host: Str!("example.com"), port: 80, target: Str!("/") });
+        assert_eq!(Url::parse("http://example.com?q").unwrap().target, "/?q");
+        assert!(Url::parse("https://example.com").is_err());
+
+        let base = Url::parse("http://a:1/x/y?z").unwrap();
+        assert_eq!(base.join("/b").unwrap().target, "/b");
+        assert_eq!(base.join("c").unwrap().target, "/x/c");
+        assert_eq!(base.join("http://other/").unwrap().host, "other");
+    }
+
+    #[test]
+    fn against_server() {
+        let base = start_server();
+        let client = Client::new();
+
+        let res = client.get(&format!("{}/hello", base)).expect("request failed");
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(res.text(), "Hello");
+        // the server closes every connection, so there is nothing to reuse
+        assert_eq!(client.idle_connections(), 0);
+
+        let res = client.post(&format!("{}/echo", base), "some body").expect("request failed");
+        assert_eq!(res.text(), "some body");
+
+        assert_eq!(client.get(&format!("{}/missing", base)).expect("request failed").status, StatusCode::NotFound);
+
+        let mut client = Client::new();
+        client.set_max_body_size(4);
+        let err = client.get(&format!("{}/hello", base)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn redirects() {
+        let base = start_server();
+        let mut client = Client::new();
+
+        let res = client.get(&format!("{}/redirect", base)).expect("request failed");
+        assert_eq!(res.status, StatusCode::Found);
+        assert_eq!(res.headers.get("Location"), Some("/hello"));
+
+        client.set_follow_redirects(3);
+        let res = client.post(&format!("{}/redirect", base), "ignored").expect("request failed");
+        assert_eq!(res.text(), "Hello");
+
+        assert!(client.get(&format!("{}/loop", base)).is_err());
+    }
+
+    #[test]
+    fn redirect_headers() {
+        let base = start_server();
+        let mut client = Client::new();
+        client.set_follow_redirects(3);
+
+        let res = client.request(Method::POST, &format!("{}/headers", base))
+            .header("Content-Type", "text/plain")
+            .header("Authorization", "Bearer token")
+            .body("dropped")
+            .send()
+            .expect("request failed");
+        let text = res.text();
+        assert!(text.contains("Authorization: Bearer token"), "{}", text);
+        assert!(!text.contains("Content-Type") && !text.contains("Content-Length"), "{}", text);
+
+        let res = client.request(Method::GET, &format!("{}/away", base))
+            .header("Authorization", "Bearer token")
+            .header("Proxy-Authorization", "Basic abc")
+            .header("Cookie", "session=1")
+            .header("X-Kept", "yes")
+            .send()
+            .expect("request failed");
+        let text = res.text();
+        assert!(text.starts_with("Host: localhost:"), "{}", text);
+        assert!(text.contains("X-Kept: yes"), "{}", text);
+        assert!(!text.contains("Authorization") && !text.contains("Cookie"), "{}", text);
+    }
+
+    #[test]
+    fn retries() {
+        // answers one request per connection but says it can be kept open, so every idle connection is stale
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.expect("failed to accept");
+                let mut buf = [0; 1024];
+                let mut read = 0;
+                while !buf[..read].windows(4).any(|w| w == b"\r\n\r\n") {
+                    read += stream.read(&mut buf[read..]).expect("failed to read");
+                }
+                write!(stream, "HTTP/1.1 200 Ok\r\nContent-Length: 1\r\n\r\n{}", i).expect("failed to write");
+            }
+        });
+
+        let client = Client::new();
+        let url = format!("http://{}/", addr);
+        assert_eq!(client.get(&url).expect("first request failed").text(), "0");
+        assert_eq!(client.idle_connections(), 1);
+        thread::sleep(Duration::from_millis(50));
+        // sent again on a new connection
+        assert_eq!(client.get(&url).expect("retry failed").text(), "1");
+
+        // a POST could have been handled before the connection closed, so it isn't repeated
+        thread::sleep(Duration::from_millis(50));
+        assert!(client.post(&url, "once").is_err());
+        assert_eq!(client.get(&url).expect("request failed").text(), "2");
+    }
+
+    #[test]
+    fn keep_alive() {
+        // answers two requests on the same connection, then stops accepting
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("failed to accept");
+            for i in 0..2 {
+                let mut buf = [0; 1024];
+                let mut read = 0;
+                while !buf[..read].windows(4).any(|w| w == b"\r\n\r\n") {
+                    read += stream.read(&mut buf[read..]).expect("failed to read");
+                }
+                write!(stream, "HTTP/1.1 200 Ok\r\nContent-Length: 1\r\n\r\n{}", i).expect("failed to write");
+            }
+        });
+
+        let client = Client::new();
+        let url = format!("http://{}/", addr);
+        assert_eq!(client.get(&url).expect("first request failed").text(), "0");
+        assert_eq!(client.idle_connections(), 1);
+        assert_eq!(client.get(&url).expect("second request failed").text(), "1");
+    }
+
+    #[test]
+    fn interim_responses() {
+        // sends an Early Hints before each answer on the same connection
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("failed to accept");
+            for i in 0..2 {
+                let mut buf = [0; 1024];
+                let mut read = 0;
+                while !buf[..read].windows(4).any(|w| w == b"\r\n\r\n") {
+                    read += stream.read(&mut buf[read..]).expect("failed to read");
+                }
+                write!(stream, "HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 200 Ok\r\nContent-Length: 1\r\n\r\n{}", i).expect("failed to write");
+            }
+        });
+
+        let client = Client::new();
+        let url = format!("http://{}/", addr);
+        let res = client.get(&url).expect("first request failed");
+        assert_eq!((res.status, res.text().as_str()), (StatusCode::Ok, "0"));
+        assert_eq!(client.get(&url).expect("second request failed").text(), "1");
+    }
+
+    #[test]
+    fn timeout() {
+        // accepts but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _connection = listener.accept();
+            thread::sleep(Duration::from_secs(5));
+        });
+
+        let mut client = Client::new();
+        client.set_timeout(Some(Duration::from_millis(100)));
+        assert!(client.get(&format!("http://{}/", addr)).is_err());
+
+        client.set_timeout(Some(Duration::ZERO));
+        assert_eq!(client.get(&format!("{}/hello", start_server())).expect("request failed").text(), "Hello");
+    }
+}
@@ -0,0 +1,205 @@
+use std::io::{Write, Result as IoResult};
+use flate2::{
+    Compression as Level,
+    write::{GzEncoder, ZlibEncoder}
+};
+
+use super::Headers;
+
+/// A content coding the server knows how to produce
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+impl Encoding {
+    /// Every supported encoding, most preferred first. Used to break ties between equal q-values
+    #[cfg(feature = "brotli")]
+    const PREFERENCE: &'static [Encoding] = &[Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+    #[cfg(not(feature = "brotli"))]
+    const PREFERENCE: &'static [Encoding] = &[Encoding::Gzip, Encoding::Deflate];
+
+    /// The token used for this encoding in `Accept-Encoding` and `Content-Encoding`
+    pub fn token(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            Self::Brotli => "br",
+        }
+    }
+
+    pub fn encode(&self, bytes: &[u8], level: u32) -> IoResult<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Level::new(level));
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+            Self::Deflate => {
+                // "deflate" in HTTP means the zlib format, not a raw deflate stream
+                let mut encoder = ZlibEncoder::new(Vec::new(), Level::new(level));
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+            #[cfg(feature = "brotli")]
+            Self::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, level.min(11), 22);
+                    encoder.write_all(bytes)?;
+                }
+                Ok(out)
+            },
+        }
+    }
+}
+
+/// Settings for compressing response bodies on the fly
+#[derive(Clone, Debug)]
+pub struct Compression {
+    /// Bodies smaller than this many bytes are sent as is
+    pub threshold: usize,
+    /// Compression level, 0-9 for gzip/deflate (brotli accepts up to 11)
+    pub level: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self { threshold: 1024, level: 6 }
+    }
+}
+
+impl Compression {
+    pub fn new(threshold: usize, level: u32) -> Self {
+        Self { threshold, level }
+    }
+
+    /// Picks the encoding with the highest q-value from an `Accept-Encoding` header,
+    /// ex `gzip;q=0.8, br` picks brotli if it is enabled, gzip otherwise
+    pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let mut best: Option<(Encoding, f32)> = None;
+
+        for encoding in Encoding::PREFERENCE {
+            let q = Self::quality(accept_encoding, encoding.token());
+            if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+                best = Some((*encoding, q));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /// Returns the q-value given to `token`, falling back to `*` and then 0
+    fn quality(accept_encoding: &str, token: &str) -> f32 {
+        let mut wildcard = None;
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or("").trim();
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if name.eq_ignore_ascii_case(token) {
+                return q;
+            }
+            if name == "*" {
+                wildcard = Some(q);
+            }
+        }
+        wildcard.unwrap_or(0.0)
+    }
+
+    /// Text based content types compress well, images/video/archives are already compressed
+    pub fn is_compressible(content_type: &str) -> bool {
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        mime.starts_with("text/")
+            || mime.ends_with("+json")
+            || mime.ends_with("+xml")
+            || matches!(
+                mime.as_str(),
+                "application/json" | "application/javascript" | "application/xml"
+                | "application/wasm" | "image/svg+xml"
+            )
+    }
+
+    /// Compresses `body` for a client that sent `accept_encoding`, updating `Content-Encoding` and `Vary`.
+    /// Bodies that already have a `Content-Encoding`, are too small or are not compressible are returned unchanged
+    pub fn apply(&self, accept_encoding: Option<&str>, headers: &mut Headers, body: Vec<u8>) -> IoResult<Vec<u8>> {
+        if headers.contains("Content-Encoding") || body.len() < self.threshold {
+            return Ok(body);
+        }
+        match headers.get("Content-Type") {
+            Some(content_type) if Self::is_compressible(content_type) => {},
+            _ => return Ok(body)
+        }
+
+        // whether or not we compress, the response now depends on Accept-Encoding
+        if !headers.has_token("Vary", "Accept-Encoding") && !headers.has_token("Vary", "*") {
+            headers.add("Vary", "Accept-Encoding");
+        }
+
+        let encoding = match accept_encoding.and_then(Self::negotiate) {
+            Some(encoding) => encoding,
+            None => return Ok(body)
+        };
+
+        let compressed = encoding.encode(&body, self.level)?;
+        if compressed.len() >= body.len() {
+            return Ok(body);
+        }
+        headers.set("Content-Encoding", encoding.token());
+        Ok(compressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(Compression::negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(Compression::negotiate("gzip;q=0.5, deflate"), Some(Encoding::Deflate));
+        assert_eq!(Compression::negotiate("gzip;q=0, br;q=0, *;q=0.1"), Some(Encoding::Deflate));
+        assert_eq!(Compression::negotiate("identity"), None);
+        assert_eq!(Compression::negotiate("*;q=0"), None);
+    }
+
+    #[test]
+    fn compresses_text() {
+        let mut headers = Headers::new();
+        headers.set("Content-Type", "text/html; charset=utf-8");
+        let body = "<p>Bananas</p>".repeat(200).into_bytes();
+
+        let out = Compression::default().apply(Some("gzip"), &mut headers, body.clone()).expect("compression failed");
+        assert_eq!(headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(headers.get("Vary"), Some("Accept-Encoding"));
+
+        let mut decoded = Vec::new();
+        GzDecoder::new(&out[..]).read_to_end(&mut decoded).expect("invalid gzip");
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn skips_encoded_and_binary() {
+        let body = vec![b'a'; 4096];
+
+        let mut headers = Headers::new();
+        headers.set("Content-Type", "text/plain").set("Content-Encoding", "gzip");
+        let out = Compression::default().apply(Some("gzip"), &mut headers, body.clone()).expect("compression failed");
+        assert_eq!(out, body);
+        assert_eq!(headers.get("Vary"), None);
+
+        let mut headers = Headers::new();
+        headers.set("Content-Type", "image/png");
+        let out = Compression::default().apply(Some("gzip"), &mut headers, body.clone()).expect("compression failed");
+        assert_eq!(out, body);
+        assert_eq!(headers.get("Content-Encoding"), None);
+    }
+}
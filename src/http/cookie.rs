@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use super::{date::http_date, query_string::percent_decode};
+/*
+EXAMPLE:
+
+Cookie: theme=dark; session=a1b2c3                  -> req.cookies() is {"theme": "dark", "session": "a1b2c3"}
+
+res.set_cookie(Cookie::new("session", "a1b2c3")
+    .set_path("/")
+    .set_max_age(Duration::from_secs(3600))
+    .set_http_only(true)
+    .set_same_site(SameSite::Lax));
+Set-Cookie: session=a1b2c3; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax
+
+res.set_cookie(Cookie::new("session", "").set_path("/").expire());
+Set-Cookie: session=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0
+*/
+
+/// Which cross-site requests the browser sends the cookie with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    /// Only requests from the same site
+    Strict,
+    /// Same site requests and top level navigations from other sites, the default in most browsers
+    Lax,
+    /// Every request, browsers only accept this for [Secure](Cookie::set_secure) cookies
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Strict => write!(f, "Strict"),
+            Self::Lax => write!(f, "Lax"),
+            Self::None => write!(f, "None"),
+        }
+    }
+}
+
+/// A cookie to send with [Response::set_cookie()](super::Response::set_cookie). Displays as the `Set-Cookie` value,
+/// the name and value are percent-encoded where cookies don't allow a character so anything can be stored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// A session cookie, the browser drops it when it is closed unless an expiry is set
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            expires: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn name(&self) -> &str { &self.name }
+    pub fn value(&self) -> &str { &self.value }
+
+    pub fn set_value(&mut self, value: impl Into<String>) -> &mut Self {
+        self.value = value.into();
+        self
+    }
+
+    /// Only send the cookie for requests under `path`, without it the browser uses the directory of the request
+    pub fn set_path(&mut self, path: &str) -> &mut Self {
+        self.path = Some(Str!(path));
+        self
+    }
+
+    /// Send the cookie to `domain` and its subdomains, without it only the exact host that set it gets it back
+    pub fn set_domain(&mut self, domain: &str) -> &mut Self {
+        self.domain = Some(Str!(domain));
+        self
+    }
+
+    pub fn set_expires(&mut self, time: SystemTime) -> &mut Self {
+        self.expires = Some(time);
+        self
+    }
+
+    /// How long the cookie lives for, taking priority over [Cookie::set_expires()] in browsers that understand it
+    pub fn set_max_age(&mut self, max_age: Duration) -> &mut Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Only send the cookie over HTTPS
+    pub fn set_secure(&mut self, secure: bool) -> &mut Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Hide the cookie from JavaScript
+    pub fn set_http_only(&mut self, http_only: bool) -> &mut Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn set_same_site(&mut self, same_site: SameSite) -> &mut Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Turns the cookie into one that deletes it from the browser, emptied and expired in the past.
+    /// The path and domain have to match the ones it was set with
+    pub fn expire(&mut self) -> &mut Self {
+        self.value.clear();
+        self.expires = Some(UNIX_EPOCH);
+        self.max_age = Some(Duration::ZERO);
+        self
+    }
+}
+
+impl Display for Cookie {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}={}", encode(&self.name, true), encode(&self.value, false))?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", attribute(path))?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", attribute(domain))?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        // browsers throw away SameSite=None cookies that aren't Secure
+        if self.secure || self.same_site == Some(SameSite::None) {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `Cookie` header values, `name=value; name2=value2`, into a map with the names and values decoded.
+/// When a name is sent more than once the first wins, browsers send the cookie with the most specific path first
+pub fn parse<'a>(headers: impl IntoIterator<Item = &'a str>) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for pair in headers.into_iter().flat_map(|header| header.split(';')) {
+        let Some((name, value)) = pair.split_once('=') else { continue };
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        let value = value.trim();
+        let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+        cookies.entry(percent_decode(name, false).into_owned()).or_insert_with(|| percent_decode(value, false).into_owned());
+    }
+    cookies
+}
+
+/// Percent-encodes everything a cookie can't hold as is, `%` included so [parse()] gets back the original.
+/// Names also can't contain `=` or the separators of an HTTP token
+fn encode(input: &str, is_name: bool) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        let allowed = matches!(byte, 0x21..=0x7E)
+            && !matches!(byte, b'"' | b',' | b';' | b'\\' | b'%')
+            && !(is_name && matches!(byte, b'=' | b'(' | b')' | b'<' | b'>' | b'@' | b':' | b'/' | b'[' | b']' | b'?' | b'{' | b'}'));
+        if allowed {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+/// Path and Domain are written as is, minus anything that would end the attribute or the header
+fn attribute(value: &str) -> String {
+    value.chars().filter(|&c| c != ';' && !c.is_control()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use super::super::{Request, Response, RequestHandler, test_client::TestClient};
+    use std::io::Result as IoResult;
+
+    #[test]
+    fn set_cookie() {
+        let cookie = Cookie::new("session", "a1b2c3")
+            .set_path("/app")
+            .set_domain("example.com")
+            .set_expires(UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+            .set_max_age(Duration::from_secs(3600))
+            .set_secure(true)
+            .set_http_only(true)
+            .set_same_site(SameSite::Strict)
+            .clone();
+        assert_eq!(
+            cookie.to_string(),
+            "session=a1b2c3; Path=/app; Domain=example.com; Expires=Tue, 14 Nov 2023 22:13:20 GMT; Max-Age=3600; Secure; HttpOnly; SameSite=Strict"
+        );
+
+        assert_eq!(Cookie::new("a", "b").to_string(), "a=b");
+        assert_eq!(Cookie::new("a", "b").set_same_site(SameSite::None).to_string(), "a=b; Secure; SameSite=None");
+        assert_eq!(Cookie::new("a", "b").set_path("/x;\r\nSet-Cookie: evil=1").to_string(), "a=b; Path=/xSet-Cookie: evil=1");
+    }
+
+    #[test]
+    fn expire() {
+        let cookie = Cookie::new("session", "a1b2c3").set_path("/").expire().to_string();
+        assert_eq!(cookie, "session=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0");
+    }
+
+    #[test]
+    fn encoding() {
+        let cookie = Cookie::new("cart items", "2 × \"apple\"; 1,pear 100%+");
+        assert_eq!(cookie.to_string(), "cart%20items=2%20%C3%97%20%22apple%22%3B%201%2Cpear%20100%25+");
+
+        let cookies = parse([cookie.to_string().as_str()]);
+        assert_eq!(cookies["cart items"], "2 × \"apple\"; 1,pear 100%+");
+        assert_eq!(Cookie::new("a=b", "c=d").to_string(), "a%3Db=c=d");
+    }
+
+    #[test]
+    fn parse_header() {
+        let cookies = parse(["theme=dark; session=\"a1b2c3\";;  empty=; =nameless; flag", "theme=light; raw=%zz+1%+1"]);
+        assert_eq!(cookies.len(), 4);
+        assert_eq!(cookies["theme"], "dark");
+        assert_eq!(cookies["session"], "a1b2c3");
+        assert_eq!(cookies["empty"], "");
+        assert_eq!(cookies["raw"], "%zz+1%+1");
+    }
+
+    struct Visits;
+
+    impl RequestHandler for Visits {
+        fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            let visits: u32 = req.cookie("visits").and_then(|v| v.parse().ok()).unwrap_or(0);
+            res.set_cookie(Cookie::new("visits", (visits + 1).to_string()).set_path("/"))
+                .set_cookie(Cookie::new("old", "").expire())
+                .ok(Some(format!("visit {}", visits + 1)))
+        }
+    }
+
+    #[test]
+    fn request_and_response() {
+        let req = Request::try_from(&b"GET / HTTP/1.1\r\nCookie: a=1; b=2\r\nCookie: c=3\r\n\r\n"[..]).unwrap();
+        assert_eq!(req.cookies().len(), 3);
+        assert_eq!(req.cookie("c").as_deref(), Some("3"));
+        assert_eq!(req.cookie("d"), None);
+
+        let client = TestClient::new(Visits);
+        let res = client.request(super::super::Method::GET, "/").header("Cookie", "visits=41").send().unwrap();
+        assert_eq!(res.text(), "visit 42");
+        let set: Vec<&str> = res.headers.get_all("Set-Cookie").collect();
+        assert_eq!(set, ["visits=42; Path=/", "old=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0"]);
+    }
+}
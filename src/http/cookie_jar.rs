@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use aes_gcm::{
+    Aes256Gcm,
+    KeyInit,
+    Nonce,
+    aead::{Aead, AeadCore, OsRng, Payload, rand_core::RngCore}
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::{Request, Response, cookie::Cookie};
+/*
+EXAMPLE:
+
+// the first key signs and encrypts, the rest are only used to read cookies from before a rotation
+let keys = [Key::from_secret(b"new secret of at least 32 bytes.....").unwrap(), Key::from_secret(OLD_SECRET).unwrap()];
+
+let mut jar = CookieJar::from_request(req);
+let user = jar.signed(&keys).get("user");          // None if missing or tampered with
+jar.private(&keys).add(Cookie::new("cart", "3 apples").set_path("/"));
+jar.write_to(res);
+res.ok(None)
+
+Set-Cookie: cart=<base64 of nonce + AES-256-GCM ciphertext>; Path=/
+*/
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the HMAC-SHA256 tag in base64, the front of every signed value
+const SIGNATURE_LENGTH: usize = 43;
+const NONCE_LENGTH: usize = 12;
+const TAG_LENGTH: usize = 16;
+
+/// A server secret for [SignedJar] and [PrivateJar], split into separate signing and encryption keys
+#[derive(Clone)]
+pub struct Key {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl Key {
+    /// Secrets shorter than this are refused, they'd be too easy to guess
+    pub const MIN_SECRET_LENGTH: usize = 32;
+
+    /// Derives the keys from `secret`, which should be random and stay the same across restarts.
+    /// None when it is shorter than [Key::MIN_SECRET_LENGTH]
+    pub fn from_secret(secret: &[u8]) -> Option<Self> {
+        if secret.len() < Self::MIN_SECRET_LENGTH {
+            return None;
+        }
+        let derive = |label: &[u8]| -> [u8; 32] {
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+            mac.update(label);
+            mac.finalize().into_bytes().into()
+        };
+        Some(Self { signing: derive(b"cookie signing"), encryption: derive(b"cookie encryption") })
+    }
+
+    /// A random key, cookies made with it can't be read after a restart
+    pub fn generate() -> Self {
+        let mut secret = [0; 64];
+        OsRng.fill_bytes(&mut secret);
+        Self::from_secret(&secret).expect("64 bytes is long enough")
+    }
+
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.signing).expect("HMAC accepts keys of any length");
+        // the name is part of the signature so a value can't be moved to another cookie
+        mac.update(&(name.len() as u64).to_be_bytes());
+        mac.update(name.as_bytes());
+        mac.update(value.as_bytes());
+        mac
+    }
+}
+
+/// Keeps the keys out of logs
+impl Debug for Key {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Key(..)")
+    }
+}
+
+/// The cookies of a request along with the ones to send back, see [CookieJar::write_to()]
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: HashMap<String, String>,
+    changes: Vec<Cookie>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_request(req: &Request) -> Self {
+        Self { cookies: req.cookies(), changes: Vec::new() }
+    }
+
+    /// The value of the cookie `name` as it was sent, or as it was last added
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.get(name).map(String::as_str)
+    }
+
+    pub fn add(&mut self, cookie: &Cookie) -> &mut Self {
+        self.cookies.insert(Str!(cookie.name()), Str!(cookie.value()));
+        self.changes.retain(|c| c.name() != cookie.name());
+        self.changes.push(cookie.clone());
+        self
+    }
+
+    /// Deletes the cookie from the browser, the path and domain of `cookie` have to match the ones it was set with
+    pub fn remove(&mut self, cookie: &Cookie) -> &mut Self {
+        self.add(cookie.clone().expire());
+        self.cookies.remove(cookie.name());
+        self
+    }
+
+    /// The cookies added or removed since the jar was made
+    pub fn changes(&self) -> impl Iterator<Item = &Cookie> {
+        self.changes.iter()
+    }
+
+    /// Adds a `Set-Cookie` header for every change
+    pub fn write_to<'r>(&self, res: &'r mut Response) -> &'r mut Response {
+        for cookie in &self.changes {
+            res.set_cookie(cookie);
+        }
+        res
+    }
+
+    /// A view of the jar where values are signed, readable by the client but not changeable. The first key signs,
+    /// any of them verifies so older keys can be kept around while cookies signed with them expire.
+    /// Panics if `keys` is empty
+    pub fn signed<'j>(&'j mut self, keys: &'j [Key]) -> SignedJar<'j> {
+        assert!(!keys.is_empty(), "a signed jar needs at least one key");
+        SignedJar { jar: self, keys }
+    }
+
+    /// A view of the jar where values are encrypted and signed, neither readable nor changeable by the client.
+    /// Keys work as in [CookieJar::signed()], panics if `keys` is empty
+    pub fn private<'j>(&'j mut self, keys: &'j [Key]) -> PrivateJar<'j> {
+        assert!(!keys.is_empty(), "a private jar needs at least one key");
+        PrivateJar { jar: self, keys }
+    }
+}
+
+/// Signed cookies, see [CookieJar::signed()]. Values are an HMAC-SHA256 signature followed by the plain value
+pub struct SignedJar<'j> {
+    jar: &'j mut CookieJar,
+    keys: &'j [Key],
+}
+
+impl SignedJar<'_> {
+    /// The value of the cookie `name`, None if it is missing or its signature doesn't match any key
+    pub fn get(&self, name: &str) -> Option<String> {
+        let signed = self.jar.get(name)?;
+        let (signature, value) = (signed.get(..SIGNATURE_LENGTH)?, &signed[SIGNATURE_LENGTH..]);
+        let signature = BASE64_URL.decode(signature).ok()?;
+        self.keys.iter()
+            .any(|key| key.mac(name, value).verify_slice(&signature).is_ok())
+            .then(|| Str!(value))
+    }
+
+    pub fn add(&mut self, cookie: &Cookie) -> &mut Self {
+        let signature = self.keys[0].mac(cookie.name(), cookie.value()).finalize().into_bytes();
+        let mut signed = cookie.clone();
+        signed.set_value(format!("{}{}", BASE64_URL.encode(signature), cookie.value()));
+        self.jar.add(&signed);
+        self
+    }
+
+    pub fn remove(&mut self, cookie: &Cookie) -> &mut Self {
+        self.jar.remove(cookie);
+        self
+    }
+}
+
+/// Encrypted cookies, see [CookieJar::private()]. Values are the base64 of a random nonce followed by the
+/// AES-256-GCM ciphertext, with the cookie name as associated data so a value can't be moved to another cookie
+pub struct PrivateJar<'j> {
+    jar: &'j mut CookieJar,
+    keys: &'j [Key],
+}
+
+impl PrivateJar<'_> {
+    /// The decrypted value of the cookie `name`, None if it is missing or can't be decrypted with any key
+    pub fn get(&self, name: &str) -> Option<String> {
+        let sealed = BASE64_URL.decode(self.jar.get(name)?).ok()?;
+        if sealed.len() < NONCE_LENGTH + TAG_LENGTH {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+        let value = self.keys.iter().find_map(|key| {
+            let payload = Payload { msg: ciphertext, aad: name.as_bytes() };
+            Aes256Gcm::new(&key.encryption.into()).decrypt(Nonce::from_slice(nonce), payload).ok()
+        })?;
+        String::from_utf8(value).ok()
+    }
+
+    pub fn add(&mut self, cookie: &Cookie) -> &mut Self {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: cookie.value().as_bytes(), aad: cookie.name().as_bytes() };
+        let ciphertext = Aes256Gcm::new(&self.keys[0].encryption.into())
+            .encrypt(&nonce, payload)
+            .expect("cookie values are far below the AES-GCM size limit");
+
+        let mut sealed = cookie.clone();
+        sealed.set_value(BASE64_URL.encode([nonce.as_slice(), &ciphertext].concat()));
+        self.jar.add(&sealed);
+        self
+    }
+
+    pub fn remove(&mut self, cookie: &Cookie) -> &mut Self {
+        self.jar.remove(cookie);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::convert::TryFrom;
+    use std::rc::Rc;
+
+    fn key(byte: u8) -> Key {
+        Key::from_secret(&[byte; 32]).unwrap()
+    }
+
+    /// Carries the jar's changes over to a new request, like a browser would
+    fn round_trip(jar: &CookieJar) -> CookieJar {
+        // only the encoded name=value, without the attributes
+        let header: Vec<String> = jar.changes().map(|c| Str!(c.to_string().split(';').next().unwrap())).collect();
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", header.join("; "));
+        CookieJar::from_request(&Request::try_from(raw.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn keys() {
+        assert!(Key::from_secret(&[7; 31]).is_none());
+        assert_eq!(key(1).signing, key(1).signing);
+        assert_ne!(key(1).signing, key(1).encryption);
+        assert_ne!(Key::generate().signing, Key::generate().signing);
+        assert_eq!(format!("{:?}", key(1)), "Key(..)");
+    }
+
+    #[test]
+    fn signed() {
+        let keys = [key(1)];
+        let mut jar = CookieJar::new();
+        jar.signed(&keys).add(Cookie::new("user", "jo; admin=false").set_path("/"));
+        let value = Str!(jar.get("user").unwrap());
+        assert!(value.ends_with("jo; admin=false"));
+        assert_eq!(jar.signed(&keys).get("user").as_deref(), Some("jo; admin=false"));
+
+        let mut jar = round_trip(&jar);
+        assert_eq!(jar.signed(&keys).get("user").as_deref(), Some("jo; admin=false"));
+
+        // changing the value, moving it to another name or using another key all fail
+        jar.add(&Cookie::new("user", value.replace("false", "true")));
+        assert_eq!(jar.signed(&keys).get("user"), None);
+        jar.add(&Cookie::new("other", value.clone()));
+        assert_eq!(jar.signed(&keys).get("other"), None);
+        jar.add(&Cookie::new("user", value.clone()));
+        assert_eq!(jar.signed(&[key(2)]).get("user"), None);
+        jar.add(&Cookie::new("user", "short"));
+        assert_eq!(jar.signed(&keys).get("user"), None);
+        assert_eq!(jar.signed(&keys).get("missing"), None);
+    }
+
+    #[test]
+    fn private() {
+        let keys = [key(1)];
+        let mut jar = CookieJar::new();
+        jar.private(&keys).add(&Cookie::new("cart", "3 apples"));
+        let sealed = Str!(jar.get("cart").unwrap());
+        assert!(!sealed.contains("apples"));
+        // a new nonce every time
+        jar.private(&keys).add(&Cookie::new("cart", "3 apples"));
+        assert_ne!(jar.get("cart"), Some(sealed.as_str()));
+
+        let mut jar = round_trip(&jar);
+        assert_eq!(jar.private(&keys).get("cart").as_deref(), Some("3 apples"));
+        assert_eq!(jar.private(&[key(2)]).get("cart"), None);
+        // signed and private values aren't interchangeable
+        assert_eq!(jar.signed(&keys).get("cart"), None);
+
+        let mut tampered = BASE64_URL.decode(&sealed).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        jar.add(&Cookie::new("cart", BASE64_URL.encode(&tampered)));
+        assert_eq!(jar.private(&keys).get("cart"), None);
+        jar.add(&Cookie::new("moved", sealed));
+        assert_eq!(jar.private(&keys).get("moved"), None);
+        jar.add(&Cookie::new("cart", "AAAA"));
+        assert_eq!(jar.private(&keys).get("cart"), None);
+    }
+
+    #[test]
+    fn rotation() {
+        let (old, new) = (key(1), key(2));
+        let mut jar = CookieJar::new();
+        jar.signed(std::slice::from_ref(&old)).add(&Cookie::new("user", "jo"));
+        jar.private(std::slice::from_ref(&old)).add(&Cookie::new("cart", "3 apples"));
+
+        let mut jar = round_trip(&jar);
+        let keys = [new.clone(), old];
+        assert_eq!(jar.signed(&keys).get("user").as_deref(), Some("jo"));
+        assert_eq!(jar.private(&keys).get("cart").as_deref(), Some("3 apples"));
+
+        // new cookies use the first key, so they still work once the old one is dropped
+        jar.signed(&keys).add(&Cookie::new("user", "jo"));
+        jar.private(&keys).add(&Cookie::new("cart", "3 apples"));
+        let mut jar = round_trip(&jar);
+        let keys = [new];
+        assert_eq!(jar.signed(&keys).get("user").as_deref(), Some("jo"));
+        assert_eq!(jar.private(&keys).get("cart").as_deref(), Some("3 apples"));
+    }
+
+    #[test]
+    fn write_to() {
+        let keys = [key(1)];
+        let req = Request::try_from(&b"GET / HTTP/1.1\r\nCookie: theme=dark; old=1\r\n\r\n"[..]).unwrap();
+        let mut jar = CookieJar::from_request(&req);
+        assert_eq!(jar.get("theme"), Some("dark"));
+
+        jar.add(&Cookie::new("theme", "light"));
+        jar.add(&Cookie::new("theme", "blue"));
+        jar.remove(Cookie::new("old", "").set_path("/"));
+        jar.private(&keys).add(&Cookie::new("cart", "3 apples"));
+        assert_eq!(jar.get("old"), None);
+
+        let mut res = Response::new(Rc::new(RefCell::new(Vec::new())));
+        jar.write_to(&mut res);
+        let set: Vec<&str> = res.headers.get_all("Set-Cookie").collect();
+        assert_eq!(set.len(), 3);
+        assert_eq!(set[0], "theme=blue");
+        assert_eq!(set[1], "old=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0");
+        assert!(set[2].starts_with("cart="));
+    }
+}
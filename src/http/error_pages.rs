@@ -0,0 +1,167 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Result as IoResult,
+    path::Path
+};
+
+use super::StatusCode;
+
+/// Used for any status that doesn't have a template registered
+pub const DEFAULT_TEMPLATE: &str = "<!DOCTYPE html><html><head><title>{{status}} {{reason}}</title></head><body><h1>{{status}} {{reason}}</h1><p>{{detail}}</p></body></html>";
+
+/// HTML templates for error responses, keyed by status code.
+/// `{{status}}`, `{{reason}}` and `{{detail}}` are replaced when a page is rendered
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPages {
+    templates: HashMap<u16, String>
+}
+
+impl ErrorPages {
+    pub fn new() -> Self {
+        Self { templates: HashMap::new() }
+    }
+
+    /// Loads every `<code>.html` file in `dir` as the template for that code, ex `public/404.html`
+    pub fn load_dir(dir: &str) -> IoResult<Self> {
+        let mut pages = Self::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("html") {
+                continue;
+            }
+            let code = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .filter(|stem| stem.len() == 3)
+                .and_then(|stem| stem.parse::<u16>().ok());
+
+            if let Some(code) = code {
+                pages.register_file(code, &path)?;
+            }
+        }
+        Ok(pages)
+    }
+
+    pub fn register(&mut self, code: u16, template: String) -> &mut Self {
+        self.templates.insert(code, template);
+        self
+    }
+
+    pub fn register_file(&mut self, code: u16, path: &Path) -> IoResult<&mut Self> {
+        let template = fs::read_to_string(path)?;
+        Ok(self.register(code, template))
+    }
+
+    /// Renders the page for `status`, returning the content type and the body.
+    /// Clients that prefer `application/json` in their `Accept` header get a JSON object instead of HTML
+    pub fn render(&self, status: StatusCode, detail: &str, accept: Option<&str>) -> (&'static str, String) {
+        if accept.is_some_and(prefers_json) {
+            let body = format!(
+                "{{\"status\":{},\"reason\":\"{}\",\"detail\":\"{}\"}}",
+                status.code(), json_escape(&status.to_string()), json_escape(detail)
+            );
+            return ("application/json", body);
+        }
+
+        let template = self.templates.get(&status.code()).map(String::as_str).unwrap_or(DEFAULT_TEMPLATE);
+        let body = template
+            .replace("{{status}}", &status.code().to_string())
+            .replace("{{reason}}", &status.to_string())
+            .replace("{{detail}}", &html_escape(detail));
+        ("text/html; charset=utf-8", body)
+    }
+}
+
+/// Whether `application/json` is ranked above `text/html` in an `Accept` header.
+/// Wildcards are ignored, a client that only sends `*/*` gets HTML
+fn prefers_json(accept: &str) -> bool {
+    let mut best: Option<(bool, f32)> = None;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let is_json = match params.next().unwrap_or("").trim() {
+            "application/json" => true,
+            "text/html" => false,
+            _ => continue
+        };
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        // on a tie the one listed first wins
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((is_json, q));
+        }
+    }
+    best.is_some_and(|(is_json, _)| is_json)
+}
+
+pub fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c)
+        }
+    }
+    out
+}
+
+pub fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template() {
+        let mut pages = ErrorPages::new();
+        pages.register(404, Str!("<html><title>{{status}}</title><b>{{reason}}</b> {{detail}}</html>"));
+
+        let (content_type, body) = pages.render(StatusCode::NotFound, "<gone>", None);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert_eq!(body, "<html><title>404</title><b>Not Found</b> &lt;gone&gt;</html>");
+
+        let (_, body) = pages.render(StatusCode::BadRequest, "oops", Some("text/html"));
+        assert!(body.starts_with("<!DOCTYPE html><html>"));
+        assert!(body.contains("<h1>400 Bad Request</h1><p>oops</p>"));
+    }
+
+    #[test]
+    fn render_json() {
+        let pages = ErrorPages::new();
+        let (content_type, body) = pages.render(StatusCode::NotFound, "no \"bananas\"", Some("application/json"));
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, r#"{"status":404,"reason":"Not Found","detail":"no \"bananas\""}"#);
+
+        assert!(prefers_json("application/json, text/html"));
+        assert!(prefers_json("text/html;q=0.5, application/json"));
+        assert!(!prefers_json("text/html, application/json"));
+        assert!(!prefers_json("*/*"));
+    }
+
+    #[test]
+    fn load_public_dir() {
+        let pages = ErrorPages::load_dir(&format!("{}/public", env!("CARGO_MANIFEST_DIR"))).expect("failed to read public");
+        assert!(pages.templates.contains_key(&404));
+        assert!(!pages.templates.contains_key(&200));
+    }
+}
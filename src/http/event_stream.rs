@@ -0,0 +1,336 @@
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{ErrorKind, Write, Result as IoResult},
+    net::TcpStream,
+    sync::{Arc, Weak, Mutex},
+    thread,
+    time::{Duration, Instant}
+};
+
+use super::{Response, StatusCode};
+
+/*
+EXAMPLE EVENT STREAM:
+
+HTTP/1.1 200 Ok\r\n
+Content-Type: text/event-stream\r\n
+Cache-Control: no-cache\r\n
+Connection: close\r\n
+\r\n
+retry: 5000\n
+\n
+id: 1\n
+event: price\n
+data: {"apples": 3}\n
+\n
+: keep-alive\n
+\n
+*/
+
+/// One Server-Sent Event, built up from its data
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// An event carrying `data`, which can span several lines
+    pub fn new(data: &str) -> Self {
+        Self { data: Some(Str!(data)), ..Default::default() }
+    }
+
+    /// Sent back by the browser as `Last-Event-ID` when it reconnects
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id));
+        self
+    }
+
+    /// The event type, browsers dispatch it to listeners for this name instead of `message`
+    pub fn event(mut self, name: &str) -> Self {
+        self.event = Some(single_line(name));
+        self
+    }
+
+    /// How long the browser waits before reconnecting after the stream drops
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+/// Writes the event in the `text/event-stream` format, ending with the blank line that dispatches it
+impl Display for Event {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", id)?;
+        }
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", event)?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        if let Some(data) = &self.data {
+            for line in data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+                writeln!(f, "data: {}", line)?;
+            }
+        }
+        writeln!(f)
+    }
+}
+
+/// Line breaks would end the field early and start a new one
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+struct Connection {
+    stream: TcpStream,
+    last_write: Instant,
+    connected: bool,
+    keep_alive: Duration,
+}
+
+impl Connection {
+    fn write(&mut self, bytes: &[u8]) -> IoResult<()> {
+        if !self.connected {
+            return Err(err!(NotConnected, "Client disconnected", "The client closed the event stream"));
+        }
+        let result = self.stream.write_all(bytes).and_then(|_| self.stream.flush());
+        match &result {
+            Ok(_) => self.last_write = Instant::now(),
+            Err(_) => self.connected = false
+        }
+        result
+    }
+
+    /// The client never sends anything on an event stream, so anything readable means it closed the connection
+    fn check_connected(&mut self) -> bool {
+        if self.connected && self.stream.set_nonblocking(true).is_ok() {
+            let mut byte = [0; 1];
+            self.connected = match self.stream.peek(&mut byte) {
+                Ok(0) => false,
+                Ok(_) => true,
+                Err(e) => e.kind() == ErrorKind::WouldBlock
+            };
+            // the stream is shared with the writes, they expect it to block
+            self.connected &= self.stream.set_nonblocking(false).is_ok();
+        }
+        self.connected
+    }
+}
+
+/// A `text/event-stream` response that stays open after the handler returns, see [EventStream::open()].
+/// Clones share the same connection, so it can be handed to whatever produces the events.
+/// The body is delimited by the connection closing, which happens once every clone is dropped
+#[derive(Clone)]
+pub struct EventStream {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl EventStream {
+    /// Sends the headers of the event stream and takes the connection out of `res`.
+    /// A keep-alive comment is sent whenever nothing else was for 15 seconds, see [EventStream::set_keep_alive()]
+    pub fn open(res: &mut Response) -> IoResult<Self> {
+        let stream = res.take_connection()
+            .ok_or_else(|| err!(Unsupported, "Can't stream", "The response isn't written to a socket that can be kept open"))?;
+
+        res.status = StatusCode::Ok;
+        res.body = None;
+        res.set_header("Content-Type", "text/event-stream")
+            .set_header("Cache-Control", "no-cache")
+            .set_header("Connection", "close")
+            .send_head()?;
+
+        let connection = Arc::new(Mutex::new(Connection {
+            stream,
+            last_write: Instant::now(),
+            connected: true,
+            keep_alive: Duration::from_secs(15)
+        }));
+        let weak = Arc::downgrade(&connection);
+        thread::Builder::new()
+            .name(Str!("event-stream"))
+            .spawn(move || keep_alive_loop(weak))?;
+
+        Ok(Self { connection })
+    }
+
+    pub fn send(&self, event: &Event) -> IoResult<()> {
+        self.connection.lock().unwrap().write(event.to_string().as_bytes())
+    }
+
+    /// Sends an unnamed event with just `data`
+    pub fn send_data(&self, data: &str) -> IoResult<()> {
+        self.send(&Event::new(data))
+    }
+
+    /// Sends a comment, which browsers ignore
+    pub fn comment(&self, text: &str) -> IoResult<()> {
+        self.connection.lock().unwrap().write(format!(": {}\n\n", single_line(text)).as_bytes())
+    }
+
+    /// Sends a keep-alive comment after `interval` without anything else being sent, so proxies
+    /// don't time out the connection and a client that went away is noticed
+    pub fn set_keep_alive(&self, interval: Duration) -> &Self {
+        self.connection.lock().unwrap().keep_alive = interval;
+        self
+    }
+
+    /// Whether the client is still there, checked without writing anything
+    pub fn is_connected(&self) -> bool {
+        self.connection.lock().unwrap().check_connected()
+    }
+}
+
+/// Sends keep-alive comments and watches for the client leaving, until it does or every [EventStream] is dropped
+fn keep_alive_loop(connection: Weak<Mutex<Connection>>) {
+    // how often to check whether the client disconnected
+    let poll = Duration::from_millis(500);
+    loop {
+        let wait = match connection.upgrade() {
+            Some(connection) => {
+                let mut connection = connection.lock().unwrap();
+                if !connection.check_connected() {
+                    return;
+                }
+                let idle = connection.last_write.elapsed();
+                if idle >= connection.keep_alive {
+                    if connection.write(b": keep-alive\n\n").is_err() {
+                        return;
+                    }
+                    connection.keep_alive
+                } else {
+                    connection.keep_alive - idle
+                }
+            },
+            None => return
+        };
+        thread::sleep(wait.min(poll));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use crate::http::{
+        Request,
+        RequestHandler,
+        parsed_response::ParsedResponse,
+        test_client::{TestClient, spawn_test_server}
+    };
+
+    #[derive(Clone, Default)]
+    struct Feed {
+        streams: Arc<Mutex<Vec<EventStream>>>
+    }
+
+    impl RequestHandler for Feed {
+        fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            let stream = EventStream::open(res)?;
+            if req.path() == "/fast" {
+                stream.set_keep_alive(Duration::from_millis(20));
+            }
+            stream.send(&Event::new("").retry(Duration::from_secs(5)))?;
+            self.streams.lock().unwrap().push(stream);
+            Ok(())
+        }
+    }
+
+    fn connect(addr: &str, path: &str) -> BufReader<TcpStream> {
+        let mut stream = TcpStream::connect(addr).expect("failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: {}\r\nAccept: text/event-stream\r\n\r\n", path, addr).as_bytes()).unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let head = ParsedResponse::read_head(&mut reader).expect("failed to read head");
+        assert_eq!(head.headers.get("Content-Type"), Some("text/event-stream"));
+        assert!(!head.headers.contains("Content-Length"));
+        reader
+    }
+
+    /// Reads up to and including the blank line ending an event
+    fn read_event(reader: &mut impl BufRead) -> String {
+        let mut event = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("failed to read event");
+            event.push_str(&line);
+            if line == "\n" {
+                return event;
+            }
+        }
+    }
+
+    fn wait_for(mut condition: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn event_format() {
+        let event = Event::new("line one\nline two\r\nline three")
+            .id("42")
+            .event("update\ninjected: field")
+            .retry(Duration::from_millis(1500));
+        assert_eq!(
+            event.to_string(),
+            "id: 42\nevent: update injected: field\nretry: 1500\ndata: line one\ndata: line two\ndata: line three\n\n"
+        );
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+    }
+
+    #[test]
+    fn push_events() {
+        let feed = Feed::default();
+        let addr = spawn_test_server(feed.clone());
+        let mut reader = connect(&addr, "/");
+
+        assert_eq!(read_event(&mut reader), "retry: 5000\ndata: \n\n");
+        assert!(wait_for(|| feed.streams.lock().unwrap().len() == 1));
+
+        let stream = feed.streams.lock().unwrap()[0].clone();
+        let producer = thread::spawn(move || {
+            stream.send(&Event::new("{\"apples\": 3}").id("1").event("price")).unwrap();
+            stream.send_data("two\nlines").unwrap();
+        });
+        producer.join().unwrap();
+
+        assert_eq!(read_event(&mut reader), "id: 1\nevent: price\ndata: {\"apples\": 3}\n\n");
+        assert_eq!(read_event(&mut reader), "data: two\ndata: lines\n\n");
+    }
+
+    #[test]
+    fn keep_alive_and_disconnect() {
+        let feed = Feed::default();
+        let addr = spawn_test_server(feed.clone());
+        let mut reader = connect(&addr, "/fast");
+
+        read_event(&mut reader);
+        assert_eq!(read_event(&mut reader), ": keep-alive\n\n");
+
+        assert!(wait_for(|| feed.streams.lock().unwrap().len() == 1));
+        let stream = feed.streams.lock().unwrap()[0].clone();
+        assert!(stream.is_connected());
+
+        drop(reader);
+        assert!(wait_for(|| !stream.is_connected()));
+        assert!(stream.send_data("anyone there?").is_err());
+    }
+
+    #[test]
+    fn needs_a_socket() {
+        let res = TestClient::new(Feed::default()).get("/");
+        assert!(res.is_err());
+    }
+}
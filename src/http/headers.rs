@@ -0,0 +1,105 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// An ordered list of HTTP header fields. Lookups ignore the case of the name,
+/// but the original casing and order are kept for when the headers are written out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers {
+    entries: Vec<(String, String)>
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    /// Returns the value of the first header named `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns every value of the headers named `name`, in the order they were added
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Replaces every header named `name` with a single header holding `value`
+    pub fn set(&mut self, name: &str, value: impl Into<String>) -> &mut Self {
+        self.remove(name);
+        self.add(name, value)
+    }
+
+    /// Adds a header without touching any existing ones with the same name
+    pub fn add(&mut self, name: &str, value: impl Into<String>) -> &mut Self {
+        self.entries.push((Str!(name), value.into()));
+        self
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Checks whether the comma separated header `name` contains `token`, ignoring case.
+    /// ex `Connection: keep-alive, Upgrade` contains `upgrade`
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+}
+
+/// Writes the headers as they appear on the wire, each line ending with CRLF
+impl Display for Headers {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        for (key, value) in self.iter() {
+            write!(f, "{}: {}\r\n", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_lookup() {
+        let mut headers = Headers::new();
+        headers.add("Content-Type", "text/html").add("vary", "Accept-Encoding");
+
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("VARY"), Some("Accept-Encoding"));
+        assert_eq!(headers.get("Accept"), None);
+
+        headers.set("CONTENT-TYPE", "text/plain");
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.to_string(), "vary: Accept-Encoding\r\nCONTENT-TYPE: text/plain\r\n");
+    }
+
+    #[test]
+    fn tokens() {
+        let mut headers = Headers::new();
+        headers.add("Connection", "keep-alive, Upgrade");
+        assert!(headers.has_token("connection", "upgrade"));
+        assert!(!headers.has_token("connection", "close"));
+    }
+}
@@ -0,0 +1,417 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::OnceLock
+};
+
+use super::ParseError;
+
+/*
+HPACK header compression for HTTP/2 (RFC 7541).
+The Decoder understands everything a peer can send, encode() keeps things simple and never
+adds to the peer's dynamic table, so it has no state to keep in sync.
+*/
+
+/// `(name, value)` pairs numbered from 1, RFC 7541 appendix A
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""), (":method", "GET"), (":method", "POST"), (":path", "/"), (":path", "/index.html"),
+    (":scheme", "http"), (":scheme", "https"), (":status", "200"), (":status", "204"), (":status", "206"),
+    (":status", "304"), (":status", "400"), (":status", "404"), (":status", "500"), ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"), ("accept-language", ""), ("accept-ranges", ""), ("accept", ""),
+    ("access-control-allow-origin", ""), ("age", ""), ("allow", ""), ("authorization", ""), ("cache-control", ""),
+    ("content-disposition", ""), ("content-encoding", ""), ("content-language", ""), ("content-length", ""),
+    ("content-location", ""), ("content-range", ""), ("content-type", ""), ("cookie", ""), ("date", ""),
+    ("etag", ""), ("expect", ""), ("expires", ""), ("from", ""), ("host", ""), ("if-match", ""),
+    ("if-modified-since", ""), ("if-none-match", ""), ("if-range", ""), ("if-unmodified-since", ""),
+    ("last-modified", ""), ("link", ""), ("location", ""), ("max-forwards", ""), ("proxy-authenticate", ""),
+    ("proxy-authorization", ""), ("range", ""), ("referer", ""), ("refresh", ""), ("retry-after", ""),
+    ("server", ""), ("set-cookie", ""), ("strict-transport-security", ""), ("transfer-encoding", ""),
+    ("user-agent", ""), ("vary", ""), ("via", ""), ("www-authenticate", ""),
+];
+
+/// `(code, length in bits)` of each byte and EOS (256), RFC 7541 appendix B
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28), (0xfffffe5, 28), (0xfffffe6, 28), (0xfffffe7, 28),
+    (0xfffffe8, 28), (0xffffea, 24), (0x3ffffffc, 30), (0xfffffe9, 28), (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28), (0xfffffec, 28),
+    (0xfffffed, 28), (0xfffffee, 28), (0xfffffef, 28), (0xffffff0, 28), (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28),
+    (0xffffff4, 28), (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28), (0xffffffa, 28), (0xffffffb, 28),
+    (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12), (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11),
+    (0x3fa, 10), (0x3fb, 10), (0xf9, 8), (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6),
+    (0x0, 5), (0x1, 5), (0x2, 5), (0x19, 6), (0x1a, 6), (0x1b, 6), (0x1c, 6), (0x1d, 6),
+    (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8), (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10),
+    (0x1ffa, 13), (0x21, 6), (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7),
+    (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7), (0x69, 7), (0x6a, 7),
+    (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7), (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7),
+    (0xfc, 8), (0x73, 7), (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+    (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5), (0x25, 6), (0x26, 6),
+    (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7), (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5),
+    (0x2b, 6), (0x76, 7), (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14), (0x1ffd, 13), (0xffffffc, 28),
+    (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20), (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22), (0x7fffd9, 23),
+    (0x3fffd6, 22), (0x7fffda, 23), (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23),
+    (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23), (0x7fffe2, 23), (0x7fffe3, 23),
+    (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23), (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24),
+    (0x3fffda, 22), (0x1fffdd, 21), (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22), (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21),
+    (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22), (0x7fffeb, 23), (0x7fffec, 23),
+    (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21), (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23),
+    (0xfffea, 20), (0x3fffe2, 22), (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23), (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23),
+    (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23), (0x3fffe8, 22), (0x1ffffec, 25),
+    (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27), (0x7ffffdf, 27), (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25),
+    (0x7fff2, 19), (0x1fffe3, 21), (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28), (0x7ffffe3, 27), (0x7ffffe4, 27), (0x7ffffe5, 27),
+    (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21), (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23),
+    (0x3fffea, 22), (0x3fffeb, 22), (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27), (0x7ffffe8, 27), (0x7ffffe9, 27), (0x7ffffea, 27),
+    (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27), (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
+
+/// Every entry costs its name and value plus 32 bytes of overhead
+const ENTRY_OVERHEAD: usize = 32;
+
+/// Headers that shouldn't be stored by intermediaries, they are sent as never indexed
+const SENSITIVE: [&str; 3] = ["authorization", "cookie", "set-cookie"];
+
+struct DynamicTable {
+    /// Newest first, which is the order they are indexed in
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn insert(&mut self, name: String, value: String) {
+        self.size += name.len() + value.len() + ENTRY_OVERHEAD;
+        self.entries.push_front((name, value));
+        self.evict();
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict();
+    }
+
+    /// Drops the oldest entries until the table fits, an entry bigger than the whole table empties it
+    fn evict(&mut self) {
+        while self.size > self.max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= name.len() + value.len() + ENTRY_OVERHEAD,
+                None => break
+            }
+        }
+    }
+}
+
+/// Decodes the header blocks of one connection, blocks have to be decoded in the order they were received
+pub struct Decoder {
+    table: DynamicTable,
+    /// The most the peer may grow the dynamic table to, our `SETTINGS_HEADER_TABLE_SIZE`
+    max_table_size: usize,
+}
+
+impl Decoder {
+    pub fn new(max_table_size: usize) -> Self {
+        Self {
+            table: DynamicTable { entries: VecDeque::new(), size: 0, max_size: max_table_size },
+            max_table_size
+        }
+    }
+
+    /// Size of the dynamic table as counted by HPACK
+    pub fn table_size(&self) -> usize {
+        self.table.size
+    }
+
+    /// Decodes a complete header block into `(name, value)` pairs in the order they were sent
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, ParseError> {
+        let mut input = block;
+        let mut headers = Vec::new();
+
+        while let Some(&first) = input.first() {
+            if first & 0x80 != 0 {
+                // indexed header field
+                let index = decode_integer(&mut input, 7)?;
+                headers.push(self.lookup(index)?);
+            } else if first & 0x40 != 0 {
+                // literal with incremental indexing
+                let (name, value) = self.decode_literal(&mut input, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                headers.push((name, value));
+            } else if first & 0x20 != 0 {
+                // dynamic table size update
+                let size = decode_integer(&mut input, 5)?;
+                if size > self.max_table_size {
+                    return Err(ParseError::InvalidHeader);
+                }
+                self.table.set_max_size(size);
+            } else {
+                // literal without indexing or never indexed, both are only about what intermediaries may store
+                headers.push(self.decode_literal(&mut input, 4)?);
+            }
+        }
+        Ok(headers)
+    }
+
+    fn decode_literal(&self, input: &mut &[u8], prefix_bits: u8) -> Result<(String, String), ParseError> {
+        let name = match decode_integer(input, prefix_bits)? {
+            0 => decode_string(input)?,
+            index => self.lookup(index)?.0
+        };
+        Ok((name, decode_string(input)?))
+    }
+
+    fn lookup(&self, index: usize) -> Result<(String, String), ParseError> {
+        let (name, value) = match index {
+            0 => return Err(ParseError::InvalidHeader),
+            1..=61 => STATIC_TABLE[index - 1],
+            _ => {
+                let (name, value) = self.table.entries.get(index - 62).ok_or(ParseError::InvalidHeader)?;
+                (name.as_str(), value.as_str())
+            }
+        };
+        Ok((Str!(name), Str!(value)))
+    }
+}
+
+/// Encodes a header block. Names have to be lowercase for HTTP/2
+pub fn encode<'h>(headers: impl IntoIterator<Item = (&'h str, &'h str)>) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in headers {
+        if let Some(i) = STATIC_TABLE.iter().position(|entry| *entry == (name, value)) {
+            encode_integer(&mut block, 0x80, 7, i + 1);
+            continue;
+        }
+
+        let flags = if SENSITIVE.contains(&name) { 0x10 } else { 0x00 };
+        match STATIC_TABLE.iter().position(|(static_name, _)| *static_name == name) {
+            Some(i) => encode_integer(&mut block, flags, 4, i + 1),
+            None => {
+                block.push(flags);
+                encode_string(&mut block, name);
+            }
+        }
+        encode_string(&mut block, value);
+    }
+    block
+}
+
+/// Integers fill the low `prefix_bits` of the first byte, bigger ones continue 7 bits at a time
+fn decode_integer(input: &mut &[u8], prefix_bits: u8) -> Result<usize, ParseError> {
+    let (&first, rest) = input.split_first().ok_or(ParseError::InvalidHeader)?;
+    *input = rest;
+
+    let max = (1 << prefix_bits) - 1;
+    let mut value = first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first().ok_or(ParseError::InvalidHeader)?;
+        *input = rest;
+        // anything past 28 bits is bigger than any size we'd accept
+        if shift > 28 {
+            return Err(ParseError::InvalidHeader);
+        }
+        value += ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_integer(block: &mut Vec<u8>, flags: u8, prefix_bits: u8, value: usize) {
+    let max = (1 << prefix_bits) - 1;
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max as u8);
+    let mut value = value - max;
+    while value >= 0x80 {
+        block.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    block.push(value as u8);
+}
+
+fn decode_string(input: &mut &[u8]) -> Result<String, ParseError> {
+    let huffman = input.first().is_some_and(|first| first & 0x80 != 0);
+    let length = decode_integer(input, 7)?;
+    if input.len() < length {
+        return Err(ParseError::InvalidHeader);
+    }
+    let (raw, rest) = input.split_at(length);
+    *input = rest;
+
+    let bytes = if huffman { huffman_decode(raw)? } else { raw.to_vec() };
+    String::from_utf8(bytes).map_err(|_| ParseError::InvalidEncoding)
+}
+
+/// Huffman codes only pay off for some strings, this uses whichever is shorter
+fn encode_string(block: &mut Vec<u8>, value: &str) {
+    let huffman = huffman_encode(value.as_bytes());
+    if huffman.len() < value.len() {
+        encode_integer(block, 0x80, 7, huffman.len());
+        block.extend_from_slice(&huffman);
+    } else {
+        encode_integer(block, 0x00, 7, value.len());
+        block.extend_from_slice(value.as_bytes());
+    }
+}
+
+/// Maps `(length, code)` back to the symbol
+fn huffman_symbols() -> &'static HashMap<(u8, u32), u16> {
+    static SYMBOLS: OnceLock<HashMap<(u8, u32), u16>> = OnceLock::new();
+    SYMBOLS.get_or_init(|| {
+        HUFFMAN_CODES.iter()
+            .enumerate()
+            .map(|(symbol, &(code, length))| ((length, code), symbol as u16))
+            .collect()
+    })
+}
+
+fn huffman_decode(input: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let symbols = huffman_symbols();
+    let mut decoded = Vec::with_capacity(input.len() * 8 / 5);
+    let (mut code, mut length) = (0_u32, 0_u8);
+
+    for byte in input {
+        for bit in (0..8).rev() {
+            code = (code << 1) | ((byte >> bit) & 1) as u32;
+            length += 1;
+            match symbols.get(&(length, code)) {
+                // EOS can't be part of a string
+                Some(256) => return Err(ParseError::InvalidHeader),
+                Some(&symbol) => {
+                    decoded.push(symbol as u8);
+                    code = 0;
+                    length = 0;
+                },
+                None if length >= 30 => return Err(ParseError::InvalidHeader),
+                None => {}
+            }
+        }
+    }
+    // what's left has to be padding, the start of EOS which is all ones, and less than a byte of it
+    if length > 7 || code != (1 << length) - 1 {
+        return Err(ParseError::InvalidHeader);
+    }
+    Ok(decoded)
+}
+
+fn huffman_encode(input: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(input.len());
+    let (mut bits, mut count) = (0_u64, 0_u32);
+
+    for &byte in input {
+        let (code, length) = HUFFMAN_CODES[byte as usize];
+        bits = (bits << length) | code as u64;
+        count += length as u32;
+        while count >= 8 {
+            count -= 8;
+            encoded.push((bits >> count) as u8);
+        }
+        bits &= (1 << count) - 1;
+    }
+    // pad with the most significant bits of EOS
+    if count > 0 {
+        encoded.push(((bits << (8 - count)) as u8) | (0xFF >> count));
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        let hex: String = hex.split_whitespace().collect();
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers.iter().map(|(name, value)| (Str!(*name), Str!(*value))).collect()
+    }
+
+    #[test]
+    fn integers() {
+        // RFC 7541 C.1
+        let mut block = Vec::new();
+        encode_integer(&mut block, 0, 5, 10);
+        encode_integer(&mut block, 0, 5, 1337);
+        encode_integer(&mut block, 0, 8, 42);
+        assert_eq!(block, [0x0A, 0x1F, 0x9A, 0x0A, 0x2A]);
+
+        let mut input = &block[..];
+        assert_eq!(decode_integer(&mut input, 5), Ok(10));
+        assert_eq!(decode_integer(&mut input, 5), Ok(1337));
+        assert_eq!(decode_integer(&mut input, 8), Ok(42));
+        assert!(input.is_empty());
+        assert_eq!(decode_integer(&mut &[0x1F, 0xFF][..], 5), Err(ParseError::InvalidHeader));
+    }
+
+    #[test]
+    fn requests_without_huffman() {
+        // RFC 7541 C.3, three requests on one connection
+        let mut decoder = Decoder::new(4096);
+        let first = decoder.decode(&hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d")).unwrap();
+        assert_eq!(first, pairs(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")]));
+        assert_eq!(decoder.table_size(), 57);
+
+        let second = decoder.decode(&hex("8286 84be 5808 6e6f 2d63 6163 6865")).unwrap();
+        assert_eq!(second[3..], pairs(&[(":authority", "www.example.com"), ("cache-control", "no-cache")]));
+        assert_eq!(decoder.table_size(), 110);
+
+        let third = decoder.decode(&hex("8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65")).unwrap();
+        assert_eq!(third, pairs(&[
+            (":method", "GET"), (":scheme", "https"), (":path", "/index.html"),
+            (":authority", "www.example.com"), ("custom-key", "custom-value")
+        ]));
+        assert_eq!(decoder.table_size(), 164);
+    }
+
+    #[test]
+    fn requests_with_huffman() {
+        // RFC 7541 C.4, the same requests with Huffman coded strings
+        let mut decoder = Decoder::new(4096);
+        let first = decoder.decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff")).unwrap();
+        assert_eq!(first[3], (Str!(":authority"), Str!("www.example.com")));
+
+        let second = decoder.decode(&hex("8286 84be 5886 a8eb 1064 9cbf")).unwrap();
+        assert_eq!(second[4], (Str!("cache-control"), Str!("no-cache")));
+
+        let third = decoder.decode(&hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf")).unwrap();
+        assert_eq!(third[4], (Str!("custom-key"), Str!("custom-value")));
+        assert_eq!(decoder.table_size(), 164);
+    }
+
+    #[test]
+    fn eviction() {
+        let mut decoder = Decoder::new(4096);
+        // a size update to 60 leaves room for one small entry
+        decoder.decode(&hex("3f1d 4001 6101 62 4001 6301 64")).unwrap();
+        assert_eq!(decoder.table_size(), 34);
+        assert_eq!(decoder.decode(&hex("be")).unwrap(), pairs(&[("c", "d")]));
+        assert_eq!(decoder.decode(&hex("bf")), Err(ParseError::InvalidHeader));
+        // bigger than what we allowed in our settings
+        assert_eq!(Decoder::new(100).decode(&hex("3f e1 1f")), Err(ParseError::InvalidHeader));
+    }
+
+    #[test]
+    fn round_trip() {
+        let headers = [
+            (":status", "200"), (":status", "418"), ("content-type", "text/html; charset=utf-8"),
+            ("set-cookie", "id=a3fWa; Secure"), ("x-custom", "Ünïcödé \u{1F34C}"), ("x-empty", "")
+        ];
+        let block = encode(headers.iter().copied());
+        assert_eq!(block[0], 0x88);
+        // set-cookie is never indexed
+        assert!(block.contains(&(0x10 | 0x0F)));
+
+        assert_eq!(Decoder::new(4096).decode(&block).unwrap(), pairs(&headers));
+        assert_eq!(huffman_decode(&huffman_encode(b"www.example.com")).unwrap(), b"www.example.com");
+        assert_eq!(huffman_encode(b"www.example.com"), hex("f1e3 c2e5 f23a 6ba0 ab90 f4ff"));
+    }
+}
@@ -0,0 +1,1184 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufReader, Read, Write, Result as IoResult},
+    net::{TcpStream, SocketAddr},
+    rc::Rc,
+    cell::RefCell,
+    sync::{
+        Arc, Mutex, MutexGuard, Condvar,
+        atomic::{AtomicUsize, Ordering}
+    },
+    thread
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL};
+use rayon::ThreadPool;
+
+use super::{
+    Headers,
+    Method,
+    Request,
+    Response,
+    RequestHandler,
+    StatusCode,
+    hpack,
+    parsed_response::ParsedResponse,
+    server::{self, respond, Refusal, ResponseOptions}
+};
+
+/*
+EXAMPLE CONNECTION (prior knowledge):
+
+client: PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n
+client: SETTINGS
+server: SETTINGS
+server: SETTINGS ack
+client: HEADERS stream=1 END_STREAM END_HEADERS   :method GET, :path /, ...
+client: HEADERS stream=3 END_HEADERS              :method POST, :path /upload, ...
+client: DATA stream=3 END_STREAM
+server: HEADERS stream=3 END_HEADERS              :status 201, ...
+server: DATA stream=3 END_STREAM
+server: HEADERS stream=1 END_HEADERS              :status 200, ...
+server: DATA stream=1 END_STREAM
+*/
+
+/// What every HTTP/2 client sends first
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// The protocol id to offer in TLS ALPN, once negotiated the connection starts with the [PREFACE] like prior knowledge
+pub const ALPN_PROTOCOL: &str = "h2";
+
+const FRAME_HEADER_LENGTH: usize = 9;
+/// The smallest max frame size a peer may set, and the default
+const DEFAULT_MAX_FRAME_SIZE: usize = 16_384;
+const DEFAULT_WINDOW_SIZE: i64 = 65_535;
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+const MAX_CONCURRENT_STREAMS: usize = 100;
+/// Header blocks (with their CONTINUATION frames) bigger than this end the connection
+const MAX_HEADER_BLOCK: usize = 64 * 1024;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+/// Error codes for RST_STREAM and GOAWAY, RFC 9113 section 7
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    SettingsTimeout = 0x4,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    Cancel = 0x8,
+    CompressionError = 0x9,
+    ConnectError = 0xA,
+    EnhanceYourCalm = 0xB,
+    InadequateSecurity = 0xC,
+    Http11Required = 0xD,
+}
+
+/// Whether an error ends one stream or the whole connection
+enum H2Error {
+    Connection(ErrorCode, &'static str),
+    Stream(u32, ErrorCode),
+    Io(io::Error),
+}
+
+impl From<io::Error> for H2Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Whether `bytes`, the start of a connection, is HTTP/2 with prior knowledge or an `Upgrade: h2c` request
+pub fn is_http2(bytes: &[u8]) -> bool {
+    let prefix = &PREFACE[..bytes.len().min(PREFACE.len())];
+    (!bytes.is_empty() && bytes.starts_with(prefix)) || upgrade_settings(bytes).is_some()
+}
+
+/// The decoded `HTTP2-Settings` of an `Upgrade: h2c` request. Requests with a body aren't upgraded,
+/// they are answered over HTTP/1.1 like any other
+fn upgrade_settings(bytes: &[u8]) -> Option<Vec<u8>> {
+    let req = Request::try_from(bytes).ok()?;
+    let headers = req.headers();
+    if !headers.has_token("Upgrade", "h2c") || !headers.has_token("Connection", "upgrade")
+        || !headers.has_token("Connection", "HTTP2-Settings") || req.body().is_some() {
+        return None;
+    }
+    let mut settings = headers.get_all("HTTP2-Settings");
+    match (settings.next(), settings.next()) {
+        (Some(settings), None) => BASE64_URL.decode(settings.trim().trim_end_matches('=')).ok(),
+        _ => None
+    }
+}
+
+/// Serves an HTTP/2 connection. `received` is what was already read from `stream`, either the start of the
+/// client preface or an `Upgrade: h2c` request, which is answered with a 101 and becomes stream 1.
+/// The connection is read on its own thread and each request is handled on `pool`, so slow requests don't hold up the others
+/// Responses are buffered and sent once the handler returns, so streamed bodies arrive all at once and
+/// [EventStream](super::event_stream::EventStream) or [websocket](super::websocket) upgrades fail like with [TestClient](super::test_client::TestClient).
+/// Bodies are buffered too, each is limited to [RequestHandler::max_body_size()] or else `max_body_size`, bigger ones get a 413
+pub fn serve<H: RequestHandler + Send + Sync + 'static>(
+    stream: TcpStream,
+    received: &[u8],
+    handler: Arc<H>,
+    pool: Arc<ThreadPool>,
+    options: ResponseOptions,
+    remote_addr: Option<SocketAddr>,
+    max_body_size: usize
+) -> IoResult<()> {
+    let mut writer = stream.try_clone()?;
+    let upgrade = match upgrade_settings(received) {
+        Some(settings) => {
+            writer.write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n")?;
+            Some((received.to_vec(), settings))
+        },
+        None => None
+    };
+    let received = if upgrade.is_some() { Vec::new() } else { received.to_vec() };
+
+    let connection = Arc::new(Connection {
+        shared: Mutex::new(Shared {
+            writer: Box::new(writer),
+            send_window: DEFAULT_WINDOW_SIZE,
+            stream_windows: HashMap::new(),
+            initial_window: DEFAULT_WINDOW_SIZE,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            closed: false
+        }),
+        window_opened: Condvar::new()
+    });
+    let dispatcher = Dispatcher {
+        handler,
+        pool,
+        options,
+        remote_addr,
+        max_body_size,
+        connection: connection.clone(),
+        active: Arc::new(AtomicUsize::new(0))
+    };
+
+    thread::Builder::new()
+        .name(Str!("http2"))
+        .spawn(move || {
+            let reader = BufReader::new(io::Cursor::new(received).chain(stream));
+            let mut reader = FrameReader::new(reader, dispatcher);
+            if let Err(e) = reader.run(upgrade) {
+                eprintln!("HTTP/2 connection failed: {}", e);
+            }
+            connection.close();
+        })?;
+    Ok(())
+}
+
+/// The writing half of the connection, shared by the reader and the threads sending responses
+struct Connection {
+    shared: Mutex<Shared>,
+    /// Signalled when a WINDOW_UPDATE arrives or the connection closes
+    window_opened: Condvar,
+}
+
+struct Shared {
+    writer: Box<dyn Write + Send>,
+    /// How much DATA the peer will still accept on the connection
+    send_window: i64,
+    /// The same for each stream with a response still to send
+    stream_windows: HashMap<u32, i64>,
+    /// The peer's `SETTINGS_INITIAL_WINDOW_SIZE`
+    initial_window: i64,
+    /// The peer's `SETTINGS_MAX_FRAME_SIZE`
+    max_frame_size: usize,
+    closed: bool,
+}
+
+impl Shared {
+    fn write_frame(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> IoResult<()> {
+        let length = (payload.len() as u32).to_be_bytes();
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LENGTH + payload.len());
+        frame.extend_from_slice(&length[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&(stream_id & 0x7FFF_FFFF).to_be_bytes());
+        frame.extend_from_slice(payload);
+        self.writer.write_all(&frame)?;
+        self.writer.flush()
+    }
+
+    /// Writes a header block, splitting it into CONTINUATION frames if it doesn't fit in one
+    fn write_headers(&mut self, stream_id: u32, block: &[u8], end_stream: bool) -> IoResult<()> {
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+        loop {
+            let chunk = chunks.next().unwrap_or(&[]);
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+                return self.write_frame(kind, flags, stream_id, chunk);
+            }
+            self.write_frame(kind, flags, stream_id, chunk)?;
+            kind = CONTINUATION;
+            flags = 0;
+        }
+    }
+}
+
+impl Connection {
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().unwrap()
+    }
+
+    fn send_frame(&self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> IoResult<()> {
+        self.lock().write_frame(kind, flags, stream_id, payload)
+    }
+
+    fn reset(&self, stream_id: u32, code: ErrorCode) -> IoResult<()> {
+        let mut shared = self.lock();
+        shared.stream_windows.remove(&stream_id);
+        self.window_opened.notify_all();
+        shared.write_frame(RST_STREAM, 0, stream_id, &(code as u32).to_be_bytes())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.window_opened.notify_all();
+    }
+
+    /// Sends a response that was written by a [RequestHandler], as HEADERS, DATA and trailing HEADERS frames
+    fn send_response(&self, stream_id: u32, res: &ParsedResponse) -> IoResult<()> {
+        let status = res.code.to_string();
+        let names: Vec<String> = res.headers.iter().map(|(name, _)| name.to_ascii_lowercase()).collect();
+        let fields = names.iter().map(String::as_str).zip(res.headers.iter().map(|(_, value)| value));
+        let block = hpack::encode([(":status", status.as_str())].into_iter().chain(fields.filter(|(name, _)| !is_connection_specific(name))));
+
+        let has_trailers = !res.trailers.is_empty();
+        self.lock().write_headers(stream_id, &block, res.body.is_empty() && !has_trailers)?;
+
+        let mut remaining = &res.body[..];
+        while !remaining.is_empty() {
+            let mut shared = self.lock();
+            let window = loop {
+                let stream_window = match shared.stream_windows.get(&stream_id) {
+                    Some(window) if !shared.closed => *window,
+                    // reset by the client or the connection is gone, nobody wants the rest
+                    _ => return Ok(())
+                };
+                let window = stream_window.min(shared.send_window);
+                if window > 0 {
+                    break window as usize;
+                }
+                shared = self.window_opened.wait(shared).unwrap();
+            };
+
+            let length = window.min(shared.max_frame_size).min(remaining.len());
+            let (chunk, rest) = remaining.split_at(length);
+            let end_stream = rest.is_empty() && !has_trailers;
+            shared.write_frame(DATA, if end_stream { END_STREAM } else { 0 }, stream_id, chunk)?;
+            shared.send_window -= length as i64;
+            if let Some(stream_window) = shared.stream_windows.get_mut(&stream_id) {
+                *stream_window -= length as i64;
+            }
+            remaining = rest;
+        }
+
+        let mut shared = self.lock();
+        if has_trailers {
+            let names: Vec<String> = res.trailers.iter().map(|(name, _)| name.to_ascii_lowercase()).collect();
+            let block = hpack::encode(names.iter().map(String::as_str).zip(res.trailers.iter().map(|(_, value)| value)));
+            shared.write_headers(stream_id, &block, true)?;
+        }
+        shared.stream_windows.remove(&stream_id);
+        Ok(())
+    }
+}
+
+/// Headers that only mean something for one HTTP/1.1 connection, HTTP/2 doesn't allow them
+fn is_connection_specific(name: &str) -> bool {
+    ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"].iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+/// Whether `s` is an HTTP token, the characters allowed in methods and header names
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Hands complete requests to the handler on the pool
+struct Dispatcher<H: RequestHandler + Send + Sync + 'static> {
+    handler: Arc<H>,
+    pool: Arc<ThreadPool>,
+    options: ResponseOptions,
+    remote_addr: Option<SocketAddr>,
+    /// The server's limit, for handlers without their own
+    max_body_size: usize,
+    connection: Arc<Connection>,
+    /// Streams being handled or sent, for `SETTINGS_MAX_CONCURRENT_STREAMS`
+    active: Arc<AtomicUsize>,
+}
+
+impl<H: RequestHandler + Send + Sync + 'static> Dispatcher<H> {
+    /// Turns the stream into the HTTP/1.1 request the handler expects and sends back whatever it answers
+    fn dispatch(&self, stream_id: u32, headers: Vec<(String, String)>, body: Vec<u8>) -> Result<(), H2Error> {
+        let (raw, method) = to_http1(&headers, Some(&body)).map_err(|code| H2Error::Stream(stream_id, code))?;
+        let handler = self.handler.clone();
+        let remote_addr = self.remote_addr;
+        self.answer(stream_id, method, false, move |response, options| respond(handler.as_ref(), &raw, remote_addr, response, options));
+        Ok(())
+    }
+
+    /// Checks the head of a stream that is about to send a body the same way as an HTTP/1.1 request,
+    /// returning how big its body can be. Refused streams are answered and reset, and `Ok(None)` is returned
+    fn body_limit(&self, stream_id: u32, headers: &[(String, String)]) -> Result<Option<usize>, H2Error> {
+        let (head, method) = to_http1(headers, None).map_err(|code| H2Error::Stream(stream_id, code))?;
+        let req = Request::try_from(&head[..]).map_err(|_| H2Error::Stream(stream_id, ErrorCode::ProtocolError))?;
+        let max_body_size = self.handler.max_body_size(&req).unwrap_or(self.max_body_size);
+        match server::check_body(&req, 0, max_body_size) {
+            Ok(_) => Ok(Some(max_body_size)),
+            Err(refusal) => {
+                self.refuse(stream_id, head, method, refusal);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Answers a stream with an error before its body is done, then resets it so the client stops sending
+    fn refuse(&self, stream_id: u32, head: Vec<u8>, method: Method, refusal: Refusal) {
+        self.answer(stream_id, method, true, move |response, options| server::refuse(&head, &refusal, response, options));
+    }
+
+    /// Runs `write` on the pool and sends the response it writes on the stream, then resets the stream if the client
+    /// could still be sending its body
+    fn answer(
+        &self,
+        stream_id: u32,
+        method: Method,
+        reset: bool,
+        write: impl FnOnce(&mut Response, &ResponseOptions) -> IoResult<()> + Send + 'static
+    ) {
+        let options = self.options.clone();
+        let connection = self.connection.clone();
+        let active = self.active.clone();
+        active.fetch_add(1, Ordering::SeqCst);
+
+        self.pool.spawn(move || {
+            let buffer = Rc::new(RefCell::new(Vec::<u8>::new()));
+            let mut response = Response::new(buffer.clone());
+            if let Err(e) = write(&mut response, &options) {
+                eprintln!("Something went wrong handling HTTP/2 stream {}: {}", stream_id, e);
+            }
+
+            let bytes = buffer.borrow();
+            let sent = match ParsedResponse::read_from(&mut &bytes[..], &method) {
+                Ok(res) if reset => connection.send_response(stream_id, &res).and_then(|_| connection.reset(stream_id, ErrorCode::NoError)),
+                Ok(res) => connection.send_response(stream_id, &res),
+                Err(_) => connection.reset(stream_id, ErrorCode::InternalError)
+            };
+            if let Err(e) = sent {
+                eprintln!("Failed to send HTTP/2 response on stream {}: {}", stream_id, e);
+            }
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Builds the HTTP/1.1 request matching an HTTP/2 one, the method is returned to know how the response is framed.
+/// Without a `body` only the head is built, with the `content-length` the client sent
+fn to_http1(fields: &[(String, String)], body: Option<&[u8]>) -> Result<(Vec<u8>, Method), ErrorCode> {
+    let (mut method, mut path, mut authority) = (None, None, None);
+    let mut headers = Headers::new();
+    let mut cookies = Vec::new();
+
+    for (i, (name, value)) in fields.iter().enumerate() {
+        // anything that could end a line would let the client write its own headers or request into the HTTP/1.1 one
+        let bare_name = name.strip_prefix(':').unwrap_or(name);
+        if !is_token(bare_name) || name.bytes().any(|b| b.is_ascii_uppercase()) || is_connection_specific(name)
+            || value.bytes().any(|b| matches!(b, b'\r' | b'\n' | b'\0')) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        match name.as_str() {
+            // pseudo headers have to come first
+            _ if name.starts_with(':') && fields[..i].iter().any(|(n, _)| !n.starts_with(':')) => return Err(ErrorCode::ProtocolError),
+            ":method" => method = Some(value.as_str()),
+            ":path" => path = Some(value.as_str()),
+            ":authority" => authority = Some(value.as_str()),
+            ":scheme" => {},
+            _ if name.starts_with(':') => return Err(ErrorCode::ProtocolError),
+            "te" if value != "trailers" => return Err(ErrorCode::ProtocolError),
+            // cookies can be split into several fields to compress better
+            "cookie" => cookies.push(value.as_str()),
+            _ => { headers.add(name, value.as_str()); }
+        }
+    }
+
+    let (method, path) = method.zip(path).ok_or(ErrorCode::ProtocolError)?;
+    // the request line is split on spaces
+    if !is_token(method) || path.is_empty() || path.bytes().any(|b| b.is_ascii_whitespace() || b.is_ascii_control()) {
+        return Err(ErrorCode::ProtocolError);
+    }
+    if let Some(authority) = authority.filter(|_| !headers.contains("host")) {
+        headers.add("host", authority);
+    }
+    if !cookies.is_empty() {
+        headers.add("cookie", cookies.join("; "));
+    }
+    if let Some(body) = body.filter(|body| !body.is_empty() || headers.contains("content-length")) {
+        headers.set("content-length", body.len().to_string());
+    }
+
+    let mut raw = format!("{} {} HTTP/1.1\r\n{}\r\n", method, path, headers).into_bytes();
+    raw.extend_from_slice(body.unwrap_or(&[]));
+    let method = if method == "HEAD" { Method::HEAD } else { Method::GET };
+    Ok((raw, method))
+}
+
+/// A stream that is still receiving its request
+struct OpenStream {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    /// From [Dispatcher::body_limit()]
+    max_body_size: usize,
+}
+
+/// Reads the client's frames and keeps track of the streams
+struct FrameReader<R: Read, H: RequestHandler + Send + Sync + 'static> {
+    reader: R,
+    dispatcher: Dispatcher<H>,
+    decoder: hpack::Decoder,
+    streams: HashMap<u32, OpenStream>,
+    /// Streams answered before their body ended, the rest of the body is thrown away
+    refused: HashSet<u32>,
+    last_stream_id: u32,
+    /// How much DATA the client may still send on the connection before we send a WINDOW_UPDATE
+    receive_window: i64,
+    /// A header block waiting for CONTINUATION frames: stream, END_STREAM and the block so far
+    continuing: Option<(u32, bool, Vec<u8>)>,
+    /// The client said it is going away, no new streams are accepted
+    going_away: bool,
+}
+
+impl<R: Read, H: RequestHandler + Send + Sync + 'static> FrameReader<R, H> {
+    fn new(reader: R, dispatcher: Dispatcher<H>) -> Self {
+        Self {
+            reader,
+            dispatcher,
+            decoder: hpack::Decoder::new(4096),
+            streams: HashMap::new(),
+            refused: HashSet::new(),
+            last_stream_id: 0,
+            receive_window: DEFAULT_WINDOW_SIZE,
+            continuing: None,
+            going_away: false
+        }
+    }
+
+    fn connection(&self) -> &Connection {
+        &self.dispatcher.connection
+    }
+
+    /// Runs the connection until the client closes it or breaks the protocol
+    fn run(&mut self, upgrade: Option<(Vec<u8>, Vec<u8>)>) -> IoResult<()> {
+        let mut settings = Vec::new();
+        for (id, value) in [(SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS as u32), (SETTINGS_ENABLE_PUSH, 0)] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+        self.connection().send_frame(SETTINGS, 0, 0, &settings)?;
+
+        // the upgraded request is stream 1, already complete
+        if let Some((request, settings)) = upgrade {
+            self.apply_settings(&settings).map_err(|_| err!(InvalidData, "Invalid HTTP2-Settings"))?;
+            let req = Request::try_from(&request[..]).map_err(io::Error::from)?;
+            let mut fields = vec![
+                (Str!(":method"), req.method().to_string()),
+                (Str!(":path"), Str!(req.target())),
+                (Str!(":scheme"), Str!("http"))
+            ];
+            let skip = ["connection", "upgrade", "http2-settings", "host"];
+            fields.extend(req.headers().iter().filter(|(name, _)| !skip.iter().any(|s| s.eq_ignore_ascii_case(name)))
+                .map(|(name, value)| (name.to_ascii_lowercase(), Str!(value))));
+            if let Some(host) = req.headers().get("Host") {
+                fields.push((Str!(":authority"), Str!(host)));
+            }
+            // pseudo headers first
+            fields.sort_by_key(|(name, _)| !name.starts_with(':'));
+
+            self.last_stream_id = 1;
+            self.open_send_window(1);
+            if let Err(H2Error::Stream(id, code)) = self.dispatcher.dispatch(1, fields, Vec::new()) {
+                self.connection().reset(id, code)?;
+            }
+        }
+
+        let mut preface = [0; PREFACE.len()];
+        self.reader.read_exact(&mut preface)?;
+        if preface != PREFACE {
+            return self.go_away(ErrorCode::ProtocolError, "Invalid connection preface");
+        }
+
+        let mut first = true;
+        loop {
+            let result = self.read_frame().and_then(|(kind, flags, stream_id, payload)| {
+                if first && kind != SETTINGS {
+                    return Err(H2Error::Connection(ErrorCode::ProtocolError, "The first frame has to be SETTINGS"));
+                }
+                first = false;
+                self.handle_frame(kind, flags, stream_id, payload)
+            });
+
+            match result {
+                Ok(()) => {},
+                Err(H2Error::Stream(stream_id, code)) => {
+                    self.streams.remove(&stream_id);
+                    self.connection().reset(stream_id, code)?;
+                },
+                Err(H2Error::Connection(code, reason)) => return self.go_away(code, reason),
+                // the client closed the connection
+                Err(H2Error::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(H2Error::Io(e)) => return Err(e)
+            }
+        }
+    }
+
+    fn go_away(&mut self, code: ErrorCode, reason: &str) -> IoResult<()> {
+        eprintln!("Closing HTTP/2 connection: {}", reason);
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&(code as u32).to_be_bytes());
+        payload.extend_from_slice(reason.as_bytes());
+        self.connection().send_frame(GOAWAY, 0, 0, &payload)
+    }
+
+    fn read_frame(&mut self) -> Result<(u8, u8, u32, Vec<u8>), H2Error> {
+        let mut header = [0; FRAME_HEADER_LENGTH];
+        self.reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let (kind, flags) = (header[3], header[4]);
+        let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF;
+
+        // we never raise SETTINGS_MAX_FRAME_SIZE
+        if length > DEFAULT_MAX_FRAME_SIZE {
+            return Err(H2Error::Connection(ErrorCode::FrameSizeError, "Frame is bigger than the max frame size"));
+        }
+        let mut payload = vec![0; length];
+        self.reader.read_exact(&mut payload)?;
+        Ok((kind, flags, stream_id, payload))
+    }
+
+    fn handle_frame(&mut self, kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Result<(), H2Error> {
+        if let Some((continued_id, _, _)) = &self.continuing {
+            if kind != CONTINUATION || stream_id != *continued_id {
+                return Err(H2Error::Connection(ErrorCode::ProtocolError, "Expected a CONTINUATION frame"));
+            }
+        }
+        if stream_id == 0 && matches!(kind, DATA | HEADERS | PRIORITY | RST_STREAM | CONTINUATION) {
+            return Err(H2Error::Connection(ErrorCode::ProtocolError, "Frame needs a stream"));
+        }
+        if stream_id != 0 && matches!(kind, SETTINGS | PING | GOAWAY) {
+            return Err(H2Error::Connection(ErrorCode::ProtocolError, "Frame can't be sent on a stream"));
+        }
+
+        match kind {
+            DATA => self.on_data(flags, stream_id, &payload),
+            HEADERS => {
+                let mut block = strip_padding(flags, &payload)?;
+                if flags & PRIORITY_FLAG != 0 {
+                    block = block.get(5..).ok_or(H2Error::Connection(ErrorCode::FrameSizeError, "HEADERS too short for its priority"))?;
+                }
+                self.continuing = Some((stream_id, flags & END_STREAM != 0, block.to_vec()));
+                if flags & END_HEADERS != 0 {
+                    self.on_header_block()?;
+                }
+                Ok(())
+            },
+            CONTINUATION => {
+                let (_, _, block) = self.continuing.as_mut()
+                    .ok_or(H2Error::Connection(ErrorCode::ProtocolError, "CONTINUATION without HEADERS"))?;
+                block.extend_from_slice(&payload);
+                if block.len() > MAX_HEADER_BLOCK {
+                    return Err(H2Error::Connection(ErrorCode::EnhanceYourCalm, "Header block is too big"));
+                }
+                if flags & END_HEADERS != 0 {
+                    self.on_header_block()?;
+                }
+                Ok(())
+            },
+            // we don't prioritize, but the frame still has to be well formed
+            PRIORITY if payload.len() != 5 => Err(H2Error::Stream(stream_id, ErrorCode::FrameSizeError)),
+            PRIORITY => Ok(()),
+            RST_STREAM => {
+                if payload.len() != 4 {
+                    return Err(H2Error::Connection(ErrorCode::FrameSizeError, "RST_STREAM has to be 4 bytes"));
+                }
+                if stream_id > self.last_stream_id {
+                    return Err(H2Error::Connection(ErrorCode::ProtocolError, "RST_STREAM on an idle stream"));
+                }
+                self.streams.remove(&stream_id);
+                self.refused.remove(&stream_id);
+                let connection = self.connection();
+                connection.lock().stream_windows.remove(&stream_id);
+                connection.window_opened.notify_all();
+                Ok(())
+            },
+            SETTINGS if flags & ACK != 0 => match payload.len() {
+                0 => Ok(()),
+                _ => Err(H2Error::Connection(ErrorCode::FrameSizeError, "SETTINGS ack with a payload"))
+            },
+            SETTINGS => {
+                self.apply_settings(&payload)?;
+                Ok(self.connection().send_frame(SETTINGS, ACK, 0, &[])?)
+            },
+            PUSH_PROMISE => Err(H2Error::Connection(ErrorCode::ProtocolError, "Clients can't push")),
+            PING if payload.len() != 8 => Err(H2Error::Connection(ErrorCode::FrameSizeError, "PING has to be 8 bytes")),
+            PING if flags & ACK != 0 => Ok(()),
+            PING => Ok(self.connection().send_frame(PING, ACK, 0, &payload)?),
+            GOAWAY => {
+                // streams already started still get their responses
+                self.going_away = true;
+                Ok(())
+            },
+            WINDOW_UPDATE => self.on_window_update(stream_id, &payload),
+            // unknown frame types are ignored
+            _ => Ok(())
+        }
+    }
+
+    fn on_data(&mut self, flags: u8, stream_id: u32, payload: &[u8]) -> Result<(), H2Error> {
+        // padding counts towards flow control too
+        self.receive_window -= payload.len() as i64;
+        if self.receive_window < 0 {
+            return Err(H2Error::Connection(ErrorCode::FlowControlError, "DATA beyond the connection window"));
+        }
+        let data = strip_padding(flags, payload)?;
+        let end_stream = flags & END_STREAM != 0;
+
+        // the connection window is given straight back, the data is either buffered for the handler or thrown away
+        let increment = (payload.len() as u32).to_be_bytes();
+        if !payload.is_empty() {
+            self.receive_window += payload.len() as i64;
+            self.connection().send_frame(WINDOW_UPDATE, 0, 0, &increment)?;
+        }
+
+        if self.refused.contains(&stream_id) {
+            if end_stream {
+                self.refused.remove(&stream_id);
+            }
+            return Ok(());
+        }
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(stream) => stream,
+            None if stream_id > self.last_stream_id => return Err(H2Error::Connection(ErrorCode::ProtocolError, "DATA on an idle stream")),
+            None => return Err(H2Error::Stream(stream_id, ErrorCode::StreamClosed))
+        };
+        if stream.body.len() + data.len() > stream.max_body_size {
+            let stream = self.streams.remove(&stream_id).unwrap();
+            let detail = format!("The body can't be over {} bytes", stream.max_body_size);
+            let (head, method) = to_http1(&stream.headers, None).map_err(|code| H2Error::Stream(stream_id, code))?;
+            self.dispatcher.refuse(stream_id, head, method, Refusal { status: StatusCode::PayloadTooLarge, detail });
+            if !end_stream {
+                self.refused.insert(stream_id);
+            }
+            return Ok(());
+        }
+        stream.body.extend_from_slice(data);
+
+        // the stream's window only opens again for data the limit still allows
+        if !payload.is_empty() && !end_stream {
+            self.connection().send_frame(WINDOW_UPDATE, 0, stream_id, &increment)?;
+        }
+
+        if end_stream {
+            let stream = self.streams.remove(&stream_id).unwrap();
+            self.dispatcher.dispatch(stream_id, stream.headers, stream.body)?;
+        }
+        Ok(())
+    }
+
+    fn on_header_block(&mut self) -> Result<(), H2Error> {
+        let (stream_id, end_stream, block) = self.continuing.take().unwrap();
+        // always decoded, even for refused streams, to keep the dynamic table in sync
+        let headers = self.decoder.decode(&block)
+            .map_err(|_| H2Error::Connection(ErrorCode::CompressionError, "Invalid header block"))?;
+
+        // trailers of a stream that is sending a body
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            if !end_stream {
+                return Err(H2Error::Stream(stream_id, ErrorCode::ProtocolError));
+            }
+            stream.headers.extend(headers.into_iter().filter(|(name, _)| !name.starts_with(':')));
+            let stream = self.streams.remove(&stream_id).unwrap();
+            return self.dispatcher.dispatch(stream_id, stream.headers, stream.body);
+        }
+
+        if stream_id % 2 == 0 || stream_id <= self.last_stream_id {
+            return Err(H2Error::Connection(ErrorCode::ProtocolError, "HEADERS on a closed or server stream"));
+        }
+        self.last_stream_id = stream_id;
+        if self.going_away || self.streams.len() + self.dispatcher.active.load(Ordering::SeqCst) >= MAX_CONCURRENT_STREAMS {
+            return Err(H2Error::Stream(stream_id, ErrorCode::RefusedStream));
+        }
+
+        self.open_send_window(stream_id);
+        if end_stream {
+            self.dispatcher.dispatch(stream_id, headers, Vec::new())
+        } else {
+            match self.dispatcher.body_limit(stream_id, &headers)? {
+                Some(max_body_size) => { self.streams.insert(stream_id, OpenStream { headers, body: Vec::new(), max_body_size }); },
+                None => { self.refused.insert(stream_id); }
+            }
+            Ok(())
+        }
+    }
+
+    fn open_send_window(&self, stream_id: u32) {
+        let mut shared = self.connection().lock();
+        let initial = shared.initial_window;
+        shared.stream_windows.insert(stream_id, initial);
+    }
+
+    fn on_window_update(&mut self, stream_id: u32, payload: &[u8]) -> Result<(), H2Error> {
+        let increment = match payload {
+            [a, b, c, d] => (u32::from_be_bytes([*a, *b, *c, *d]) & 0x7FFF_FFFF) as i64,
+            _ => return Err(H2Error::Connection(ErrorCode::FrameSizeError, "WINDOW_UPDATE has to be 4 bytes"))
+        };
+        if increment == 0 {
+            return match stream_id {
+                0 => Err(H2Error::Connection(ErrorCode::ProtocolError, "WINDOW_UPDATE of 0")),
+                _ => Err(H2Error::Stream(stream_id, ErrorCode::ProtocolError))
+            };
+        }
+
+        let connection = self.connection();
+        let mut shared = connection.lock();
+        let window = match stream_id {
+            0 => &mut shared.send_window,
+            _ => match shared.stream_windows.get_mut(&stream_id) {
+                Some(window) => window,
+                // the response is already sent
+                None => return Ok(())
+            }
+        };
+        *window += increment;
+        if *window > MAX_WINDOW_SIZE {
+            return match stream_id {
+                0 => Err(H2Error::Connection(ErrorCode::FlowControlError, "Connection window too big")),
+                _ => Err(H2Error::Stream(stream_id, ErrorCode::FlowControlError))
+            };
+        }
+        connection.window_opened.notify_all();
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), H2Error> {
+        if payload.len() % 6 != 0 {
+            return Err(H2Error::Connection(ErrorCode::FrameSizeError, "SETTINGS length isn't a multiple of 6"));
+        }
+        let connection = self.connection();
+        let mut shared = connection.lock();
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(H2Error::Connection(ErrorCode::ProtocolError, "Invalid ENABLE_PUSH")),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if value as i64 > MAX_WINDOW_SIZE {
+                        return Err(H2Error::Connection(ErrorCode::FlowControlError, "Initial window size too big"));
+                    }
+                    // applies to the streams already open as well
+                    let delta = value as i64 - shared.initial_window;
+                    shared.initial_window = value as i64;
+                    for window in shared.stream_windows.values_mut() {
+                        *window += delta;
+                    }
+                },
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(DEFAULT_MAX_FRAME_SIZE..=0xFF_FFFF).contains(&(value as usize)) {
+                        return Err(H2Error::Connection(ErrorCode::ProtocolError, "Invalid max frame size"));
+                    }
+                    shared.max_frame_size = value as usize;
+                },
+                // our encoder doesn't use the dynamic table, so SETTINGS_HEADER_TABLE_SIZE doesn't matter.
+                // we don't push or open streams, unknown settings are ignored
+                _ => {}
+            }
+        }
+        connection.window_opened.notify_all();
+        Ok(())
+    }
+}
+
+fn strip_padding(flags: u8, payload: &[u8]) -> Result<&[u8], H2Error> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    match payload.split_first() {
+        Some((&padding, rest)) if (padding as usize) <= rest.len() => Ok(&rest[..rest.len() - padding as usize]),
+        _ => Err(H2Error::Connection(ErrorCode::ProtocolError, "Padding is longer than the frame"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::http::test_client::spawn_test_server;
+
+    struct Handler;
+
+    impl RequestHandler for Handler {
+        fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            match req.path() {
+                "/slow" => {
+                    thread::sleep(Duration::from_millis(300));
+                    res.ok(Some(Str!("slow")))
+                },
+                "/big" => res.ok(Some("x".repeat(100))),
+                "/stream" => {
+                    res.set_header("Trailer", "X-Count");
+                    let mut writer = res.stream()?;
+                    writer.write_all(b"streamed")?;
+                    writer.set_trailer("X-Count", "1");
+                    writer.finish()
+                },
+                _ => {
+                    res.set_header("X-Cookie", req.headers().get("Cookie").unwrap_or(""));
+                    res.ok(Some(format!("{} {}", req.target(), req.headers().get("Host").unwrap_or(""))))
+                }
+            }
+        }
+        fn post(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            res.status = StatusCode::Created;
+            res.set_body(req.body().unwrap_or(&[]).to_vec()).send()
+        }
+        fn max_body_size(&self, req: &Request) -> Option<usize> {
+            (req.path() == "/small").then_some(10)
+        }
+    }
+
+    /// Just enough of an HTTP/2 client to talk to the server
+    struct TestConnection {
+        stream: TcpStream,
+        decoder: hpack::Decoder,
+    }
+
+    struct TestResponse {
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+        trailers: Vec<(String, String)>,
+    }
+
+    impl TestResponse {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+        }
+    }
+
+    impl TestConnection {
+        fn connect(addr: &str, settings: &[(u16, u32)]) -> Self {
+            let stream = TcpStream::connect(addr).expect("failed to connect");
+            Self::start(stream, settings)
+        }
+
+        fn start(mut stream: TcpStream, settings: &[(u16, u32)]) -> Self {
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut preface = PREFACE.to_vec();
+            preface.extend(frame(SETTINGS, 0, 0, &settings.iter().flat_map(|(id, value)| {
+                id.to_be_bytes().into_iter().chain(value.to_be_bytes())
+            }).collect::<Vec<u8>>()));
+            stream.write_all(&preface).unwrap();
+            Self { stream, decoder: hpack::Decoder::new(4096) }
+        }
+
+        fn send(&mut self, kind: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+            self.stream.write_all(&frame(kind, flags, stream_id, payload)).unwrap();
+        }
+
+        fn request(&mut self, stream_id: u32, method: &str, path: &str, extra: &[(&str, &str)], end_stream: bool) {
+            let fields = [(":method", method), (":scheme", "http"), (":path", path), (":authority", "example.com")];
+            let block = hpack::encode(fields.iter().chain(extra).copied());
+            self.send(HEADERS, END_HEADERS | if end_stream { END_STREAM } else { 0 }, stream_id, &block);
+        }
+
+        fn read(&mut self) -> (u8, u8, u32, Vec<u8>) {
+            let mut header = [0; FRAME_HEADER_LENGTH];
+            self.stream.read_exact(&mut header).expect("failed to read frame");
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let mut payload = vec![0; length];
+            self.stream.read_exact(&mut payload).unwrap();
+            (header[3], header[4], u32::from_be_bytes([header[5], header[6], header[7], header[8]]), payload)
+        }
+
+        /// Reads frames until one of the responses ends, returning its stream
+        fn next_response(&mut self, responses: &mut HashMap<u32, TestResponse>) -> u32 {
+            loop {
+                let (kind, flags, stream_id, payload) = self.read();
+                match kind {
+                    HEADERS => {
+                        let fields = self.decoder.decode(&payload).expect("invalid header block");
+                        match responses.get_mut(&stream_id) {
+                            Some(res) => res.trailers = fields,
+                            None => {
+                                responses.insert(stream_id, TestResponse { headers: fields, body: Vec::new(), trailers: Vec::new() });
+                            }
+                        }
+                    },
+                    DATA => responses.get_mut(&stream_id).expect("DATA before HEADERS").body.extend(payload),
+                    GOAWAY | RST_STREAM => panic!("unexpected error frame {} {:?}", kind, payload),
+                    _ => continue
+                }
+                if flags & END_STREAM != 0 {
+                    return stream_id;
+                }
+            }
+        }
+
+        fn response(&mut self) -> TestResponse {
+            let mut responses = HashMap::new();
+            let stream_id = self.next_response(&mut responses);
+            responses.remove(&stream_id).unwrap()
+        }
+
+        /// Reads until a GOAWAY or RST_STREAM, returning its type and error code
+        fn error(&mut self) -> (u8, u32) {
+            loop {
+                let (kind, _, _, payload) = self.read();
+                match kind {
+                    GOAWAY => return (kind, u32::from_be_bytes(payload[4..8].try_into().unwrap())),
+                    RST_STREAM => return (kind, u32::from_be_bytes(payload[..4].try_into().unwrap())),
+                    _ => continue
+                }
+            }
+        }
+    }
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend([kind, flags]);
+        frame.extend(stream_id.to_be_bytes());
+        frame.extend(payload);
+        frame
+    }
+
+    #[test]
+    fn prior_knowledge() {
+        let mut conn = TestConnection::connect(&spawn_test_server(Handler), &[]);
+        conn.request(1, "GET", "/hello?a=1", &[("cookie", "a=1"), ("cookie", "b=2")], true);
+        let res = conn.response();
+
+        assert_eq!(res.header(":status"), Some("200"));
+        assert_eq!(res.header("x-cookie"), Some("a=1; b=2"));
+        assert_eq!(res.header("connection"), None);
+        assert_eq!(res.body, b"/hello?a=1 example.com");
+
+        conn.request(3, "POST", "/upload", &[], false);
+        conn.send(DATA, 0, 3, b"Apples ");
+        conn.send(DATA, PADDED | END_STREAM, 3, b"\x03and Bananas\0\0\0");
+        let res = conn.response();
+        assert_eq!(res.header(":status"), Some("201"));
+        assert_eq!(res.body, b"Apples and Bananas");
+
+        conn.request(5, "GET", "/stream", &[], true);
+        let res = conn.response();
+        assert_eq!(res.body, b"streamed");
+        assert_eq!(res.header("transfer-encoding"), None);
+        assert_eq!(res.trailers, [(Str!("x-count"), Str!("1"))]);
+    }
+
+    #[test]
+    fn multiplexing() {
+        let mut conn = TestConnection::connect(&spawn_test_server(Handler), &[]);
+        conn.request(1, "GET", "/slow", &[], true);
+        conn.request(3, "GET", "/fast", &[], true);
+
+        let mut responses = HashMap::new();
+        let first = conn.next_response(&mut responses);
+        let second = conn.next_response(&mut responses);
+        // the server's pool has a thread per core, with just one the handlers still run in turn
+        if thread::available_parallelism().map_or(1, |n| n.get()) > 1 {
+            assert_eq!((first, second), (3, 1));
+        }
+        assert_eq!(responses[&1].body, b"slow");
+        assert_eq!(responses[&3].body, b"/fast example.com");
+    }
+
+    #[test]
+    fn flow_control() {
+        let mut conn = TestConnection::connect(&spawn_test_server(Handler), &[(SETTINGS_INITIAL_WINDOW_SIZE, 10)]);
+        conn.request(1, "GET", "/big", &[], true);
+
+        let mut received = Vec::new();
+        while received.len() < 10 {
+            let (kind, _, _, payload) = conn.read();
+            if kind == DATA {
+                received.extend(payload);
+            }
+        }
+        assert_eq!(received.len(), 10);
+
+        // nothing more until the window opens
+        conn.stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        let mut byte = [0; 1];
+        assert!(conn.stream.read(&mut byte).is_err());
+        conn.stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        conn.send(WINDOW_UPDATE, 0, 1, &90_u32.to_be_bytes());
+        loop {
+            let (kind, flags, _, payload) = conn.read();
+            if kind == DATA {
+                received.extend(payload);
+                if flags & END_STREAM != 0 {
+                    break;
+                }
+            }
+        }
+        assert_eq!(received, "x".repeat(100).as_bytes());
+    }
+
+    #[test]
+    fn h2c_upgrade() {
+        let addr = spawn_test_server(Handler);
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let request = format!(
+            "GET /upgraded HTTP/1.1\r\nHost: {}\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: {}\r\n\r\n",
+            addr, BASE64_URL.encode([0, 3, 0, 0, 0, 100])
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+
+        // byte by byte, the frames that follow mustn't end up in a buffer
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).expect("failed to read the 101");
+            head.push(byte[0]);
+        }
+        assert!(head.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+
+        let mut conn = TestConnection::start(stream, &[]);
+        let res = conn.response();
+        assert_eq!(res.header(":status"), Some("200"));
+        assert_eq!(res.body, format!("/upgraded {}", addr).as_bytes());
+
+        conn.request(3, "GET", "/after", &[], true);
+        assert_eq!(conn.response().body, b"/after example.com");
+    }
+
+    #[test]
+    fn protocol_errors() {
+        let addr = spawn_test_server(Handler);
+
+        let mut conn = TestConnection::connect(&addr, &[]);
+        conn.send(DATA, END_STREAM, 0, b"no stream");
+        assert_eq!(conn.error(), (GOAWAY, ErrorCode::ProtocolError as u32));
+
+        let mut conn = TestConnection::connect(&addr, &[]);
+        conn.request(1, "GET", "/", &[("connection", "keep-alive")], true);
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::ProtocolError as u32));
+        // the connection is still usable
+        conn.request(3, "GET", "/fine", &[], true);
+        assert_eq!(conn.response().header(":status"), Some("200"));
+
+        let mut conn = TestConnection::connect(&addr, &[]);
+        conn.send(HEADERS, END_HEADERS | END_STREAM, 1, &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+        assert_eq!(conn.error(), (GOAWAY, ErrorCode::CompressionError as u32));
+    }
+
+    #[test]
+    fn body_limits() {
+        let mut conn = TestConnection::connect(&spawn_test_server(Handler), &[]);
+        // refused from the head, before any DATA
+        conn.request(1, "POST", "/small", &[("content-length", "11")], false);
+        let res = conn.response();
+        assert_eq!(res.header(":status"), Some("413"));
+        assert!(String::from_utf8_lossy(&res.body).contains("10 bytes"));
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::NoError as u32));
+        // the client may still be sending, that is thrown away
+        conn.send(DATA, END_STREAM, 1, b"01234567890");
+
+        // refused once the DATA goes over the handler's limit
+        conn.request(3, "POST", "/small", &[], false);
+        conn.send(DATA, 0, 3, b"012345");
+        conn.send(DATA, 0, 3, b"67890");
+        assert_eq!(conn.response().header(":status"), Some("413"));
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::NoError as u32));
+
+        conn.request(5, "POST", "/small", &[], false);
+        conn.send(DATA, END_STREAM, 5, b"0123456789");
+        assert_eq!(conn.response().body, b"0123456789");
+
+        // everything else gets the server's limit
+        conn.request(7, "POST", "/upload", &[], false);
+        let chunk = vec![b'x'; DEFAULT_MAX_FRAME_SIZE];
+        let mut sent = 0;
+        while sent <= 1024 * 1024 {
+            conn.send(DATA, 0, 7, &chunk);
+            sent += chunk.len();
+            // wait for the connection window to come back before sending more
+            while !matches!(conn.read(), (WINDOW_UPDATE, _, 0, _)) {}
+        }
+        assert_eq!(conn.response().header(":status"), Some("413"));
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::NoError as u32));
+
+        // the connection window was still given back, so other streams work
+        conn.request(9, "POST", "/upload", &[], false);
+        conn.send(DATA, END_STREAM, 9, b"small");
+        assert_eq!(conn.response().body, b"small");
+    }
+
+    #[test]
+    fn header_injection() {
+        let mut conn = TestConnection::connect(&spawn_test_server(Handler), &[]);
+        conn.request(1, "GET", "/", &[("x-evil", "a\r\nx-injected: 1")], true);
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::ProtocolError as u32));
+        conn.request(3, "GET", "/", &[("x-evil\r\nx-injected", "1")], true);
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::ProtocolError as u32));
+        conn.request(5, "GET", "/ HTTP/1.1\r\nHost: a\r\n\r\nGET /second", &[], true);
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::ProtocolError as u32));
+        conn.request(7, "GET /x", "/", &[], true);
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::ProtocolError as u32));
+        conn.request(9, "GET", "/", &[("x-nul", "a\0b")], true);
+        assert_eq!(conn.error(), (RST_STREAM, ErrorCode::ProtocolError as u32));
+
+        conn.request(11, "GET", "/fine", &[], true);
+        assert_eq!(conn.response().header(":status"), Some("200"));
+    }
+
+    #[test]
+    fn http1_request() {
+        let fields = |extra: &[(&str, &str)]| -> Vec<(String, String)> {
+            [(":method", "POST"), (":path", "/a"), (":authority", "x")].iter().chain(extra)
+                .map(|(n, v)| (Str!(*n), Str!(*v))).collect()
+        };
+        let (raw, _) = to_http1(&fields(&[("x-a", "1")]), Some(b"hi")).unwrap();
+        assert_eq!(raw, b"POST /a HTTP/1.1\r\nx-a: 1\r\nhost: x\r\ncontent-length: 2\r\n\r\nhi");
+        assert_eq!(to_http1(&fields(&[("x a", "1")]), None).unwrap_err(), ErrorCode::ProtocolError);
+        assert_eq!(to_http1(&fields(&[("", "1")]), None).unwrap_err(), ErrorCode::ProtocolError);
+    }
+
+    #[test]
+    fn detects_http2() {
+        assert!(is_http2(PREFACE));
+        assert!(is_http2(b"PRI * HTTP/2.0\r\n"));
+        assert!(!is_http2(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert!(is_http2(b"GET / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABk\r\n\r\n"));
+        // a body means no upgrade
+        assert!(!is_http2(b"POST / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABk\r\n\r\nbody"));
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{self, Result as IoResult}
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+
+use super::{Request, Response, StatusCode};
+
+/*
+EXAMPLE:
+
+#[derive(Deserialize)]
+struct NewUser { name: String, age: u8 }
+
+let user: NewUser = match req.json() {
+    Ok(user) => user,
+    // 415 {"status":415,"reason":"Unsupported Media Type","detail":"Expected an application/json body"}
+    // 400 {"status":400,"reason":"Bad Request","detail":"missing field `age`","line":1,"column":16}
+    Err(e) => return e.send(res)
+};
+res.json(&json!({ "id": 7, "name": user.name }))?.send()
+*/
+
+/// Why a request body couldn't be turned into the requested type, either a 415 for a body that isn't JSON
+/// or a 400 for JSON that doesn't parse or doesn't fit the type
+#[derive(Debug, PartialEq)]
+pub struct JsonError {
+    status: StatusCode,
+    detail: String,
+    /// Line and column the parser stopped at, both starting at 1
+    position: Option<(usize, usize)>,
+}
+
+impl JsonError {
+    pub fn status(&self) -> StatusCode { self.status }
+    pub fn detail(&self) -> &str { &self.detail }
+    /// Line and column in the body where the error was found, starting at 1
+    pub fn position(&self) -> Option<(usize, usize)> { self.position }
+
+    /// The error as a JSON object in the same shape as the [ErrorPages](super::ErrorPages) JSON errors,
+    /// with `line` and `column` added when the error has a position
+    pub fn to_json(&self) -> String {
+        let mut body = json!({
+            "status": self.status.code(),
+            "reason": self.status.to_string(),
+            "detail": self.detail,
+        });
+        if let Some((line, column)) = self.position {
+            body["line"] = json!(line);
+            body["column"] = json!(column);
+        }
+        body.to_string()
+    }
+
+    /// Sends the error as the response, with [JsonError::to_json()] as the body
+    pub fn send(&self, res: &mut Response) -> IoResult<()> {
+        res.status = self.status;
+        res.headers.set("Content-Type", "application/json");
+        res.set_body(self.to_json()).send()
+    }
+}
+
+impl From<serde_json::Error> for JsonError {
+    fn from(e: serde_json::Error) -> Self {
+        // serde_json puts the position at the end of the message, it has its own fields here
+        let position = (e.line() > 0).then(|| (e.line(), e.column()));
+        let message = e.to_string();
+        let detail = match position {
+            Some((line, column)) => message.trim_end_matches(&format!(" at line {} column {}", line, column)).to_string(),
+            None => message
+        };
+        Self { status: StatusCode::BadRequest, detail, position }
+    }
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.position {
+            Some((line, column)) => write!(f, "{} at line {} column {}", self.detail, line, column),
+            None => write!(f, "{}", self.detail)
+        }
+    }
+}
+
+impl Error for JsonError {}
+
+impl From<JsonError> for io::Error {
+    fn from(e: JsonError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+impl Request<'_> {
+    /// The body as `T`. The `Content-Type` has to be `application/json` or end in `+json`,
+    /// like `application/problem+json`, parameters such as `charset` are ignored
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, JsonError> {
+        let content_type = self.headers().get("Content-Type").unwrap_or("");
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        if mime != "application/json" && !(mime.starts_with("application/") && mime.ends_with("+json")) {
+            return Err(JsonError {
+                status: StatusCode::UnsupportedMediaType,
+                detail: Str!("Expected an application/json body"),
+                position: None
+            });
+        }
+        Ok(serde_json::from_slice(self.body().unwrap_or(&[]))?)
+    }
+}
+
+impl Response {
+    /// Serializes `value` as the body and sets `Content-Type: application/json`.
+    /// Fails for values JSON can't represent, like a map with non-string keys
+    pub fn json<T: Serialize + ?Sized>(&mut self, value: &T) -> IoResult<&mut Self> {
+        let body = serde_json::to_vec(value)?;
+        self.headers.set("Content-Type", "application/json");
+        Ok(self.set_body(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use serde::Deserialize;
+    use super::super::{RequestHandler, test_client::TestClient};
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct User {
+        name: String,
+        age: u8,
+        #[serde(default)]
+        tags: Vec<String>,
+    }
+
+    struct Users;
+
+    impl RequestHandler for Users {
+        fn post(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            let mut user: User = match req.json() {
+                Ok(user) => user,
+                Err(e) => return e.send(res)
+            };
+            user.tags.push(Str!("created"));
+            res.status = StatusCode::Created;
+            res.json(&user)?.send()
+        }
+    }
+
+    fn post(content_type: &str, body: &str) -> (StatusCode, Option<String>, serde_json::Value) {
+        let client = TestClient::new(Users);
+        let res = client.request(super::super::Method::POST, "/users")
+            .header("Content-Type", content_type)
+            .body(body)
+            .send()
+            .unwrap();
+        let json = serde_json::from_slice(&res.body).unwrap_or(serde_json::Value::Null);
+        (res.status, res.headers.get("Content-Type").map(String::from), json)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (status, content_type, body) = post("application/json; charset=utf-8", r#"{"name":"Jo \"JJ\"","age":30}"#);
+        assert_eq!(status, StatusCode::Created);
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        assert_eq!(body, json!({"name": "Jo \"JJ\"", "age": 30, "tags": ["created"]}));
+
+        let (status, _, _) = post("Application/Vnd.Api+JSON", r#"{"name":"Jo","age":30}"#);
+        assert_eq!(status, StatusCode::Created);
+    }
+
+    #[test]
+    fn structured_errors() {
+        let (status, content_type, body) = post("text/plain", r#"{"name":"Jo","age":30}"#);
+        assert_eq!(status, StatusCode::UnsupportedMediaType);
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        assert_eq!(body, json!({"status": 415, "reason": "Unsupported Media Type", "detail": "Expected an application/json body"}));
+
+        let (status, _, body) = post("application/json", "{\"name\":\"Jo\",\n\"age\":300}");
+        assert_eq!(status, StatusCode::BadRequest);
+        assert_eq!(body["line"], 2);
+        assert!(body["detail"].as_str().unwrap().starts_with("invalid value: integer `300`"), "{}", body);
+
+        let (_, _, body) = post("application/json", r#"{"name":"Jo"}"#);
+        assert_eq!(body["detail"], "missing field `age`");
+        assert_eq!((&body["line"], &body["column"]), (&json!(1), &json!(13)));
+
+        let (_, _, body) = post("application/json", r#"{"name": ?}"#);
+        assert_eq!(body["detail"], "expected value");
+
+        let (status, _, body) = post("application/json", r#"{"name":"Jo""#);
+        assert_eq!(status, StatusCode::BadRequest);
+        assert_eq!(body["detail"], "EOF while parsing an object");
+    }
+
+    #[test]
+    fn unserializable() {
+        let mut res = Response::new(std::rc::Rc::new(std::cell::RefCell::new(Vec::new())));
+        let map = HashMap::from([((1, 2), "tuple keys")]);
+        assert!(res.json(&map).is_err());
+        assert!(res.body.is_none());
+
+        let err = io::Error::from(serde_json::from_str::<User>("7").map_err(JsonError::from).unwrap_err());
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "invalid type: integer `7`, expected struct User at line 1 column 1");
+    }
+}
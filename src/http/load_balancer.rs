@@ -0,0 +1,422 @@
+use std::{
+    io::Result as IoResult,
+    sync::{
+        Arc, Weak, Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering}
+    },
+    thread,
+    time::{Duration, Instant}
+};
+
+use super::{
+    Request,
+    Response,
+    RequestHandler,
+    StatusCode,
+    client::Client,
+    proxy::{ProxyHandler, ProxyError}
+};
+
+/// How a [LoadBalancer] picks the upstream for each request
+#[derive(Clone, Debug)]
+pub enum Strategy {
+    /// Each upstream in turn
+    RoundRobin,
+    /// The upstream with the fewest requests in flight
+    LeastConnections,
+    /// The same key always goes to the same upstream while it is available, requests without the key use round robin
+    ConsistentHash(HashKey),
+}
+
+/// What a [Strategy::ConsistentHash] hashes
+#[derive(Clone, Debug)]
+pub enum HashKey {
+    ClientIp,
+    /// The value of this request header, ex a session or tenant id
+    Header(String),
+}
+
+/// Settings for actively checking the upstreams in the background
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    /// Path requested on each upstream, any 2xx or 3xx answer is healthy
+    pub path: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Failed checks in a row before an upstream is taken out of rotation
+    pub unhealthy_after: u32,
+    /// Passed checks in a row before it is put back
+    pub healthy_after: u32,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            path: Str!("/health"),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            unhealthy_after: 2,
+            healthy_after: 2
+        }
+    }
+}
+
+struct Upstream {
+    proxy: ProxyHandler,
+    /// Set by the active health checks
+    healthy: AtomicBool,
+    /// Requests currently being proxied to this upstream
+    active: AtomicUsize,
+    /// Failed requests in a row, for passive ejection
+    errors: AtomicU32,
+    ejected_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn available(&self) -> bool {
+        if !self.healthy.load(Ordering::Relaxed) {
+            return false;
+        }
+        match *self.ejected_until.lock().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true
+        }
+    }
+}
+
+/// Passive ejection settings, see [LoadBalancer::set_passive_ejection()]
+#[derive(Clone, Copy, Debug)]
+struct Ejection {
+    max_errors: u32,
+    duration: Duration,
+}
+
+/// Proxies requests to a pool of upstream servers, see [Strategy] for how one is picked.
+/// Upstreams are taken out of rotation when they fail health checks or fail too many requests in a row
+pub struct LoadBalancer {
+    upstreams: Arc<Vec<Upstream>>,
+    strategy: Strategy,
+    next: AtomicUsize,
+    ejection: Ejection,
+    /// Points on the hash ring and the upstream each belongs to, sorted by point
+    ring: Vec<(u64, usize)>,
+}
+
+/// How many points each upstream gets on the hash ring, more spreads keys more evenly
+const VIRTUAL_NODES: usize = 100;
+
+impl LoadBalancer {
+    /// `upstreams` are `host:port` addresses
+    pub fn new(upstreams: &[&str], strategy: Strategy) -> Self {
+        let upstreams: Vec<Upstream> = upstreams.iter().map(|addr| Upstream {
+            proxy: ProxyHandler::new(addr),
+            healthy: AtomicBool::new(true),
+            active: AtomicUsize::new(0),
+            errors: AtomicU32::new(0),
+            ejected_until: Mutex::new(None)
+        }).collect();
+
+        let mut ring: Vec<(u64, usize)> = upstreams.iter().enumerate()
+            .flat_map(|(i, upstream)| (0..VIRTUAL_NODES).map(move |v| (fnv1a(format!("{}#{}", upstream.proxy.upstream(), v).as_bytes()), i)))
+            .collect();
+        ring.sort_unstable();
+
+        Self {
+            upstreams: Arc::new(upstreams),
+            strategy,
+            next: AtomicUsize::new(0),
+            ejection: Ejection { max_errors: 5, duration: Duration::from_secs(30) },
+            ring
+        }
+    }
+
+    /// Take an upstream out of rotation for `duration` after `max_errors` failed requests in a row.
+    /// Connection errors, timeouts and 5xx responses count as failures
+    pub fn set_passive_ejection(&mut self, max_errors: u32, duration: Duration) -> &mut Self {
+        self.ejection = Ejection { max_errors, duration };
+        self
+    }
+
+    /// Sets the timeout of every upstream, see [ProxyHandler::set_timeout()]
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        // the health check thread shares the upstreams, so they are changed in place
+        for upstream in self.upstreams.iter() {
+            upstream.proxy.store_timeout(timeout);
+        }
+        self
+    }
+
+    /// Starts a thread that checks every upstream each [HealthCheck::interval].
+    /// It stops on its own once the load balancer is dropped
+    pub fn start_health_checks(&self, check: HealthCheck) {
+        let upstreams = Arc::downgrade(&self.upstreams);
+        thread::spawn(move || Self::health_check_loop(upstreams, check));
+    }
+
+    fn health_check_loop(upstreams: Weak<Vec<Upstream>>, check: HealthCheck) {
+        let mut client = Client::new();
+        client.set_timeout(Some(check.timeout)).set_keep_alive(false);
+        let mut streaks: Vec<(u32, u32)> = Vec::new();
+
+        while let Some(upstreams) = upstreams.upgrade() {
+            streaks.resize(upstreams.len(), (0, 0));
+            for (upstream, (passed, failed)) in upstreams.iter().zip(streaks.iter_mut()) {
+                let url = format!("http://{}{}", upstream.proxy.upstream(), check.path);
+                let ok = client.get(&url).is_ok_and(|res| (200..400).contains(&res.code));
+
+                if ok {
+                    *passed += 1;
+                    *failed = 0;
+                    if *passed >= check.healthy_after {
+                        upstream.healthy.store(true, Ordering::Relaxed);
+                    }
+                } else {
+                    *failed += 1;
+                    *passed = 0;
+                    if *failed >= check.unhealthy_after && upstream.healthy.swap(false, Ordering::Relaxed) {
+                        eprintln!("Upstream {} failed its health check, taking it out of rotation", upstream.proxy.upstream());
+                    }
+                }
+            }
+            // don't keep the upstreams alive while sleeping
+            drop(upstreams);
+            thread::sleep(check.interval);
+        }
+    }
+
+    /// Addresses of the upstreams currently in rotation
+    pub fn available_upstreams(&self) -> Vec<String> {
+        self.upstreams.iter()
+            .filter(|upstream| upstream.available())
+            .map(|upstream| Str!(upstream.proxy.upstream()))
+            .collect()
+    }
+
+    /// Picks the upstream for `req`, or [None] if none are available
+    fn pick(&self, req: &Request) -> Option<usize> {
+        let count = self.upstreams.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let round_robin = (0..count).map(|i| (start + i) % count).find(|&i| self.upstreams[i].available());
+
+        match &self.strategy {
+            Strategy::RoundRobin => round_robin,
+            Strategy::LeastConnections => (0..count)
+                .map(|i| (start + i) % count)
+                .filter(|&i| self.upstreams[i].available())
+                .min_by_key(|&i| self.upstreams[i].active.load(Ordering::Relaxed)),
+            Strategy::ConsistentHash(key) => {
+                let key = match key {
+                    HashKey::ClientIp => req.remote_addr().map(|addr| addr.ip().to_string()),
+                    HashKey::Header(name) => req.headers().get(name).map(String::from)
+                };
+                match key {
+                    Some(key) => self.ring_lookup(fnv1a(key.as_bytes())),
+                    None => round_robin
+                }
+            }
+        }
+    }
+
+    /// Walks the ring clockwise from `hash` to the first available upstream
+    fn ring_lookup(&self, hash: u64) -> Option<usize> {
+        let start = self.ring.partition_point(|(point, _)| *point < hash);
+        (0..self.ring.len())
+            .map(|i| self.ring[(start + i) % self.ring.len()].1)
+            .find(|&i| self.upstreams[i].available())
+    }
+
+    fn record(&self, upstream: &Upstream, failed: bool) {
+        if !failed {
+            upstream.errors.store(0, Ordering::Relaxed);
+            return;
+        }
+        let errors = upstream.errors.fetch_add(1, Ordering::Relaxed) + 1;
+        if errors >= self.ejection.max_errors {
+            eprintln!("Upstream {} failed {} requests in a row, ejecting it", upstream.proxy.upstream(), errors);
+            *upstream.ejected_until.lock().unwrap() = Some(Instant::now() + self.ejection.duration);
+            upstream.errors.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl RequestHandler for LoadBalancer {
+    fn handle(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+        let upstream = match self.pick(req) {
+            Some(i) => &self.upstreams[i],
+            None => return res.send_error(StatusCode::ServiceUnavailable, "No upstream servers are available.")
+        };
+
+        upstream.active.fetch_add(1, Ordering::Relaxed);
+        let result = upstream.proxy.forward(req, res);
+        upstream.active.fetch_sub(1, Ordering::Relaxed);
+
+        match result {
+            Ok(()) => {
+                self.record(upstream, res.status.code() >= 500);
+                Ok(())
+            },
+            Err(ProxyError::Upstream(e)) => {
+                self.record(upstream, true);
+                upstream.proxy.send_upstream_error(res, &e)
+            },
+            Err(ProxyError::Downstream(e)) => Err(e)
+        }
+    }
+}
+
+/// 64 bit FNV-1a, stable across runs unlike the std hasher so keys map to the same upstream after a restart.
+/// FNV alone barely changes the high bits for keys that only differ at the end (like `host:port#1` and `host:port#2`),
+/// so the result goes through the murmur3 finalizer to spread them around the ring
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3));
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, net::TcpListener};
+    use crate::http::{Method, Server, client::Client, test_client::spawn_test_server};
+
+    struct Named(String);
+
+    impl RequestHandler for Named {
+        fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            match req.path() {
+                "/health" if self.0.ends_with("sick") => res.send_error(StatusCode::ServiceUnavailable, "sick"),
+                "/fail" => res.send_error(StatusCode::InternalServerError, "failed"),
+                _ => res.ok(Some(self.0.clone()))
+            }
+        }
+    }
+
+    fn start_named(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| spawn_test_server(Named(Str!(*name)))).collect()
+    }
+
+    fn get(url: &str, header: Option<&str>) -> String {
+        let client = Client::new();
+        let mut req = client.request(Method::GET, url);
+        if let Some(value) = header {
+            req = req.header("X-Tenant", value);
+        }
+        req.send().expect("request failed").text()
+    }
+
+    #[test]
+    fn round_robin() {
+        let upstreams = start_named(&["a", "b", "c"]);
+        let addrs: Vec<&str> = upstreams.iter().map(String::as_str).collect();
+        let lb = spawn_test_server(LoadBalancer::new(&addrs, Strategy::RoundRobin));
+
+        let mut seen: Vec<String> = (0..6).map(|_| get(&format!("http://{}/", lb), None)).collect();
+        assert_eq!(seen[..3], seen[3..]);
+        seen.sort();
+        assert_eq!(seen, ["a", "a", "b", "b", "c", "c"]);
+    }
+
+    #[test]
+    fn least_connections() {
+        let lb = LoadBalancer::new(&["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"], Strategy::LeastConnections);
+        lb.upstreams[0].active.store(2, Ordering::Relaxed);
+        lb.upstreams[1].active.store(1, Ordering::Relaxed);
+        lb.upstreams[2].active.store(3, Ordering::Relaxed);
+
+        let req = Request::try_from(&b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap();
+        for _ in 0..3 {
+            assert_eq!(lb.pick(&req), Some(1));
+        }
+        lb.upstreams[1].healthy.store(false, Ordering::Relaxed);
+        assert_eq!(lb.pick(&req), Some(0));
+    }
+
+    #[test]
+    fn consistent_hash() {
+        let upstreams = start_named(&["a", "b", "c", "d"]);
+        let addrs: Vec<&str> = upstreams.iter().map(String::as_str).collect();
+        let lb = spawn_test_server(LoadBalancer::new(&addrs, Strategy::ConsistentHash(HashKey::Header(Str!("X-Tenant")))));
+        let url = format!("http://{}/", lb);
+
+        let mut picked = HashSet::new();
+        for tenant in ["alpha", "beta", "gamma", "delta", "epsilon", "zeta"] {
+            let first = get(&url, Some(tenant));
+            for _ in 0..3 {
+                assert_eq!(get(&url, Some(tenant)), first);
+            }
+            picked.insert(first);
+        }
+        assert!(picked.len() > 1);
+
+        let by_ip = spawn_test_server(LoadBalancer::new(&addrs, Strategy::ConsistentHash(HashKey::ClientIp)));
+        let first = get(&format!("http://{}/", by_ip), None);
+        assert_eq!(get(&format!("http://{}/", by_ip), None), first);
+    }
+
+    #[test]
+    fn passive_ejection() {
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let alive = spawn_test_server(Named(Str!("alive")));
+
+        let mut lb = LoadBalancer::new(&[&dead, &alive], Strategy::RoundRobin);
+        lb.set_passive_ejection(2, Duration::from_secs(60));
+        let lb = Arc::new(lb);
+
+        let mut server = Server::new(Str!("127.0.0.1"), 0);
+        let addr = server.addr();
+        let handler = lb.clone();
+        thread::spawn(move || server.run(handler));
+
+        let results: Vec<String> = (0..4).map(|_| get(&format!("http://{}/", addr), None)).collect();
+        assert_eq!(results.iter().filter(|r| r.as_str() != "alive").count(), 2);
+        assert_eq!(lb.available_upstreams(), vec![alive.clone()]);
+        for _ in 0..3 {
+            assert_eq!(get(&format!("http://{}/", addr), None), "alive");
+        }
+    }
+
+    #[test]
+    fn health_checks() {
+        let upstreams = start_named(&["well", "sick"]);
+        let addrs: Vec<&str> = upstreams.iter().map(String::as_str).collect();
+        let lb = LoadBalancer::new(&addrs, Strategy::RoundRobin);
+        lb.start_health_checks(HealthCheck {
+            interval: Duration::from_millis(20),
+            timeout: Duration::from_millis(500),
+            unhealthy_after: 1,
+            healthy_after: 1,
+            ..Default::default()
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while lb.available_upstreams().len() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(lb.available_upstreams(), vec![upstreams[0].clone()]);
+    }
+
+    #[test]
+    fn timeout_after_health_checks() {
+        // accepts connections but never answers
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut lb = LoadBalancer::new(&[&silent.local_addr().unwrap().to_string()], Strategy::RoundRobin);
+        lb.start_health_checks(HealthCheck::default());
+        lb.set_timeout(Duration::from_millis(100));
+
+        let started = Instant::now();
+        let res = crate::http::test_client::TestClient::new(lb).get("/").expect("request failed");
+        assert_eq!(res.status, StatusCode::GatewayTimeout);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn no_upstreams() {
+        let lb = LoadBalancer::new(&["127.0.0.1:1"], Strategy::RoundRobin);
+        lb.upstreams[0].healthy.store(false, Ordering::Relaxed);
+        let res = crate::http::test_client::TestClient::new(lb).get("/").expect("request failed");
+        assert_eq!(res.status, StatusCode::ServiceUnavailable);
+    }
+}
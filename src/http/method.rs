@@ -4,8 +4,9 @@ use std::str::FromStr;
 
 use super::ParseError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[derive(PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Method {
     GET,
     DELETE,
@@ -18,6 +19,13 @@ pub enum Method {
     PATCH 
 }
 
+impl Method {
+    /// Whether sending the request twice has the same effect as sending it once, so it can be retried
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(self, Self::POST | Self::PATCH | Self::CONNECT)
+    }
+}
+
 impl Display for Method {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         let method_str = match self {
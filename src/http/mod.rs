@@ -6,6 +6,33 @@ pub mod response;
 pub mod status_code;
 pub mod parse_error;
 pub mod request_handler;
+pub mod headers;
+pub mod compression;
+pub mod error_pages;
+pub mod date;
+pub mod router;
+pub mod static_files;
+pub mod cache_rules;
+pub mod test_client;
+pub mod parsed_response;
+pub mod client;
+pub mod proxy;
+pub mod load_balancer;
+pub mod websocket;
+pub mod event_stream;
+pub mod chunked_writer;
+pub mod hpack;
+pub mod http2;
+pub mod multipart;
+pub mod cookie;
+#[cfg(feature = "async")]
+pub mod async_server;
+#[cfg(feature = "serde")]
+pub mod typed_query;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "secure-cookies")]
+pub mod cookie_jar;
 
 pub use request::Request;
 pub use parse_error::ParseError;
@@ -15,3 +42,8 @@ pub use query_string::QueryString;
 pub use response::Response;
 pub use status_code::StatusCode;
 pub use request_handler::RequestHandler;
+pub use headers::Headers;
+pub use compression::Compression;
+pub use error_pages::ErrorPages;
+pub use router::Router;
+pub use static_files::StaticFiles;
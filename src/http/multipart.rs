@@ -0,0 +1,507 @@
+use std::fs::{self, File};
+use std::io::{self, Read, ErrorKind, Result as IoResult};
+use std::path::Path;
+use std::str;
+use super::{Headers, ParseError};
+use super::request::parse_headers;
+/*
+EXAMPLE MULTIPART BODY (Content-Type: multipart/form-data; boundary=XyZ):
+
+--XyZ\r\n
+Content-Disposition: form-data; name="title"\r\n
+\r\n
+Holiday\r\n
+--XyZ\r\n
+Content-Disposition: form-data; name="photo"; filename="beach.jpg"\r\n
+Content-Type: image/jpeg\r\n
+\r\n
+BINARY DATA\r\n
+--XyZ--\r\n
+*/
+
+const CHUNK_SIZE: usize = 8 * 1024;
+/// Largest header section a single part may have
+const MAX_PART_HEAD: usize = 8 * 1024;
+const MAX_BOUNDARY: usize = 70;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// Anything before the first boundary, thrown away
+    Preamble,
+    Content,
+    /// Just past a boundary, either part headers or the closing `--` follow
+    Delimiter,
+    Done,
+}
+
+/// Streaming `multipart/form-data` parser, parts are read one at a time from the reader without copying the whole body.
+/// From [Request::multipart()](super::Request::multipart) the reader is the request body, which the server has already
+/// read into memory, so uploads are limited by the body size limit there. Errors are `io::Error`s wrapping a [ParseError]
+/// with kind `InvalidData`
+///
+/// A part larger than [set_max_part_size()](Self::set_max_part_size) fails with [ParseError::PartTooLarge] but
+/// can still be skipped with [next_part()](Self::next_part), once more than
+/// [set_max_total_size()](Self::set_max_total_size) bytes are read everything fails with
+/// [ParseError::MultipartTooLarge]. Any other error ends the parse
+pub struct Multipart<R> {
+    reader: R,
+    /// `\r\n--boundary`, the buffer starts with a `\r\n` so the opening boundary matches as well
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    start: usize,
+    state: State,
+    max_part_size: usize,
+    max_total_size: usize,
+    part_size: usize,
+    total_size: usize,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Fails with [ParseError::InvalidBoundary] unless the boundary is 1 to 70 printable characters
+    pub fn new(reader: R, boundary: &str) -> Result<Self, ParseError> {
+        let valid = (1..=MAX_BOUNDARY).contains(&boundary.len())
+            && boundary.bytes().all(|b| b.is_ascii_graphic() || b == b' ')
+            && !boundary.ends_with(' ');
+        if !valid {
+            return Err(ParseError::InvalidBoundary);
+        }
+
+        Ok(Multipart {
+            reader,
+            delimiter: [b"\r\n--", boundary.as_bytes()].concat(),
+            buffer: b"\r\n".to_vec(),
+            start: 0,
+            state: State::Preamble,
+            max_part_size: usize::MAX,
+            max_total_size: usize::MAX,
+            part_size: 0,
+            total_size: 0,
+        })
+    }
+
+    /// Most content bytes a single part may have, unlimited by default
+    pub fn set_max_part_size(&mut self, max: usize) -> &mut Self {
+        self.max_part_size = max;
+        self
+    }
+
+    /// Most bytes that will be read from the reader, boundaries and part headers included, unlimited by default
+    pub fn set_max_total_size(&mut self, max: usize) -> &mut Self {
+        self.max_total_size = max;
+        self
+    }
+
+    /// The next part, skipping whatever is left of the current one. None after the closing boundary
+    pub fn next_part(&mut self) -> IoResult<Option<Part<'_, R>>> {
+        while matches!(self.state, State::Preamble | State::Content) {
+            self.next_chunk(usize::MAX, false)?;
+        }
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        let head_end = loop {
+            let data = &self.buffer[self.start..];
+            if data.starts_with(b"--") {
+                // the closing boundary, anything after it is an epilogue to ignore
+                self.state = State::Done;
+                return Ok(None);
+            }
+            if let Some(i) = find(data, b"\r\n\r\n") {
+                if i > MAX_PART_HEAD {
+                    return Err(self.fail(ParseError::InvalidPartHeader));
+                }
+                break self.start + i + 4;
+            }
+            if data.len() > MAX_PART_HEAD {
+                return Err(self.fail(ParseError::InvalidPartHeader));
+            }
+            if !self.fill()? {
+                return Err(self.fail(ParseError::IncompleteMultipart));
+            }
+        };
+
+        let (headers, name, filename) = match parse_head(&self.buffer[self.start..head_end]) {
+            Ok(head) => head,
+            Err(e) => return Err(self.fail(e)),
+        };
+        self.start = head_end;
+        self.state = State::Content;
+        self.part_size = 0;
+        Ok(Some(Part { multipart: self, headers, name, filename }))
+    }
+
+    /// Up to `max` bytes of the current part or preamble, empty once its boundary has been reached
+    fn next_chunk(&mut self, max: usize, limited: bool) -> IoResult<&[u8]> {
+        if !matches!(self.state, State::Preamble | State::Content) {
+            return Ok(&[]);
+        }
+
+        let length = loop {
+            let data = &self.buffer[self.start..];
+            match find(data, &self.delimiter) {
+                Some(0) => {
+                    self.start += self.delimiter.len();
+                    self.state = State::Delimiter;
+                    return Ok(&[]);
+                }
+                Some(i) => break i.min(max),
+                None => {
+                    // the end of the buffer could be the start of a boundary, hold it back until more is read
+                    let safe = data.len().saturating_sub(self.delimiter.len() - 1);
+                    if safe > 0 {
+                        break safe.min(max);
+                    }
+                    if !self.fill()? {
+                        return Err(self.fail(match self.state {
+                            State::Preamble => ParseError::InvalidMultipart,
+                            _ => ParseError::IncompleteMultipart,
+                        }));
+                    }
+                }
+            }
+        };
+
+        if limited && self.state == State::Content {
+            if self.part_size + length > self.max_part_size {
+                return Err(ParseError::PartTooLarge.into());
+            }
+            self.part_size += length;
+        }
+        let start = self.start;
+        self.start += length;
+        Ok(&self.buffer[start..start + length])
+    }
+
+    /// Reads more of the body into the buffer, false at the end of it
+    fn fill(&mut self) -> IoResult<bool> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+
+        let length = self.buffer.len();
+        self.buffer.resize(length + CHUNK_SIZE, 0);
+        let read = loop {
+            match self.reader.read(&mut self.buffer[length..]) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buffer.truncate(length);
+                    return Err(e);
+                }
+            }
+        };
+        self.buffer.truncate(length + read);
+
+        self.total_size += read;
+        if self.total_size > self.max_total_size {
+            return Err(self.fail(ParseError::MultipartTooLarge));
+        }
+        Ok(read > 0)
+    }
+
+    fn fail(&mut self, e: ParseError) -> io::Error {
+        self.state = State::Done;
+        e.into()
+    }
+}
+
+/// One field or file of a multipart body, reading it gives the content
+pub struct Part<'m, R> {
+    multipart: &'m mut Multipart<R>,
+    headers: Headers,
+    name: String,
+    filename: Option<String>,
+}
+
+impl<R: Read> Part<'_, R> {
+    pub fn headers(&self) -> &Headers { &self.headers }
+    /// The form field this part is for
+    pub fn name(&self) -> &str { &self.name }
+    /// The name of the uploaded file as the client sent it, which may include a path and shouldn't be trusted
+    pub fn filename(&self) -> Option<&str> { self.filename.as_deref() }
+    pub fn content_type(&self) -> Option<&str> { self.headers.get("Content-Type") }
+
+    /// The rest of the content
+    pub fn contents(&mut self) -> IoResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// The rest of the content, which has to be UTF-8
+    pub fn text(&mut self) -> IoResult<String> {
+        String::from_utf8(self.contents()?).map_err(|e| ParseError::from(e.utf8_error()).into())
+    }
+
+    /// Copies the rest of the content into a new file at `path` a chunk at a time, returning how many bytes were written.
+    /// The file is removed again if the part can't be read in full. A part from a request body is already in memory,
+    /// this saves it without another copy but can't take uploads past the body size limit
+    pub fn save_to(&mut self, path: impl AsRef<Path>) -> IoResult<u64> {
+        let path = path.as_ref();
+        let mut file = File::create(path)?;
+        match io::copy(self, &mut file) {
+            Ok(written) => Ok(written),
+            Err(e) => {
+                drop(file);
+                let _ = fs::remove_file(path);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for Part<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let chunk = self.multipart.next_chunk(buf.len(), true)?;
+        buf[..chunk.len()].copy_from_slice(chunk);
+        Ok(chunk.len())
+    }
+}
+
+/// The boundary of a `multipart/form-data` Content-Type, None for any other type
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (mime, parameters) = parse_parameters(content_type);
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parameters.into_iter().find(|(name, _)| name == "boundary").map(|(_, value)| value)
+}
+
+/// Parses what follows a boundary, `\r\n` and the part headers, into the headers, field name and filename
+fn parse_head(head: &[u8]) -> Result<(Headers, String, Option<String>), ParseError> {
+    // whitespace is allowed between the boundary and its line break
+    let padding = head.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+    let head = head[padding..].strip_prefix(b"\r\n").ok_or(ParseError::InvalidMultipart)?;
+    let head = str::from_utf8(head).map_err(|_| ParseError::InvalidPartHeader)?;
+    let (headers, _) = parse_headers(head).map_err(|_| ParseError::InvalidPartHeader)?;
+
+    let disposition = headers.get("Content-Disposition").ok_or(ParseError::InvalidPartHeader)?;
+    let (kind, parameters) = parse_parameters(disposition);
+    if !kind.eq_ignore_ascii_case("form-data") {
+        return Err(ParseError::InvalidPartHeader);
+    }
+    let parameter = |name: &str| parameters.iter().find(|(n, _)| n == name).map(|(_, value)| value.clone());
+    let name = parameter("name").ok_or(ParseError::InvalidPartHeader)?;
+    let filename = parameter("filename");
+    Ok((headers, name, filename))
+}
+
+/// Splits `type; name=value; name="quoted value"` into the type and its parameters, names lowercased
+fn parse_parameters(value: &str) -> (&str, Vec<(String, String)>) {
+    let (kind, mut rest) = value.split_once(';').unwrap_or((value, ""));
+    let mut parameters = Vec::new();
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
+        let (name, after) = match rest.find(['=', ';']) {
+            Some(i) if rest[i..].starts_with('=') => (&rest[..i], rest[i+1..].trim_start()),
+            // a parameter without a value
+            Some(i) => { rest = &rest[i..]; continue; }
+            None => break,
+        };
+
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => unquote(quoted),
+            None => {
+                let end = after.find(';').unwrap_or(after.len());
+                (after[..end].trim_end().to_string(), &after[end..])
+            }
+        };
+        parameters.push((name.trim().to_ascii_lowercase(), value));
+        rest = after;
+    }
+    (kind.trim(), parameters)
+}
+
+/// The value of a quoted string whose opening quote was already removed, and whatever follows the closing quote
+fn unquote(input: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, &input[i+1..]),
+            // only escaped quotes and backslashes, so a Windows path like C:\photos keeps its backslashes
+            '\\' if matches!(chars.peek(), Some((_, '"' | '\\'))) => value.push(chars.next().unwrap().1),
+            _ => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+    use super::super::Request;
+
+    /// Hands out the body a few bytes at a time so boundaries land across reads
+    struct Trickle<'a>(&'a [u8], usize);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    fn parse_error(e: io::Error) -> ParseError {
+        e.get_ref().and_then(|inner| inner.downcast_ref::<ParseError>()).cloned().expect("not a ParseError")
+    }
+
+    fn upload() -> Vec<u8> {
+        let mut body = b"preamble to ignore\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            Holiday\r\n--XyZ  \r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"C:\\\\beach \\\"1\\\".jpg\"\r\n\
+            Content-Type: image/jpeg\r\n\r\n".to_vec();
+        // binary with a near miss of the boundary in it
+        body.extend_from_slice(&[0xff, 0x00, b'\r', b'\n', b'-', b'-', b'X', b'y', 0xfe, b'\r']);
+        body.extend_from_slice(b"\r\n--XyZ\r\nContent-Disposition: form-data; name=empty\r\n\r\n\r\n--XyZ--\r\nepilogue");
+        body
+    }
+
+    #[test]
+    fn parts() {
+        for size in [1, 3, 7, 4096] {
+            let body = upload();
+            let mut multipart = Multipart::new(Trickle(&body, size), "XyZ").unwrap();
+
+            let mut part = multipart.next_part().unwrap().expect("title");
+            assert_eq!(part.name(), "title");
+            assert_eq!(part.filename(), None);
+            assert_eq!(part.text().unwrap(), "Holiday");
+
+            let mut part = multipart.next_part().unwrap().expect("photo");
+            assert_eq!(part.name(), "photo");
+            assert_eq!(part.filename(), Some("C:\\beach \"1\".jpg"));
+            assert_eq!(part.content_type(), Some("image/jpeg"));
+            assert_eq!(part.contents().unwrap(), [0xff, 0x00, b'\r', b'\n', b'-', b'-', b'X', b'y', 0xfe, b'\r']);
+
+            let mut part = multipart.next_part().unwrap().expect("empty");
+            assert_eq!(part.name(), "empty");
+            assert!(part.contents().unwrap().is_empty());
+
+            assert!(multipart.next_part().unwrap().is_none());
+            assert!(multipart.next_part().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn skips_unread_parts() {
+        let body = upload();
+        let mut multipart = Multipart::new(&body[..], "XyZ").unwrap();
+        let mut names = Vec::new();
+        while let Some(mut part) = multipart.next_part().unwrap() {
+            names.push(part.name().to_string());
+            // read a little of each part and leave the rest
+            part.read_exact(&mut [0; 1]).ok();
+        }
+        assert_eq!(names, ["title", "photo", "empty"]);
+    }
+
+    #[test]
+    fn save_to() {
+        let body = upload();
+        let mut multipart = Multipart::new(Trickle(&body, 5), "XyZ").unwrap();
+        multipart.next_part().unwrap();
+        let path = std::env::temp_dir().join(format!("multipart_save_{}", std::process::id()));
+
+        let written = multipart.next_part().unwrap().unwrap().save_to(&path).unwrap();
+        assert_eq!(written, 10);
+        assert_eq!(fs::read(&path).unwrap()[..2], [0xff, 0x00]);
+        fs::remove_file(&path).unwrap();
+
+        let mut multipart = Multipart::new(&body[..], "XyZ").unwrap();
+        multipart.set_max_part_size(4);
+        multipart.next_part().unwrap();
+        let e = multipart.next_part().unwrap().unwrap().save_to(&path).unwrap_err();
+        assert_eq!(parse_error(e), ParseError::PartTooLarge);
+        assert!(!path.exists(), "partial file should be removed");
+    }
+
+    #[test]
+    fn limits() {
+        let body = upload();
+        let mut multipart = Multipart::new(&body[..], "XyZ").unwrap();
+        multipart.set_max_part_size(7);
+        assert_eq!(multipart.next_part().unwrap().unwrap().text().unwrap(), "Holiday");
+        let e = multipart.next_part().unwrap().unwrap().contents().unwrap_err();
+        assert_eq!(parse_error(e), ParseError::PartTooLarge);
+        // the oversized part can be skipped
+        assert_eq!(multipart.next_part().unwrap().unwrap().name(), "empty");
+
+        let mut multipart = Multipart::new(Trickle(&body, 16), "XyZ").unwrap();
+        multipart.set_max_total_size(100);
+        let e = loop {
+            match multipart.next_part() {
+                Ok(Some(mut part)) => if let Err(e) = part.contents() { break e },
+                Ok(None) => panic!("limit not enforced"),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(parse_error(e), ParseError::MultipartTooLarge);
+        assert!(multipart.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed() {
+        let first_error = |body: &[u8]| {
+            let mut multipart = Multipart::new(body, "XyZ").unwrap();
+            loop {
+                match multipart.next_part() {
+                    Ok(Some(mut part)) => if let Err(e) = part.contents() { return parse_error(e) },
+                    Ok(None) => panic!("no error for {:?}", String::from_utf8_lossy(body)),
+                    Err(e) => return parse_error(e),
+                }
+            }
+        };
+        let disposition = "Content-Disposition: form-data; name=a\r\n\r\n";
+
+        assert_eq!(first_error(b"no boundary at all"), ParseError::InvalidMultipart);
+        assert_eq!(first_error(format!("--XyZjunk\r\n{disposition}x\r\n--XyZ--").as_bytes()), ParseError::InvalidMultipart);
+        assert_eq!(first_error(format!("--XyZ\r\n{disposition}cut off").as_bytes()), ParseError::IncompleteMultipart);
+        assert_eq!(first_error(b"--XyZ\r\nContent-Disposition: form-data; na"), ParseError::IncompleteMultipart);
+        assert_eq!(first_error(b"--XyZ\r\nContent-Type: text/plain\r\n\r\nx\r\n--XyZ--"), ParseError::InvalidPartHeader);
+        assert_eq!(first_error(b"--XyZ\r\nContent-Disposition: attachment; name=a\r\n\r\nx\r\n--XyZ--"), ParseError::InvalidPartHeader);
+        assert_eq!(first_error(b"--XyZ\r\nNo colon\r\n\r\nx\r\n--XyZ--"), ParseError::InvalidPartHeader);
+        let long = format!("--XyZ\r\nContent-Disposition: form-data; name=a\r\nX-Long: {}\r\n\r\nx\r\n--XyZ--", "a".repeat(MAX_PART_HEAD));
+        assert_eq!(first_error(long.as_bytes()), ParseError::InvalidPartHeader);
+
+        assert_eq!(Multipart::new(&b""[..], "").err(), Some(ParseError::InvalidBoundary));
+        assert_eq!(Multipart::new(&b""[..], &"a".repeat(71)).err(), Some(ParseError::InvalidBoundary));
+    }
+
+    #[test]
+    fn from_request() {
+        let raw = b"POST /upload HTTP/1.1\r\nContent-Type: Multipart/Form-Data; boundary=\"a b\"\r\n\r\n\
+            --a b\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n--a b--\r\n";
+        let req = Request::try_from(&raw[..]).unwrap();
+        let mut multipart = req.multipart().unwrap();
+        assert_eq!(multipart.next_part().unwrap().unwrap().text().unwrap(), "hi");
+        assert!(multipart.next_part().unwrap().is_none());
+
+        let req = Request::try_from(&b"POST / HTTP/1.1\r\nContent-Type: multipart/form-data\r\n\r\n"[..]).unwrap();
+        assert_eq!(req.multipart().err(), Some(ParseError::InvalidBoundary));
+        let req = Request::try_from(&b"POST / HTTP/1.1\r\nContent-Type: text/plain; boundary=x\r\n\r\n"[..]).unwrap();
+        assert_eq!(req.multipart().err(), Some(ParseError::InvalidBoundary));
+    }
+
+    #[test]
+    fn parameters() {
+        let (kind, parameters) = parse_parameters("form-data; Name=\"a;b\"; flag; filename=plain.txt ;x=\"unterminated");
+        assert_eq!(kind, "form-data");
+        assert_eq!(parameters, [
+            (String::from("name"), String::from("a;b")),
+            (String::from("filename"), String::from("plain.txt")),
+            (String::from("x"), String::from("unterminated")),
+        ]);
+        assert_eq!(boundary("multipart/form-data;boundary=----WebKitFormBoundary7MA4"), Some(String::from("----WebKitFormBoundary7MA4")));
+        assert_eq!(boundary("multipart/mixed; boundary=x"), None);
+    }
+}
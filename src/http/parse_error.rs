@@ -1,13 +1,29 @@
 use std::fmt::{Display, Formatter, Result as FmtResult, Debug};
 use std::str::{self, Utf8Error};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[derive(PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum ParseError {
     InvalidRequest,
     InvalidEncoding,
     InvalidProtocol,
     InvalidMethod,
+    InvalidHeader,
+    InvalidStatus,
+    InvalidBody,
+    /// A `multipart/form-data` content type without a usable boundary
+    InvalidBoundary,
+    /// A multipart body that doesn't open with the boundary or has junk after one
+    InvalidMultipart,
+    /// A multipart body that ended before its closing boundary
+    IncompleteMultipart,
+    /// A part whose headers are malformed, too long or lack a `form-data` Content-Disposition
+    InvalidPartHeader,
+    PartTooLarge,
+    MultipartTooLarge,
+    /// A status line and headers, or a chunk size line, over the size limit
+    HeadTooLarge,
 }
 
 impl ParseError {
@@ -17,6 +33,16 @@ impl ParseError {
             Self::InvalidEncoding => "Invalid Encoding",
             Self::InvalidProtocol => "Invalid Protocol",
             Self::InvalidMethod => "Invalid Method",
+            Self::InvalidHeader => "Invalid Header",
+            Self::InvalidStatus => "Invalid Status",
+            Self::InvalidBody => "Invalid Body",
+            Self::InvalidBoundary => "Invalid Boundary",
+            Self::InvalidMultipart => "Invalid Multipart",
+            Self::IncompleteMultipart => "Incomplete Multipart",
+            Self::InvalidPartHeader => "Invalid Part Header",
+            Self::PartTooLarge => "Part Too Large",
+            Self::MultipartTooLarge => "Multipart Too Large",
+            Self::HeadTooLarge => "Head Too Large",
         }
     }
 }
@@ -25,6 +51,12 @@ impl From<Utf8Error> for ParseError {
     fn from(_: Utf8Error) -> Self { ParseError::InvalidEncoding }
 }
 
+impl From<ParseError> for std::io::Error {
+    fn from(e: ParseError) -> Self { std::io::Error::new(std::io::ErrorKind::InvalidData, e) }
+}
+
+impl std::error::Error for ParseError {}
+
 impl Display for ParseError {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(f, "{}", self.message())
@@ -0,0 +1,315 @@
+use std::io::{BufRead, Read, Result as IoResult};
+
+use super::{Headers, Method, ParseError, StatusCode, request::parse_headers, server::MAX_HEAD_SIZE};
+
+/*
+EXAMPLE HTTP RESPONSE:
+
+HTTP/1.1 200 Ok\r\n
+Content-Length: 4\r\n
+\r\n
+BODY
+*/
+
+/// A response read back off the wire, ex one written by [Response::send()](super::Response::send)
+#[derive(Debug)]
+pub struct ParsedResponse {
+    /// `HTTP/1.1` or `HTTP/1.0`
+    pub version: String,
+    pub status: StatusCode,
+    /// The numeric code as sent, which can differ from `status` for codes [StatusCode] doesn't know
+    pub code: u16,
+    pub reason: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Headers sent after a chunked body
+    pub trailers: Headers,
+}
+
+impl ParsedResponse {
+    /// Parses a complete response held in memory. A body without `Content-Length` or
+    /// `Transfer-Encoding: chunked` runs to the end of `bytes`
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        Self::read_from(&mut &bytes[..], &Method::GET).map_err(|e| {
+            match e.get_ref().and_then(|inner| inner.downcast_ref::<ParseError>()) {
+                Some(parse_error) => parse_error.clone(),
+                None => ParseError::InvalidBody
+            }
+        })
+    }
+
+    /// Reads one response from `reader`, leaving anything after it (like the next response on a
+    /// keep-alive connection) unread. `method` is the method of the request, a HEAD response has no body
+    pub fn read_from(reader: &mut impl BufRead, method: &Method) -> IoResult<Self> {
+        Self::read_limited(reader, method, u64::MAX)
+    }
+
+    /// Like [ParsedResponse::read_from()], but fails with [InvalidData](std::io::ErrorKind::InvalidData)
+    /// instead of reading a body over `max_body_size` bytes
+    pub fn read_limited(reader: &mut impl BufRead, method: &Method, max_body_size: u64) -> IoResult<Self> {
+        let mut response = Self::read_head(reader)?;
+        // interim responses like 100 Continue and 103 Early Hints come before the real one, only a 101 is final
+        while (100..200).contains(&response.code) && response.code != 101 {
+            response = Self::read_head(reader)?;
+        }
+        let too_large = || err!(InvalidData, "Response body too large", "The body is over {} bytes", max_body_size);
+
+        response.body = match response.framing(method)? {
+            Framing::Empty => Vec::new(),
+            Framing::Chunked => read_chunked(reader, &mut response.trailers, max_body_size)?,
+            Framing::Length(length) if length > max_body_size => return Err(too_large()),
+            Framing::Length(length) => {
+                let mut body = Vec::new();
+                reader.take(length).read_to_end(&mut body)?;
+                if (body.len() as u64) < length {
+                    return Err(err!(UnexpectedEof, "Connection closed before the end of the body"));
+                }
+                body
+            },
+            Framing::Close => {
+                let mut body = Vec::new();
+                reader.take(max_body_size.saturating_add(1)).read_to_end(&mut body)?;
+                if body.len() as u64 > max_body_size {
+                    return Err(too_large());
+                }
+                body
+            }
+        };
+        Ok(response)
+    }
+
+    /// Reads only the status line and headers, leaving the body in `reader`.
+    /// Use [ParsedResponse::framing()] to know how much of it to read
+    pub fn read_head(reader: &mut impl BufRead) -> IoResult<Self> {
+        let head = read_head(reader)?;
+        let (status_line, rest) = head.split_once("\r\n").unwrap_or((&head, ""));
+
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or("");
+        if version != "HTTP/1.1" && version != "HTTP/1.0" {
+            return Err(ParseError::InvalidProtocol.into());
+        }
+        let code = parts.next()
+            .filter(|code| code.len() == 3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or(ParseError::InvalidStatus)?;
+        let status = StatusCode::from_code_or_class(code).ok_or(ParseError::InvalidStatus)?;
+        let reason = Str!(parts.next().unwrap_or(""));
+        let (headers, _) = parse_headers(rest)?;
+
+        Ok(Self { version: Str!(version), status, code, reason, headers, body: Vec::new(), trailers: Headers::new() })
+    }
+
+    /// How the body following these headers is delimited, for a response to a `method` request
+    pub fn framing(&self, method: &Method) -> IoResult<Framing> {
+        if *method == Method::HEAD || !self.status.allows_body() {
+            Ok(Framing::Empty)
+        } else if self.headers.has_token("Transfer-Encoding", "chunked") {
+            Ok(Framing::Chunked)
+        } else if let Some(length) = self.headers.get("Content-Length") {
+            let length = length.trim().parse::<u64>().map_err(|_| ParseError::InvalidHeader)?;
+            Ok(Framing::Length(length))
+        } else {
+            Ok(Framing::Close)
+        }
+    }
+
+    /// The body as text, replacing anything that isn't UTF-8
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// Whether the connection can be reused for another request after this response
+    pub fn keep_alive(&self) -> bool {
+        let framed = self.headers.contains("Content-Length")
+            || self.headers.has_token("Transfer-Encoding", "chunked")
+            || !self.status.allows_body();
+        let wants_close = self.headers.has_token("Connection", "close")
+            || (self.version == "HTTP/1.0" && !self.headers.has_token("Connection", "keep-alive"));
+        framed && !wants_close
+    }
+}
+
+/// How the end of a response body is found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Framing {
+    /// There is no body
+    Empty,
+    /// `Content-Length` bytes
+    Length(u64),
+    /// `Transfer-Encoding: chunked`
+    Chunked,
+    /// Everything until the connection closes
+    Close,
+}
+
+/// Reads a CRLF terminated line, without the CRLF. Lines over the server's head size limit fail with [ParseError::HeadTooLarge]
+pub fn read_line(reader: &mut impl BufRead) -> IoResult<String> {
+    read_bounded_line(reader, MAX_HEAD_SIZE)
+}
+
+/// [read_line()] for a line of at most `max` bytes, the CRLF included
+fn read_bounded_line(reader: &mut impl BufRead, max: usize) -> IoResult<String> {
+    let mut line = Vec::new();
+    // one byte more than allowed, to tell a line that is too long from one that just fits
+    if reader.by_ref().take(max as u64 + 1).read_until(b'\n', &mut line)? == 0 {
+        return Err(err!(UnexpectedEof, "Connection closed while reading a line"));
+    }
+    if line.len() > max {
+        return Err(ParseError::HeadTooLarge.into());
+    }
+    if line.ends_with(b"\r\n") {
+        line.truncate(line.len() - 2);
+    } else {
+        return Err(ParseError::InvalidRequest.into());
+    }
+    String::from_utf8(line).map_err(|_| ParseError::InvalidEncoding.into())
+}
+
+/// Reads the status line and headers, up to and including the blank line. Like a request's,
+/// a head over the server's size limit fails with [ParseError::HeadTooLarge]
+pub fn read_head(reader: &mut impl BufRead) -> IoResult<String> {
+    let mut head = String::new();
+    loop {
+        // the blank line at the end counts too
+        let line = read_bounded_line(reader, MAX_HEAD_SIZE.saturating_sub(head.len()))?;
+        if line.is_empty() {
+            return Ok(head);
+        }
+        head.push_str(&line);
+        head.push_str("\r\n");
+    }
+}
+
+/// Reads a `Transfer-Encoding: chunked` body of up to `max_body_size` bytes, putting any trailer fields into `trailers`
+pub fn read_chunked(reader: &mut impl BufRead, trailers: &mut Headers, max_body_size: u64) -> IoResult<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        // chunk extensions (`;name=value`) are allowed after the size, we ignore them
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| ParseError::InvalidBody)?;
+
+        if size == 0 {
+            let trailer_block = read_head(reader)?;
+            let (parsed, _) = parse_headers(&trailer_block)?;
+            *trailers = parsed;
+            return Ok(body);
+        }
+
+        // the size comes from the other side, nothing is allocated for it up front
+        if (body.len() as u64).checked_add(size).filter(|&total| total <= max_body_size).is_none() {
+            return Err(err!(InvalidData, "Response body too large", "The body is over {} bytes", max_body_size));
+        }
+        if reader.take(size).read_to_end(&mut body)? < size as usize {
+            return Err(err!(UnexpectedEof, "Connection closed before the end of the body"));
+        }
+        if !read_line(reader)?.is_empty() {
+            return Err(ParseError::InvalidBody.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{rc::Rc, cell::RefCell};
+    use crate::http::Response;
+
+    #[test]
+    fn content_length() {
+        let raw = b"HTTP/1.1 404 Not Found\r\nContent-Length: 5\r\nX-Fruit: banana\r\n\r\nApplesNEXT";
+        let mut reader = &raw[..];
+        let res = ParsedResponse::read_from(&mut reader, &Method::GET).expect("failed to parse");
+
+        assert_eq!(res.status, StatusCode::NotFound);
+        assert_eq!(res.reason, "Not Found");
+        assert_eq!(res.headers.get("x-fruit"), Some("banana"));
+        assert_eq!(res.body, b"Apple");
+        assert_eq!(reader, b"sNEXT");
+        assert!(res.keep_alive());
+    }
+
+    #[test]
+    fn chunked() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n6;ext=1\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\n";
+        let res = ParsedResponse::parse(raw).expect("failed to parse");
+        assert_eq!(res.text(), "Wikipedia in \r\n\r\nchunks.");
+        assert_eq!(res.trailers.get("Expires"), Some("never"));
+
+        let truncated = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nZ\r\n";
+        assert_eq!(ParsedResponse::parse(truncated).unwrap_err(), ParseError::InvalidBody);
+        let cut_short = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nA\r\nshort";
+        assert_eq!(ParsedResponse::read_from(&mut &cut_short[..], &Method::GET).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn body_limit() {
+        let read = |raw: &[u8], max| ParsedResponse::read_limited(&mut &raw[..], &Method::GET, max).map(|res| res.body);
+        let huge = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\nFFFFFFFFFFFFFFFF\r\nb\r\n0\r\n\r\n";
+        assert_eq!(read(huge, u64::MAX).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n";
+        assert_eq!(read(chunked, 6).unwrap(), b"abcdef");
+        assert!(read(chunked, 5).is_err());
+        assert!(read(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nabcdef", 5).is_err());
+        assert!(read(b"HTTP/1.0 200 OK\r\n\r\nabcdef", 5).is_err());
+        assert_eq!(read(b"HTTP/1.0 200 OK\r\n\r\nabcdef", 6).unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn head_limit() {
+        let too_large = |raw: Vec<u8>| {
+            let e = ParsedResponse::read_from(&mut &raw[..], &Method::GET).unwrap_err();
+            e.get_ref().and_then(|inner| inner.downcast_ref::<ParseError>()) == Some(&ParseError::HeadTooLarge)
+        };
+        let long_line = format!("HTTP/1.1 200 OK\r\nX-Big: {}\r\n\r\n", "a".repeat(MAX_HEAD_SIZE));
+        assert!(too_large(long_line.into_bytes()));
+        let many_lines = format!("HTTP/1.1 200 OK\r\n{}\r\n", "X-Small: a\r\n".repeat(MAX_HEAD_SIZE / 10));
+        assert!(too_large(many_lines.into_bytes()));
+        let chunk_size = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1{}\r\na\r\n0\r\n\r\n", " ".repeat(MAX_HEAD_SIZE));
+        assert!(too_large(chunk_size.into_bytes()));
+
+        let just_fits = format!("HTTP/1.1 200 OK\r\nX-Big: {}\r\n\r\n", "a".repeat(MAX_HEAD_SIZE - 28));
+        assert_eq!(just_fits.len(), MAX_HEAD_SIZE);
+        assert!(ParsedResponse::parse(just_fits.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn close_delimited() {
+        let res = ParsedResponse::parse(b"HTTP/1.0 200 OK\r\n\r\nuntil the end").expect("failed to parse");
+        assert_eq!(res.text(), "until the end");
+        assert!(!res.keep_alive());
+    }
+
+    #[test]
+    fn no_body() {
+        let raw = b"HTTP/1.1 304 Not Modified\r\nContent-Length: 100\r\n\r\n";
+        assert!(ParsedResponse::parse(raw).expect("failed to parse").body.is_empty());
+
+        let raw = b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n";
+        let res = ParsedResponse::read_from(&mut &raw[..], &Method::HEAD).expect("failed to parse");
+        assert!(res.body.is_empty());
+    }
+
+    #[test]
+    fn status() {
+        let res = ParsedResponse::parse(b"HTTP/1.1 418 I'm a teapot\r\nContent-Length: 0\r\n\r\n").expect("failed to parse");
+        assert_eq!(res.code, 418);
+        assert_eq!(res.status, StatusCode::BadRequest);
+        assert_eq!(ParsedResponse::parse(b"HTTP/1.1 20 OK\r\n\r\n").unwrap_err(), ParseError::InvalidStatus);
+        assert_eq!(ParsedResponse::parse(b"HTTP/2 200 OK\r\n\r\n").unwrap_err(), ParseError::InvalidProtocol);
+    }
+
+    #[test]
+    fn round_trip() {
+        let b = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut res = Response::new(b.clone());
+        res.set_header("X-Fruit", "banana").gen_404().send().expect("error writing to buffer");
+
+        let parsed = ParsedResponse::parse(&b.borrow()).expect("failed to parse");
+        assert_eq!(parsed.status, res.status);
+        assert_eq!(parsed.headers, res.headers);
+        assert_eq!(Some(parsed.body), res.body);
+    }
+}
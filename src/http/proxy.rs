@@ -0,0 +1,421 @@
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write, Result as IoResult},
+    net::{TcpStream, ToSocketAddrs, SocketAddr},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration
+};
+
+use super::{
+    Headers,
+    Method,
+    Request,
+    Response,
+    RequestHandler,
+    StatusCode,
+    chunked_writer::ChunkedWriter,
+    parsed_response::{ParsedResponse, Framing, read_line, read_head},
+    request::parse_headers
+};
+
+/// Headers that only apply to a single connection and must not be forwarded
+pub const HOP_BY_HOP: &[&str] = &[
+    "Connection", "Keep-Alive", "Proxy-Authenticate", "Proxy-Authorization",
+    "Proxy-Connection", "TE", "Trailer", "Transfer-Encoding", "Upgrade",
+];
+
+/// Forwards every request to an upstream server and streams its response back to the client
+pub struct ProxyHandler {
+    upstream: String,
+    /// In nanoseconds, atomic so a [LoadBalancer](super::load_balancer::LoadBalancer) can change it while in use
+    timeout: AtomicU64,
+    via: String
+}
+
+/// Where forwarding failed, which decides whether the client can still be sent an error page
+pub(super) enum ProxyError {
+    /// Nothing has been sent to the client yet
+    Upstream(io::Error),
+    /// The response is partly written, all we can do is drop the connection
+    Downstream(io::Error)
+}
+
+impl ProxyHandler {
+    /// `upstream` is the `host:port` to forward to
+    pub fn new(upstream: &str) -> Self {
+        let mut proxy = Self { upstream: Str!(upstream), timeout: AtomicU64::new(0), via: Str!("http-server") };
+        proxy.set_timeout(Duration::from_secs(30));
+        proxy
+    }
+
+    /// How long to wait for the upstream to accept the connection and for each read, before answering 504.
+    /// Zero waits forever
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.store_timeout(timeout);
+        self
+    }
+
+    /// [ProxyHandler::set_timeout()] for a proxy that is shared
+    pub(super) fn store_timeout(&self, timeout: Duration) {
+        self.timeout.store(u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_nanos(self.timeout.load(Ordering::Relaxed))
+    }
+
+    /// Name this proxy uses for itself in the `Via` header
+    pub fn set_via(&mut self, via: &str) -> &mut Self {
+        self.via = Str!(via);
+        self
+    }
+
+    pub fn upstream(&self) -> &str {
+        &self.upstream
+    }
+
+    pub(super) fn connect(&self) -> IoResult<TcpStream> {
+        let mut last_error = err!(NotFound, "Upstream did not resolve to any address", "Upstream: {}", self.upstream);
+        // the socket calls reject a zero timeout
+        let timeout = Some(self.timeout()).filter(|timeout| !timeout.is_zero());
+        for addr in self.upstream.to_socket_addrs()? {
+            let stream = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr)
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(timeout)?;
+                    stream.set_write_timeout(timeout)?;
+                    return Ok(stream);
+                },
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+
+    /// The headers to send upstream: the client's minus hop-by-hop ones, plus the forwarding headers
+    pub fn upstream_headers(&self, req: &Request) -> Headers {
+        let mut headers = strip_hop_by_hop(req.headers());
+        // the server already answered any 100-continue and read the body, the upstream gets it in one go
+        headers.remove("Expect");
+        let original_host = req.headers().get("Host").map(String::from);
+        headers.set("Host", self.upstream.as_str());
+
+        if let Some(host) = &original_host {
+            headers.set("X-Forwarded-Host", host.as_str());
+        }
+        headers.set("X-Forwarded-Proto", "http");
+
+        let mut forwarded = Vec::new();
+        if let Some(addr) = req.remote_addr() {
+            let ip = addr.ip().to_string();
+            let forwarded_for = match req.headers().get("X-Forwarded-For") {
+                Some(previous) => format!("{}, {}", previous, ip),
+                None => ip
+            };
+            headers.set("X-Forwarded-For", forwarded_for);
+            forwarded.push(format!("for={}", forwarded_node(addr)));
+        }
+        if let Some(host) = &original_host {
+            forwarded.push(format!("host=\"{}\"", host));
+        }
+        forwarded.push(Str!("proto=http"));
+        let forwarded = match req.headers().get("Forwarded") {
+            Some(previous) => format!("{}, {}", previous, forwarded.join(";")),
+            None => forwarded.join(";")
+        };
+        headers.set("Forwarded", forwarded);
+
+        append_via(&mut headers, &self.via);
+        headers
+    }
+
+    pub(super) fn forward(&self, req: &Request, res: &mut Response) -> Result<(), ProxyError> {
+        let stream = self.connect().map_err(ProxyError::Upstream)?;
+        let mut upstream = BufReader::new(stream);
+
+        let body = req.body().unwrap_or(&[]);
+        let mut headers = self.upstream_headers(req);
+        if !body.is_empty() || matches!(req.method(), Method::POST | Method::PUT | Method::PATCH) {
+            headers.set("Content-Length", body.len().to_string());
+        }
+        // one request per upstream connection, so close delimited responses work
+        headers.set("Connection", "close");
+
+        let mut raw = format!("{} {} HTTP/1.1\r\n{}\r\n", req.method(), req.target(), headers).into_bytes();
+        raw.extend_from_slice(body);
+        upstream.get_mut().write_all(&raw).map_err(ProxyError::Upstream)?;
+
+        // interim responses like 103 Early Hints come before the real one, only a 101 ends the exchange
+        let head = loop {
+            let head = ParsedResponse::read_head(&mut upstream).map_err(ProxyError::Upstream)?;
+            if !(100..200).contains(&head.code) || head.code == 101 {
+                break head;
+            }
+        };
+        let framing = head.framing(req.method()).map_err(ProxyError::Upstream)?;
+
+        // the upstream's own code and reason, StatusCode doesn't know them all
+        res.set_raw_status(head.code, &head.reason);
+        res.body = None;
+        for (name, value) in strip_hop_by_hop(&head.headers).iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                res.headers.add(name, value);
+            }
+        }
+        append_via(&mut res.headers, &self.via);
+
+        match framing {
+            Framing::Empty => {
+                if let Some(length) = head.headers.get("Content-Length") {
+                    res.set_header("Content-Length", length);
+                }
+                res.send_head().map_err(ProxyError::Downstream)
+            },
+            Framing::Length(length) => {
+                res.set_header("Content-Length", length.to_string());
+                res.send_head().map_err(ProxyError::Downstream)?;
+                let writer = res.writer();
+                let copied = io::copy(&mut upstream.take(length), &mut *writer.borrow_mut()).map_err(ProxyError::Downstream)?;
+                if copied < length {
+                    return Err(ProxyError::Downstream(err!(UnexpectedEof, "Upstream closed before the end of the body")));
+                }
+                Ok(())
+            },
+            Framing::Chunked => {
+                // re-chunked rather than copied as is, so HTTP/1.0 clients get a body they understand
+                let mut writer = res.stream().map_err(ProxyError::Downstream)?;
+                relay_chunks(&mut upstream, &mut writer)
+                    .and_then(|_| writer.finish())
+                    .map_err(ProxyError::Downstream)
+            },
+            Framing::Close => {
+                res.send_head().map_err(ProxyError::Downstream)?;
+                let writer = res.writer();
+                let copied = io::copy(&mut upstream, &mut *writer.borrow_mut());
+                copied.map(|_| ()).map_err(ProxyError::Downstream)
+            }
+        }
+    }
+
+    /// Answers 504 if the upstream timed out, 502 for anything else
+    pub(super) fn send_upstream_error(&self, res: &mut Response, e: &io::Error) -> IoResult<()> {
+        eprintln!("Proxying to {} failed: {}", self.upstream, e);
+        if is_timeout(e) {
+            res.send_error(StatusCode::GatewayTimeout, "The upstream server took too long to respond.")
+        } else {
+            res.send_error(StatusCode::BadGateway, "The upstream server could not be reached.")
+        }
+    }
+}
+
+impl RequestHandler for ProxyHandler {
+    fn handle(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+        match self.forward(req, res) {
+            Ok(()) => Ok(()),
+            Err(ProxyError::Upstream(e)) => self.send_upstream_error(res, &e),
+            Err(ProxyError::Downstream(e)) => Err(e)
+        }
+    }
+}
+
+
+pub fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
+
+/// Copies `headers` without the hop-by-hop ones, including any the `Connection` header names
+pub fn strip_hop_by_hop(headers: &Headers) -> Headers {
+    let listed: Vec<&str> = headers.get_all("Connection").flat_map(|v| v.split(',')).map(str::trim).collect();
+    let mut stripped = Headers::new();
+    for (name, value) in headers.iter() {
+        let hop_by_hop = HOP_BY_HOP.iter().chain(listed.iter()).any(|h| h.eq_ignore_ascii_case(name));
+        if !hop_by_hop {
+            stripped.add(name, value);
+        }
+    }
+    stripped
+}
+
+fn append_via(headers: &mut Headers, via: &str) {
+    let via = match headers.get("Via") {
+        Some(previous) => format!("{}, 1.1 {}", previous, via),
+        None => format!("1.1 {}", via)
+    };
+    headers.set("Via", via);
+}
+
+/// Formats an address for the `Forwarded` header, IPv6 has to be quoted and bracketed
+fn forwarded_node(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V4(v4) => v4.ip().to_string(),
+        SocketAddr::V6(v6) => format!("\"[{}]\"", v6.ip())
+    }
+}
+
+/// Copies a chunked body chunk by chunk as it arrives, so the client sees data as soon as the upstream sends it
+fn relay_chunks(upstream: &mut impl BufRead, client: &mut ChunkedWriter) -> IoResult<()> {
+    loop {
+        let line = read_line(upstream)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| err!(InvalidData, "Invalid chunk size", "Line: {}", line))?;
+
+        if size == 0 {
+            let trailers = read_head(upstream)?;
+            let (trailers, _) = parse_headers(&trailers)?;
+            for (name, value) in strip_hop_by_hop(&trailers).iter() {
+                client.set_trailer(name, value);
+            }
+            return Ok(());
+        }
+
+        io::copy(&mut upstream.take(size), client)?;
+        read_line(upstream)?;
+        client.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+    use crate::http::{client::Client, test_client::spawn_test_server};
+
+    /// Answers with the request it got, so tests can see what the proxy forwarded
+    struct Upstream;
+
+    impl RequestHandler for Upstream {
+        fn handle(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            if let Some(status) = req.path().strip_prefix("/status/") {
+                let reason = if status == "418" { "I'm a teapot" } else { "Unavailable For Legal Reasons" };
+                return write!(res.writer().borrow_mut(), "HTTP/1.1 {} {}\r\nContent-Length: 2\r\n\r\nno", status, reason);
+            }
+            if req.path() == "/early-hints" {
+                return res.writer().borrow_mut().write_all(b"HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\nHTTP/1.1 200 Ok\r\nContent-Length: 5\r\n\r\nfinal");
+            }
+            if req.path() == "/chunked" {
+                res.set_header("Transfer-Encoding", "chunked").send_head()?;
+                return res.writer().borrow_mut().write_all(b"5\r\nHello\r\n6\r\n World\r\n0\r\n\r\n");
+            }
+            res.set_header("X-Upstream", "yes").set_header("Keep-Alive", "timeout=5");
+            res.ok(Some(format!("{} {}\n{}\r\n{}", req.method(), req.target(), req.headers(), req.body_str().unwrap_or(""))))
+        }
+    }
+
+    #[test]
+    fn forwards_request() {
+        let upstream = spawn_test_server(Upstream);
+        let proxy = spawn_test_server(ProxyHandler::new(&upstream));
+
+        let res = Client::new()
+            .request(Method::POST, &format!("http://{}/path?a=1&b=2", proxy))
+            .header("X-Custom", "kept")
+            .header("X-Secret", "dropped")
+            .header("Connection", "X-Secret")
+            .body("the body")
+            .send()
+            .expect("request failed");
+
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(res.headers.get("X-Upstream"), Some("yes"));
+        assert_eq!(res.headers.get("Keep-Alive"), None);
+        assert_eq!(res.headers.get("Via"), Some("1.1 http-server"));
+
+        let text = res.text();
+        let (request_line, rest) = text.split_once('\n').unwrap();
+        assert_eq!(request_line, "POST /path?a=1&b=2");
+        let (headers, body) = parse_headers(rest).expect("invalid echoed headers");
+        assert_eq!(body, "the body");
+        assert_eq!(headers.get("Host"), Some(upstream.as_str()));
+        assert_eq!(headers.get("X-Custom"), Some("kept"));
+        assert_eq!(headers.get("X-Secret"), None);
+        assert_eq!(headers.get("X-Forwarded-For"), Some("127.0.0.1"));
+        assert_eq!(headers.get("X-Forwarded-Host"), Some(proxy.as_str()));
+        assert_eq!(headers.get("Forwarded"), Some(format!("for=127.0.0.1;host=\"{}\";proto=http", proxy).as_str()));
+        assert_eq!(headers.get("Via"), Some("1.1 http-server"));
+    }
+
+    #[test]
+    fn no_timeout() {
+        let upstream = spawn_test_server(Upstream);
+        let mut handler = ProxyHandler::new(&upstream);
+        handler.set_timeout(Duration::ZERO);
+        let proxy = spawn_test_server(handler);
+
+        let res = Client::new().get(&format!("http://{}/path", proxy)).expect("request failed");
+        assert_eq!(res.status, StatusCode::Ok);
+    }
+
+    #[test]
+    fn streams_chunked() {
+        let upstream = spawn_test_server(Upstream);
+        let proxy = spawn_test_server(ProxyHandler::new(&upstream));
+
+        let res = Client::new().get(&format!("http://{}/chunked", proxy)).expect("request failed");
+        assert_eq!(res.headers.get("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(res.text(), "Hello World");
+    }
+
+    #[test]
+    fn interim_responses() {
+        let upstream = spawn_test_server(Upstream);
+        let proxy = spawn_test_server(ProxyHandler::new(&upstream));
+
+        let res = Client::new().get(&format!("http://{}/early-hints", proxy)).expect("request failed");
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(res.text(), "final");
+
+        // the proxy answers the 100 itself, the upstream never sees the Expect
+        let mut stream = TcpStream::connect(&proxy).expect("failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(stream);
+        assert_eq!(ParsedResponse::read_head(&mut reader).unwrap().status, StatusCode::Continue);
+        reader.get_mut().write_all(b"hello").unwrap();
+
+        let res = ParsedResponse::read_from(&mut reader, &Method::POST).expect("failed to read the response");
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(res.text().starts_with("POST /upload\n"));
+        assert!(res.text().ends_with("\r\nhello"));
+        assert!(!res.text().to_ascii_lowercase().contains("expect:"));
+    }
+
+    #[test]
+    fn unlisted_status() {
+        let upstream = spawn_test_server(Upstream);
+        let proxy = spawn_test_server(ProxyHandler::new(&upstream));
+
+        for (code, reason) in [(418, "I'm a teapot"), (451, "Unavailable For Legal Reasons")] {
+            let res = Client::new().get(&format!("http://{}/status/{}", proxy, code)).expect("request failed");
+            assert_eq!((res.code, res.reason.as_str()), (code, reason));
+            assert_eq!(res.status, StatusCode::BadRequest);
+            assert_eq!(res.text(), "no");
+        }
+    }
+
+    #[test]
+    fn bad_gateway() {
+        // grab a free port then stop listening on it
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let proxy = spawn_test_server(ProxyHandler::new(&addr.to_string()));
+        let res = Client::new().get(&format!("http://{}/", proxy)).expect("request failed");
+        assert_eq!(res.status, StatusCode::BadGateway);
+    }
+
+    #[test]
+    fn gateway_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let _connection = listener.accept();
+            thread::sleep(Duration::from_secs(5));
+        });
+
+        let mut handler = ProxyHandler::new(&addr.to_string());
+        handler.set_timeout(Duration::from_millis(100));
+        let proxy = spawn_test_server(handler);
+        let res = Client::new().get(&format!("http://{}/", proxy)).expect("request failed");
+        assert_eq!(res.status, StatusCode::GatewayTimeout);
+    }
+}
@@ -1,82 +1,307 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str;
 use std::vec::Vec;
 
-#[derive(Debug)]
+/// The parameters of a query string, grouped by key in the order keys first appear. [QueryString::pairs()] and Display
+/// keep every parameter in the order it was sent. Keys and values are kept as they were sent, see [decode()]
+#[derive(Debug, Clone)]
 pub struct QueryString<'rs> {
-    data: HashMap<&'rs str, Value<'rs>>
+    data: Vec<(&'rs str, Value<'rs>)>,
+    pairs: Vec<(&'rs str, Option<&'rs str>)>
 }
 
-#[derive(Debug)]
+/// The values given for a key. `?a=` is `One("")`, only a key without any `=` is `None`
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value<'rs> {
     One(&'rs str),
     Multiple(Vec<&'rs str>),
+    /// A flag, ex `?verbose`
     None
 }
 
 impl<'rs> QueryString<'rs> {
-    pub fn get(&self, key: &str) -> Option<&Value> {
-        self.data.get(key)
+    pub fn get(&self, key: &str) -> Option<&Value<'_>> {
+        self.data.iter().find(|(k, _)| *k == key).map(|(_, value)| value)
     }
     pub fn len(&self) -> usize {
         self.data.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+    /// Every value of `key` in the order they were given, nothing for a flag or a missing key
+    pub fn get_all<'a>(&'a self, key: &str) -> impl Iterator<Item = &'rs str> + 'a {
+        let values: &[&'rs str] = match self.data.iter().find(|(k, _)| *k == key).map(|(_, value)| value) {
+            Some(Value::One(value)) => std::slice::from_ref(value),
+            Some(Value::Multiple(values)) => values,
+            Some(Value::None) | None => &[]
+        };
+        values.iter().copied()
+    }
+    /// Whether `key` was given without a value, like `?verbose` but not `?verbose=`
+    pub fn is_flag(&self, key: &str) -> bool {
+        matches!(self.get(key), Some(Value::None))
+    }
+    pub fn keys(&self) -> impl Iterator<Item = &'rs str> + '_ {
+        self.data.iter().map(|(key, _)| *key)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&'rs str, &Value<'rs>)> {
+        self.data.iter().map(|(key, value)| (*key, value))
+    }
+    /// Every parameter in the order it was sent, ex `a&a=3` is `("a", None), ("a", Some("3"))`
+    pub fn pairs(&self) -> impl Iterator<Item = (&'rs str, Option<&'rs str>)> + '_ {
+        self.pairs.iter().copied()
+    }
 }
 
-// ex a=1&b=2&c&e====&d=7&d=abc
-// { a: 1, b:2, c:None, e:===, d:[7, abc]}
-impl<'rs> From<&'rs str> for QueryString<'rs> {
-    fn from(s: &'rs str) -> Self {
-        let mut data = HashMap::new();
+/// The fields of an `application/x-www-form-urlencoded` body, see [Request::form()](super::Request::form).
+/// Unlike [QueryString] names and values are decoded, and every field is kept in the order it was sent
+#[derive(Debug, Default, PartialEq)]
+pub struct Form {
+    fields: Vec<(String, Option<String>)>
+}
 
-        for sub_str in s.split('&') {
-            let mut key = sub_str;
-            let mut val = "";
+impl Form {
+    /// The first value of `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find_map(|(n, value)| value.as_deref().filter(|_| n == name))
+    }
+    /// Every value of `name` in the order they were sent, fields sent without a value are skipped
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields.iter().filter(move |(n, _)| n == name).filter_map(|(_, value)| value.as_deref())
+    }
+    /// Whether `name` was sent without any `=`, like `news` in `name=Jo&news`
+    pub fn is_flag(&self, name: &str) -> bool {
+        self.fields.iter().any(|(n, value)| n == name && value.is_none())
+    }
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.fields.iter().any(|(n, _)| n == name)
+    }
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+}
 
-            if let Some(i) = sub_str.find('=') {
-                key = &sub_str[..i];
-                val = &sub_str[i + 1..];
-            }
-            let mut to_insert = Value::None;
+impl From<&str> for Form {
+    fn from(s: &str) -> Self {
+        let fields = s.split('&')
+            .filter(|field| !field.is_empty())
+            .map(|field| match field.split_once('=') {
+                Some((name, value)) => (decode(name).into_owned(), Some(decode(value).into_owned())),
+                None => (decode(field).into_owned(), None)
+            })
+            .collect();
+        Self { fields }
+    }
+}
+
+/// Decodes a key or value, `+` is a space and `%XX` a byte. Malformed escapes are left as they are,
+/// and bytes that don't make UTF-8 are replaced
+pub fn decode(s: &str) -> Cow<'_, str> {
+    percent_decode(s, true)
+}
 
-            if val != "" {
-                to_insert = Value::One(val);
+/// [decode()] for the places where `+` is just a `+`, like cookies
+pub(super) fn percent_decode(s: &str, plus_as_space: bool) -> Cow<'_, str> {
+    if !(s.contains('%') || plus_as_space && s.contains('+')) {
+        return Cow::Borrowed(s);
+    }
+
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        // from_str_radix alone would take `%+1`
+        let escaped = bytes.get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (b'+', _) if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
             }
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&decoded).into_owned())
+}
 
-            data.entry(key)
+/// Encodes a key or value so [decode()] gives it back. Letters, digits and `-._~` stay as they are,
+/// spaces become `+` and everything else `%XX`
+pub fn encode(s: &str) -> Cow<'_, str> {
+    let keep = |b: u8| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~');
+    if s.bytes().all(keep) {
+        return Cow::Borrowed(s);
+    }
 
-            .and_modify(|existing| match existing {
-                // Must dereference pointer in order to overwrite the value, all enum variants take up the same space
-                Value::None => {
-                    // do not switch to Value::One if there stil is no value
-                    if val != "" {
-                        *existing = Value::One(val)
-                    }
-                },
-                Value::One(prev) => *existing = Value::Multiple(vec![prev, val]),
-                Value::Multiple(vec) => vec.push(val)
+    let mut encoded = String::with_capacity(s.len() * 3);
+    for b in s.bytes() {
+        match b {
+            _ if keep(b) => encoded.push(b as char),
+            b' ' => encoded.push('+'),
+            _ => encoded.push_str(&format!("%{:02X}", b))
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+/// Builds a query string from plain keys and values, encoding them as it is written.
+/// Parameters keep the order they were added in, ex for a pagination link:
+/// `QueryBuilder::from(req.query()).set("page", 3).to_url(req.path())`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueryBuilder {
+    params: Vec<(String, Option<String>)>,
+}
 
+impl QueryBuilder {
+    pub fn new() -> Self {
+        Self { params: Vec::new() }
+    }
+
+    /// Adds `key=value`, adding the same key again gives it several values
+    pub fn add(&mut self, key: &str, value: impl ToString) -> &mut Self {
+        self.params.push((Str!(key), Some(value.to_string())));
+        self
+    }
+
+    /// Adds a key without a value, ex `?verbose`
+    pub fn flag(&mut self, key: &str) -> &mut Self {
+        self.params.push((Str!(key), None));
+        self
+    }
+
+    /// Replaces every value of `key` with `value`, keeping its place if it was already there
+    pub fn set(&mut self, key: &str, value: impl ToString) -> &mut Self {
+        let mut value = Some(value.to_string());
+        // the first one takes the value, the rest are dropped
+        self.params.retain_mut(|(k, v)| match k == key {
+            true if value.is_some() => {
+                *v = value.take();
+                true
+            },
+            true => false,
+            false => true
+        });
+        if let Some(value) = value {
+            self.params.push((Str!(key), Some(value)));
+        }
+        self
+    }
+
+    pub fn remove(&mut self, key: &str) -> &mut Self {
+        self.params.retain(|(k, _)| k != key);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    /// `path` with the query string after it, or just `path` if there are no parameters
+    pub fn to_url(&self, path: &str) -> String {
+        match self.is_empty() {
+            true => Str!(path),
+            false => format!("{}?{}", path, self)
+        }
+    }
+}
+
+/// Starts from a parsed query string, decoding its keys and values. Handy for links that change one parameter
+impl From<Option<&QueryString<'_>>> for QueryBuilder {
+    fn from(query: Option<&QueryString<'_>>) -> Self {
+        let mut builder = Self::new();
+        for (key, value) in query.into_iter().flat_map(|query| query.pairs()) {
+            match value {
+                Some(value) => builder.add(&decode(key), decode(value)),
+                None => builder.flag(&decode(key))
+            };
+        }
+        builder
+    }
+}
+
+/// Writes `a=1&b=two+words`, without the leading `?`
+impl Display for QueryBuilder {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        for (i, (key, value)) in self.params.iter().enumerate() {
+            if i > 0 {
+                f.write_str("&")?;
+            }
+            f.write_str(&encode(key))?;
+            if let Some(value) = value {
+                write!(f, "={}", encode(value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ex a=1&b=2&c&e====&d=7&d=abc&g=
+// { a: 1, b:2, c:None, e:===, d:[7, abc], g:"" }
+impl<'rs> From<&'rs str> for QueryString<'rs> {
+    fn from(s: &'rs str) -> Self {
+        let mut data: Vec<(&'rs str, Value<'rs>)> = Vec::new();
+        let pairs: Vec<(&'rs str, Option<&'rs str>)> = s.split('&')
+            .filter(|sub_str| !sub_str.is_empty())
+            .map(|sub_str| match sub_str.split_once('=') {
+                Some((key, val)) => (key, Some(val)),
+                None => (sub_str, None)
             })
+            .collect();
 
-            .or_insert(to_insert);
+        for &(key, val) in pairs.iter() {
+            let existing = match data.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, existing)) => existing,
+                None => {
+                    data.push((key, Value::None));
+                    &mut data.last_mut().unwrap().1
+                }
+            };
+            // a flag adds no value, so next to values of the same key only pairs() still has it
+            let Some(val) = val else { continue };
+            match existing {
+                Value::None => *existing = Value::One(val),
+                Value::One(prev) => *existing = Value::Multiple(vec![prev, val]),
+                Value::Multiple(vec) => vec.push(val)
+            }
         }
 
-        QueryString { data }
+        QueryString { data, pairs }
     }
 }
 
+/// Writes the parameters back as a query string in the order they were sent, without the leading `?`
+/// and minus empty parameters. Keys and values were never decoded, so they are written as they came and parse back the same
 impl<'rs> Display for QueryString<'rs> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        let mut string = Str!("{");
-        for (key, value) in (&self.data).into_iter() {
-            string = match value {
-                Value::One(val) => format!("{}\n  {}=\"{}\"", string, key, val),
-                Value::Multiple(vec) => format!("{}\n  {}={:?}", string, key, vec),
-                Value::None => format!("{}\n \"{}\"", string, key)
+        for (i, (key, value)) in self.pairs.iter().enumerate() {
+            if i > 0 {
+                f.write_str("&")?;
+            }
+            f.write_str(key)?;
+            if let Some(value) = value {
+                write!(f, "={}", value)?;
             }
         }
-
-        write!(f, "{}\n}}", string)
+        Ok(())
     }
 }
 
@@ -89,35 +314,117 @@ mod tests {
 
         // { a: 1, b:2, c:None, e:===, d:[7, abc], f:5}
 
-        match &qs.data["a"] {
+        match qs.get("a").unwrap() {
             Value::One(v) => assert_eq!(*v, "1"),
             Value::Multiple(v) => panic!("a has multiple values {:?}", v),
             Value::None => panic!("a has no value")
         }
-        match &qs.data["b"] {
+        match qs.get("b").unwrap() {
             Value::One(v) => assert_eq!(*v, "2"),
             Value::Multiple(v) => panic!("b has multiple values {:?}", v),
             Value::None => panic!("b has no value")
         }
-        match &qs.data["c"] {
+        match qs.get("c").unwrap() {
             Value::One(v) => panic!("c has a value {}", v),
             Value::Multiple(v) => panic!("c has multiple values {:?}", v),
             Value::None => {} // everything is cool, dont need to worry.
         }
-        match &qs.data["d"] {
+        match qs.get("d").unwrap() {
             Value::One(v) => panic!("d only has one value {}", v),
             Value::Multiple(v) => assert!(v.len() == 2 && v.contains(&"7") && v.contains(&"abc")),
             Value::None => panic!("d has no value")
         }
-        match &qs.data["e"] {
+        match qs.get("e").unwrap() {
             Value::One(v) => assert_eq!(*v, "==="),
             Value::Multiple(v) => panic!("e has multiple values {:?}", v),
             Value::None => panic!("e has no value")
         }
-        match &qs.data["f"] {
+        match qs.get("f").unwrap() {
             Value::One(v) => assert_eq!(*v, "5"),
             Value::Multiple(v) => panic!("f has multiple values {:?}", v),
             Value::None => panic!("f has no value")
         }
     }
+
+    #[test]
+    fn decoding() {
+        assert_eq!(decode("plain"), "plain");
+        assert_eq!(decode("a+b%20c%2Fd"), "a b c/d");
+        assert_eq!(decode("caf%C3%A9"), "café");
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz%4"), "%zz%4");
+        assert_eq!(decode("%+1%-1"), "% 1%-1");
+        assert_eq!(percent_decode("a+b%2B", false), "a+b+");
+        assert_eq!(decode("%FF"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode("plain-text_1.0~"), "plain-text_1.0~");
+        assert_eq!(encode("a b&c=d/é?"), "a+b%26c%3Dd%2F%C3%A9%3F");
+        for s in ["a b&c=d/é?", "100% + more", ""] {
+            assert_eq!(decode(&encode(s)), s);
+        }
+    }
+
+    #[test]
+    fn builder() {
+        let mut builder = QueryBuilder::new();
+        builder.add("q", "red apples").add("tag", "a&b").flag("exact").add("tag", 2).add("page", 1);
+        assert_eq!(builder.to_string(), "q=red+apples&tag=a%26b&exact&tag=2&page=1");
+
+        builder.set("tag", "one").set("page", 2).set("sort", "new").remove("exact");
+        assert_eq!(builder.to_url("/search"), "/search?q=red+apples&tag=one&page=2&sort=new");
+        assert_eq!(QueryBuilder::new().to_url("/search"), "/search");
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut builder = QueryBuilder::new();
+        builder.add("q", "x=1&y=2").add("d", "7").add("d", "a b").flag("c");
+        let built = builder.to_string();
+
+        let qs = QueryString::from(built.as_str());
+        assert!(matches!(qs.get("q"), Some(Value::One(v)) if decode(v) == "x=1&y=2"));
+        assert!(matches!(qs.get("c"), Some(Value::None)));
+        // and back, the parsed string builds the same parameters
+        assert_eq!(QueryBuilder::from(Some(&qs)).to_string(), built);
+
+        let displayed = qs.to_string();
+        let reparsed = QueryString::from(displayed.as_str());
+        assert_eq!(reparsed.len(), qs.len());
+        assert!(matches!(reparsed.get("d"), Some(Value::Multiple(v)) if v == &["7", "a+b"]));
+    }
+
+    #[test]
+    fn ordered() {
+        let qs = QueryString::from("z=1&a&m=2&a=3&z=4&&flag&empty=&m");
+
+        assert_eq!(qs.keys().collect::<Vec<_>>(), ["z", "a", "m", "flag", "empty"]);
+        assert_eq!(qs.iter().next(), Some(("z", &Value::Multiple(vec!["1", "4"]))));
+        assert_eq!(qs.get_all("z").collect::<Vec<_>>(), ["1", "4"]);
+        assert_eq!(qs.get_all("a").collect::<Vec<_>>(), ["3"]);
+        assert_eq!(qs.get_all("flag").count(), 0);
+        assert_eq!(qs.get_all("missing").count(), 0);
+        assert!(qs.contains_key("flag") && !qs.contains_key("missing"));
+
+        // a flag has no value, an empty value is still a value
+        assert!(qs.is_flag("flag"));
+        assert!(!qs.is_flag("empty"));
+        assert_eq!(qs.get("empty"), Some(&Value::One("")));
+
+        assert_eq!(qs.to_string(), "z=1&a&m=2&a=3&z=4&flag&empty=&m");
+        assert!(QueryString::from("").is_empty());
+    }
+
+    #[test]
+    fn flag_and_value() {
+        let qs = QueryString::from("a&b=1&a=3");
+
+        assert_eq!(qs.get("a"), Some(&Value::One("3")));
+        assert!(!qs.is_flag("a"));
+        assert_eq!(qs.pairs().collect::<Vec<_>>(), [("a", None), ("b", Some("1")), ("a", Some("3"))]);
+        assert_eq!(qs.to_string(), "a&b=1&a=3");
+        assert_eq!(QueryBuilder::from(Some(&qs)).to_string(), "a&b=1&a=3");
+    }
 }
\ No newline at end of file
@@ -1,7 +1,14 @@
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter, Result as FmtResult, Debug};
 use std::str;
-use super::{QueryString, Method, ParseError};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+use super::{QueryString, Method, ParseError, Headers, StatusCode};
+use super::query_string::Form;
+use super::multipart::{self, Multipart};
+use super::cookie;
 /*
 EXAMPLE HTTP REQUEST:
 
@@ -10,26 +17,125 @@ HEADERS \r\n
 BODY
 */
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Request<'rs> {
     path: &'rs str,
+    target: &'rs str,
+    version: &'rs str,
     method: Method,
     query: Option<QueryString<'rs>>,
-    body: Option<&'rs str>,
+    headers: Headers,
+    body: Option<&'rs [u8]>,
+    remote_addr: Option<SocketAddr>,
 }
 
 impl<'rs> Request<'rs> {
     pub fn path(&self) -> &str { self.path }
+    /// The same request with `prefix` removed from the path, for a [Router](super::Router) to hide where a handler is mounted.
+    /// The path is `/` when nothing is left
+    pub(super) fn without_prefix(&self, prefix: &str) -> Self {
+        let path = match self.path.strip_prefix(prefix) {
+            Some("") => "/",
+            Some(rest) => rest,
+            None => self.path
+        };
+        Self { path, ..self.clone() }
+    }
+    /// The path and query string exactly as they were sent, ex `/user?id=10`
+    pub fn target(&self) -> &str { self.target }
+    /// `HTTP/1.1` or `HTTP/1.0`
+    pub fn version(&self) -> &str { self.version }
     pub fn method(&self) -> &Method { &self.method }
-    pub fn query(&self) -> Option<&QueryString> { self.query.as_ref() }
-    pub fn body(&self) -> Option<&str> { self.body }
+    pub fn query(&self) -> Option<&QueryString<'_>> { self.query.as_ref() }
+    pub fn headers(&self) -> &Headers { &self.headers }
+    pub fn body(&self) -> Option<&[u8]> { self.body }
+    /// The body if it is valid UTF-8
+    pub fn body_str(&self) -> Option<&str> { self.body.and_then(|body| str::from_utf8(body).ok()) }
+    /// Address of the client that sent the request, if it came in over a socket
+    pub fn remote_addr(&self) -> Option<SocketAddr> { self.remote_addr }
+
+    /// The decoded fields of an `application/x-www-form-urlencoded` body. See [FormError::status()] for
+    /// what to answer when the request doesn't have one
+    pub fn form(&self) -> Result<Form, FormError> {
+        self.form_body().map(Form::from)
+    }
+
+    /// The body of a form as it was sent, still encoded
+    pub(super) fn form_body(&self) -> Result<&'rs str, FormError> {
+        let content_type = self.headers.get("Content-Type").unwrap_or("");
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        if !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return Err(FormError::UnsupportedMediaType);
+        }
+        str::from_utf8(self.body.unwrap_or(&[])).map_err(|_| FormError::InvalidEncoding)
+    }
+
+    /// A parser over the body of a `multipart/form-data` request. The whole body is read into memory before the handler
+    /// runs, so an upload can't be bigger than [RequestHandler::max_body_size()](super::RequestHandler::max_body_size)
+    /// or else [Server::set_max_body_size()](super::Server::set_max_body_size), 1 MiB by default. Raise it for the routes
+    /// that take files. [ParseError::InvalidBoundary] for other content types or a missing or invalid boundary
+    pub fn multipart(&self) -> Result<Multipart<&'rs [u8]>, ParseError> {
+        let content_type = self.headers.get("Content-Type").unwrap_or("");
+        let boundary = multipart::boundary(content_type).ok_or(ParseError::InvalidBoundary)?;
+        Multipart::new(self.body.unwrap_or(&[]), &boundary)
+    }
+
+    /// Every cookie from the `Cookie` headers by name, decoded, see [cookie::parse()]
+    pub fn cookies(&self) -> HashMap<String, String> {
+        cookie::parse(self.headers.get_all("Cookie"))
+    }
+
+    /// The value of the cookie `name`, decoded
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().remove(name)
+    }
+
+    pub(super) fn set_remote_addr(&mut self, addr: Option<SocketAddr>) {
+        self.remote_addr = addr;
+    }
+}
+
+/// Why [Request::form()] couldn't read a form from the request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormError {
+    /// The `Content-Type` isn't `application/x-www-form-urlencoded`
+    UnsupportedMediaType,
+    /// The body isn't UTF-8
+    InvalidEncoding,
+}
+
+impl FormError {
+    /// 415 Unsupported Media Type or 400 Bad Request
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+            Self::InvalidEncoding => StatusCode::BadRequest
+        }
+    }
+}
+
+impl Display for FormError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::UnsupportedMediaType => write!(f, "Expected an application/x-www-form-urlencoded body"),
+            Self::InvalidEncoding => write!(f, "The form isn't valid UTF-8")
+        }
+    }
+}
+
+impl Error for FormError {}
+
+impl From<FormError> for io::Error {
+    fn from(e: FormError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
 }
 
 /**
  * Gets the next word in the string, returning a slice of the word as well as a slice of the remaining string
  */
 fn get_next_word(input: &str) -> Option<(&str,&str)> {
-    for (i, c) in input.chars().enumerate() {
+    for (i, c) in input.char_indices() {
         if c == ' ' || c == '\r' { 
             // &input[i+1..] is NOT adding one char, it is adding one BYTE
             // this could generate invalid UTF8
@@ -40,6 +146,100 @@ fn get_next_word(input: &str) -> Option<(&str,&str)> {
     None
 }
 
+/**
+ * Parses `Name: value\r\n` lines up to the blank line that ends the header section,
+ * returning the headers as well as a slice of whatever comes after them
+ */
+pub(super) fn parse_headers(mut input: &str) -> Result<(Headers, &str), ParseError> {
+    let mut headers = Headers::new();
+
+    loop {
+        let (line, rest) = match input.find("\r\n") {
+            Some(i) => (&input[..i], &input[i+2..]),
+            // no blank line, the request ended inside the header section
+            None => (input, "")
+        };
+        input = rest;
+
+        if line.is_empty() {
+            return Ok((headers, input));
+        }
+
+        let (name, value) = line.split_once(':').ok_or(ParseError::InvalidHeader)?;
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(ParseError::InvalidHeader);
+        }
+        headers.add(name, value.trim());
+    }
+}
+
+impl<'rs> TryFrom<&'rs [u8]> for Request<'rs> {
+    type Error = ParseError;
+
+    fn try_from(bytes: &'rs [u8]) -> Result<Self, Self::Error> {
+        // the request line and headers have to be text, the body can be anything
+        let (head, body) = match bytes.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(i) => (&bytes[..i+4], &bytes[i+4..]),
+            None => (bytes, &bytes[bytes.len()..])
+        };
+        let request: &str = str::from_utf8(head)?;
+
+        // let (method, request) = match get_next_word(request) {
+        //     Some(result) => result,
+        //     None => return Err(ParseError::InvalidRequest)
+        // }
+
+        // you can use ? though
+        
+        // GET /user?id=10 HTTP/1.1\r\n
+        let (method, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
+        let (target, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
+        let (protocol, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
+
+        if protocol != "HTTP/1.1" && protocol != "HTTP/1.0" {
+            return Err(ParseError::InvalidProtocol);
+        }
+
+        // get_next_word stops on the \r, the \n is still there
+        let request = request.strip_prefix('\n').unwrap_or(request);
+        let (headers, _) = parse_headers(request)?;
+
+        let method: Method = method.parse()?;
+        let mut path = target;
+        let mut query = None;
+        if let Some(i) = path.find('?') {
+            // we know '?' is 1 byte so [i+1] is ok
+            query = Some(QueryString::from(&path[i+1..]));
+            path = &path[..i];
+        }
+
+        Ok( Self { 
+            path, 
+            target,
+            version: protocol,
+            method, 
+            query, 
+            headers,
+            body: if body.is_empty() { None } else { Some(body) },
+            remote_addr: None
+        })
+    }
+}
+
+impl<'rs> Display for Request<'rs> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let body = match self.body {
+            None => Str!("NONE"),
+            Some(body) => String::from_utf8_lossy(body).to_string()
+        };
+        let query = match &self.query {
+            None => Str!("NONE"),
+            Some(qs) => qs.to_string()
+        };
+        write!(f, "PATH: \"{}\"\nMETHOD: {}\nQUERY:\n{}\nHEADERS:\n{}BODY\n=====\n{}\n", self.path, self.method, query, self.headers, body)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::http::query_string::Value;
@@ -47,6 +247,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(unused_mut, clippy::needless_borrow, clippy::unnecessary_mut_passed)]
     fn next_word() {
         let mut my_str = "this is my\rstring ";
         let (word,mut my_str) = get_next_word(&mut my_str).expect("no next word!");
@@ -79,7 +280,7 @@ mod tests {
 
     #[test]
     fn valid_request() {
-        let req = Request::try_from("GET /user?id=10 HTTP/1.1\r\nTODO: HEADERS\r\nNice Body".as_bytes()).expect("Request failed to parse");
+        let req = Request::try_from("GET /user?id=10 HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\n\r\nNice Body".as_bytes()).expect("Request failed to parse");
 
         assert_eq!(req.method, Method::GET);
         assert_eq!(req.path, "/user");
@@ -96,57 +297,58 @@ mod tests {
             Value::None => panic!("user has no value")
         }
 
-        
-        assert_eq!(req.body, None); // TODO!!! 
+        assert_eq!(req.headers.get("host"), Some("localhost"));
+        assert_eq!(req.headers.get("Accept-Encoding"), Some("gzip"));
+        assert_eq!(req.target, "/user?id=10");
+        assert_eq!(req.body, Some("Nice Body".as_bytes()));
     }
-}
 
-impl<'rs> TryFrom<&'rs [u8]> for Request<'rs> {
-    type Error = ParseError;
+    #[test]
+    fn http_1_0() {
+        let req = Request::try_from("GET / HTTP/1.0\r\n\r\n".as_bytes()).expect("Request failed to parse");
+        assert_eq!(req.version(), "HTTP/1.0");
+        assert!(req.headers().is_empty());
+    }
 
-    fn try_from(bytes: &'rs [u8]) -> Result<Self, Self::Error> {
-        let request: &str = str::from_utf8(bytes)?;
+    #[test]
+    fn invalid_header() {
+        match Request::try_from("GET / HTTP/1.1\r\nNo colon here\r\n\r\n".as_bytes()) {
+            Err(e) => assert_eq!(e, ParseError::InvalidHeader),
+            Ok(req) => panic!("Invalid header came back valid {:?}", req)
+        }
+    }
 
-        // let (method, request) = match get_next_word(request) {
-        //     Some(result) => result,
-        //     None => return Err(ParseError::InvalidRequest)
-        // }
+    #[test]
+    fn binary_body() {
+        let mut raw = b"POST /upload HTTP/1.1\r\nContent-Length: 4\r\n\r\n".to_vec();
+        raw.extend_from_slice(&[0xff, 0x00, 0xfe, 0x01]);
+        let req = Request::try_from(&raw[..]).expect("Request failed to parse");
+        assert_eq!(req.body(), Some(&[0xff, 0x00, 0xfe, 0x01][..]));
+        assert_eq!(req.body_str(), None);
+    }
 
-        // you can use ? though
-        
-        // GET /user?id=10 HTTP/1.1\r\n
-        let (method, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
-        let (mut path, request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
-        let (protocol, _request) = get_next_word(request).ok_or(ParseError::InvalidRequest)?;
+    #[test]
+    fn form() {
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: Application/X-WWW-Form-Urlencoded; charset=UTF-8\r\n\r\nname=Jo+Smith&news&note=";
+        let req = Request::try_from(&raw[..]).expect("Request failed to parse");
+        let form = req.form().expect("not a form");
+        assert_eq!(form.get_all("name").collect::<Vec<_>>(), ["Jo Smith"]);
+        assert!(form.is_flag("news"));
+        assert_eq!(form.get_all("note").collect::<Vec<_>>(), [""]);
 
-        if protocol != "HTTP/1.1" {
-            return Err(ParseError::InvalidProtocol);
-        }
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nb=1&a%26b=x%3Dy&b=2";
+        let form = Request::try_from(&raw[..]).unwrap().form().unwrap();
+        assert_eq!(form.iter().collect::<Vec<_>>(), [("b", Some("1")), ("a&b", Some("x=y")), ("b", Some("2"))]);
+        assert_eq!(form.get("b"), Some("1"));
 
-        let method: Method = method.parse()?;
-        let mut query = None;
-        if let Some(i) = path.find('?') {
-            // we know '?' is 1 byte so [i+1] is ok
-            query = Some(QueryString::from(&path[i+1..]));
-            path = &path[..i];
-        }
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\n";
+        assert!(Request::try_from(&raw[..]).unwrap().form().is_ok_and(|form| form.is_empty()));
 
-        Ok( Self { 
-            path: path, 
-            method, 
-            query, 
-            body: None 
-        })
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: text/plain\r\n\r\nname=Jo";
+        let err = Request::try_from(&raw[..]).unwrap().form().unwrap_err();
+        assert_eq!((err, err.status()), (FormError::UnsupportedMediaType, StatusCode::UnsupportedMediaType));
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nname=\xff";
+        let err = Request::try_from(&raw[..]).unwrap().form().unwrap_err();
+        assert_eq!((err, err.status()), (FormError::InvalidEncoding, StatusCode::BadRequest));
     }
 }
-
-impl<'rs> Display for Request<'rs> {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        let body = self.body.unwrap_or("NONE");
-        let query = match &self.query {
-            None => Str!("NONE"),
-            Some(qs) => qs.to_string()
-        };
-        write!(f, "PATH: \"{}\"\nMETHOD: {}\nQUERY:\n{}\nBODY\n=====\n{}\n", self.path, self.method, query, body)
-    }
-}
\ No newline at end of file
@@ -1,6 +1,6 @@
 use std::io::Result as IoResult;
 
-use super::{ Method, Request, Response };
+use super::{ Method, Request, Response, StatusCode };
 
 
 pub trait RequestHandler { 
@@ -24,13 +24,17 @@ pub trait RequestHandler {
     }
 
     fn handle_bad(&self, res: &mut Response, body: &str) -> IoResult<()> {
-        if let Err(e) = res.bad_request(Some(format!("<h1>400 Bad Request</h1><p>{}</p>", body))) {
+        if let Err(e) = res.send_error(StatusCode::BadRequest, body) {
             eprintln!("Sending 400 response failed with error {}", e);
             return Err(e);
         }
         Ok(())
     }
     
+    /// The largest body accepted for `req`, decided from its head before the body is read.
+    /// None uses the server's limit, see [Server::set_max_body_size()](super::Server::set_max_body_size)
+    fn max_body_size(&self, _req: &Request) -> Option<usize> { None }
+
     fn get(&self, _req: &Request, res: &mut Response) -> IoResult<()> { res.send_404() }
     fn delete(&self, _req: &Request, res: &mut Response) -> IoResult<()> { res.send_404() }
     fn post(&self, _req: &Request, res: &mut Response) -> IoResult<()> { res.send_404() }
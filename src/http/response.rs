@@ -1,97 +1,263 @@
-use super::StatusCode;
+use super::{StatusCode, Headers, Compression, ErrorPages, chunked_writer::ChunkedWriter, cookie::Cookie};
 use std::{
     io::{ Write, Result as IoResult},
     fmt::{
         Formatter, Debug, Display,
         Result as FmtResult
     },
+    net::TcpStream,
     rc::Rc,
-    cell::RefCell
+    cell::RefCell,
+    sync::Arc
 };
 
 pub struct Response {
     pub status: StatusCode,
-    pub body: Option<String>,
-    writer: Rc<RefCell<dyn Write>>
+    pub body: Option<Vec<u8>>,
+    pub headers: Headers,
+    writer: Rc<RefCell<dyn Write>>,
+    compression: Option<Compression>,
+    accept_encoding: Option<String>,
+    error_pages: Option<Arc<ErrorPages>>,
+    accept: Option<String>,
+    /// The connection the response is written to, when it is a socket. Taken by protocol upgrades like WebSockets
+    connection: Option<TcpStream>,
+    /// The HTTP version of the request, [Response::stream()] can't use chunks for HTTP/1.0 clients
+    client_version: Option<String>,
+    /// A code and reason [StatusCode] may not list, from [Response::set_raw_status()]
+    raw_status: Option<(u16, String)>,
+    /// Set by [Response::set_omit_body()]
+    omit_body: bool,
 }
 
 impl Display for Response {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        let body = self.body.as_deref().unwrap_or("");
+        let body = String::from_utf8_lossy(self.body.as_deref().unwrap_or(&[]));
         write!(f, "Status: {}, Body: {}", self.status, body)
     }
 }
 impl Debug for Response {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        write!(f, "Response {{ Status: {:?}, Body: {:?} }}", self.status, self.body)
+        let body = self.body.as_deref().map(String::from_utf8_lossy);
+        write!(f, "Response {{ Status: {:?}, Headers: {:?}, Body: {:?} }}", self.status, self.headers, body)
     }
 }
 
 impl Response {
     /// Creates a new [Response] with an empty body 
     pub fn new(writer: Rc<RefCell<dyn Write>>) -> Self {
-        Self { status: StatusCode::Ok, body: None, writer }
+        Self { 
+            status: StatusCode::Ok, 
+            body: None, 
+            headers: Headers::new(), 
+            writer, 
+            compression: None, 
+            accept_encoding: None,
+            error_pages: None,
+            accept: None,
+            connection: None,
+            client_version: None,
+            raw_status: None,
+            omit_body: false
+        }
     }
     
     pub fn writer(&self) -> Rc<RefCell<dyn Write>> { self.writer.clone() }
 
+    pub(super) fn set_connection(&mut self, connection: TcpStream) {
+        self.connection = Some(connection);
+    }
+
+    pub(super) fn set_client_version(&mut self, version: &str) {
+        self.client_version = Some(Str!(version));
+    }
+
+    /// Takes the underlying socket so it can outlive the request, [None] if the response isn't
+    /// written to a socket (ex in a [TestClient](super::test_client::TestClient)) or it was already taken.
+    /// The server's [timeout](super::Server::set_timeout) is removed, it is only meant for the request
+    pub fn take_connection(&mut self) -> Option<TcpStream> {
+        let connection = self.connection.take()?;
+        let _ = connection.set_read_timeout(None).and_then(|_| connection.set_write_timeout(None));
+        Some(connection)
+    }
+
+    /// Makes [Response::send()] write everything but the body, `Content-Length` still counts it.
+    /// Answers a HEAD request with the headers the same GET would get
+    pub fn set_omit_body(&mut self, omit_body: bool) -> &mut Self {
+        self.omit_body = omit_body;
+        self
+    }
+
+    /// Sets the header `name`, replacing any previous value
+    pub fn set_header(&mut self, name: &str, value: impl Into<String>) -> &mut Self {
+        self.headers.set(name, value);
+        self
+    }
+
+    /// Adds a `Set-Cookie` header for `cookie`, keeping any cookies set before it
+    pub fn set_cookie(&mut self, cookie: &Cookie) -> &mut Self {
+        self.headers.add("Set-Cookie", cookie.to_string());
+        self
+    }
+
+    /// Compresses the body when it is sent, if the client's `Accept-Encoding` allows it
+    pub fn compress(&mut self, compression: Compression, accept_encoding: Option<&str>) -> &mut Self {
+        self.compression = Some(compression);
+        self.accept_encoding = accept_encoding.map(String::from);
+        self
+    }
+
+    /// Renders error responses with `pages`, as JSON if the client's `Accept` header asks for it
+    pub fn use_error_pages(&mut self, pages: Arc<ErrorPages>, accept: Option<&str>) -> &mut Self {
+        self.error_pages = Some(pages);
+        self.accept = accept.map(String::from);
+        self
+    }
+
     pub fn ok(&mut self, body: Option<String>) -> IoResult<()> {
         self.status = StatusCode::Ok;
-        self.body = body;
+        self.body = body.map(String::into_bytes);
         self.send()
     }
     pub fn bad_request(&mut self, body: Option<String>) -> IoResult<()> {
         self.status = StatusCode::BadRequest;
-        self.body = body;
+        self.body = body.map(String::into_bytes);
         self.send()
     }
 
     pub fn not_found(&mut self, body: Option<String>) -> IoResult<()> {
         self.status = StatusCode::NotFound;
-        self.body = body;
+        self.body = body.map(String::into_bytes);
         self.send()
     }
 
     
     
+    /// Sends a 302 pointing the client at `location`
+    pub fn redirect(&mut self, location: &str) -> IoResult<()> {
+        self.status = StatusCode::Found;
+        self.body = None;
+        self.set_header("Location", location).send()
+    }
+
     /// Generates a 403 using [Response::gen_403()] and sends it
     pub fn send_403(&mut self) -> IoResult<()> {
         self.gen_403().send()
     }
 
-    /// Sends a 403 with a generic HTML body
+    /// Sets a 403 with a generic error page
     pub fn gen_403(&mut self) -> &mut Self {
-        self.status = StatusCode::PermissionDenied;
-        self.body = some_str!("<h1>403 Permission Denied</h1><p>You do not have permission to access the requested resource.</p>");
-        self
+        self.gen_error(StatusCode::PermissionDenied, "You do not have permission to access the requested resource.")
     }
 
-    /// Sends a 404 with a generic HTML body
+    /// Sets a 404 with a generic error page
     pub fn gen_404(&mut self) -> &mut Self {
-        self.status = StatusCode::NotFound;
-        self.body = some_str!("<h1>404 Not Found</h1><p>The page you requested could not be found on this server.</p>");
+        self.gen_error(StatusCode::NotFound, "The page you requested could not be found on this server.")
+    }
+
+    /// Sets `status` and renders its error page with `detail` as the message, see [ErrorPages::render()]
+    pub fn gen_error(&mut self, status: StatusCode, detail: &str) -> &mut Self {
+        let default_pages = ErrorPages::new();
+        let pages = self.error_pages.as_deref().unwrap_or(&default_pages);
+        let (content_type, body) = pages.render(status, detail, self.accept.as_deref());
+
+        self.status = status;
+        self.headers.set("Content-Type", content_type);
+        self.body = Some(body.into_bytes());
         self
     }
+
+    /// Generates an error page using [Response::gen_error()] and sends it
+    pub fn send_error(&mut self, status: StatusCode, detail: &str) -> IoResult<()> {
+        self.gen_error(status, detail).send()
+    }
     
     /// Generates a 404 using [Response::gen_404()] and sends it
     pub fn send_404(&mut self) -> IoResult<()> {
         self.gen_404().send()
     }
 
-    /// If [self.body](Option<String>) is [Some] then appends [str](String) to it, otherwise
+    /// Replaces the body, used for anything that isn't text like images
+    pub fn set_body(&mut self, body: impl Into<Vec<u8>>) -> &mut Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// If [self.body](Option<Vec<u8>>) is [Some] then appends [str](String) to it, otherwise
     /// if it simply sets it to [str](String)
     pub fn append(&mut self, str:String) -> &mut Self {
-        self.body = match &self.body {
-            Some(cur) => Some(format!("{}{}", cur, str)),
-            None => Some(str)
+        match &mut self.body {
+            Some(cur) => cur.extend_from_slice(str.as_bytes()),
+            None => self.body = Some(str.into_bytes())
         };
         self
     }
 
+    /// Sends `code` and `reason` as they are, for codes [StatusCode] doesn't list like a proxied 418.
+    /// [Response::status] is set to the closest known code (418 becomes 400) for everything that checks it,
+    /// giving `status` a different value afterwards goes back to the usual status line. Codes outside 1xx-5xx are ignored
+    pub fn set_raw_status(&mut self, code: u16, reason: &str) -> &mut Self {
+        if let Some(status) = StatusCode::from_code_or_class(code) {
+            self.status = status;
+            self.raw_status = Some((code, reason.chars().filter(|c| !c.is_control()).collect()));
+        }
+        self
+    }
+
+    fn status_line(&self) -> String {
+        match &self.raw_status {
+            Some((code, reason)) if StatusCode::from_code_or_class(*code) == Some(self.status) => format!("HTTP/1.1 {} {}", code, reason),
+            _ => format!("HTTP/1.1 {} {}", self.status.code(), self.status)
+        }
+    }
+
+    /// Writes only the status line and headers, for handlers that write the body to [Response::writer()] themselves.
+    /// Nothing is added to the headers, so the framing (`Content-Length` etc) is up to the caller
+    pub fn send_head(&mut self) -> IoResult<()> {
+        let mut writer = self.writer.borrow_mut();
+        write!(writer, "{}\r\n{}\r\n", self.status_line(), self.headers)?;
+        writer.flush()
+    }
+
+    /// Sends the status line and headers and returns a writer for a body of unknown length.
+    /// Each write is sent as a `Transfer-Encoding: chunked` chunk, or as is when the client only speaks HTTP/1.0,
+    /// in which case the body ends when the connection closes. Any [Response::body] is ignored and nothing is compressed.
+    /// Announce trailers with a `Trailer` header before calling this, see [ChunkedWriter::set_trailer()]
+    pub fn stream(&mut self) -> IoResult<ChunkedWriter> {
+        let chunked = self.client_version.as_deref() != Some("HTTP/1.0");
+        self.headers.remove("Content-Length");
+        if chunked {
+            self.headers.set("Transfer-Encoding", "chunked");
+        } else {
+            self.headers.remove("Transfer-Encoding");
+            self.headers.set("Connection", "close");
+        }
+        self.send_head()?;
+        Ok(ChunkedWriter::new(self.writer.clone(), chunked))
+    }
+
+    /// Writes the status line, headers and body to the writer. `Content-Length` is set for everything but a 304,
+    /// and bodies without a `Content-Type` are assumed to be HTML
     pub fn send(&mut self) -> IoResult<()> {
-        let body = self.body.as_deref().unwrap_or("");
+        let mut body = self.body.clone().unwrap_or_default();
 
-        write!(self.writer.borrow_mut(),"HTTP/1.1 {} {}\r\nTODO: HEADERS\r\n\r\n{}",self.status.code(),self.status,body)
+        if !body.is_empty() && !self.headers.contains("Content-Type") {
+            self.headers.set("Content-Type", "text/html; charset=utf-8");
+        }
+        if let Some(compression) = &self.compression {
+            body = compression.apply(self.accept_encoding.as_deref(), &mut self.headers, body)?;
+        }
+        // a 304 has no body, its Content-Length would describe the cached one
+        if !matches!(self.status, StatusCode::NotModified) {
+            self.headers.set("Content-Length", body.len().to_string());
+        }
+
+        let mut writer = self.writer.borrow_mut();
+        write!(writer, "{}\r\n{}\r\n", self.status_line(), self.headers)?;
+        if !self.omit_body {
+            writer.write_all(&body)?;
+        }
+        writer.flush()
     }
 }
 
@@ -101,7 +267,7 @@ fn test_response() {
     let b = Rc::new(RefCell::new(Vec::<u8>::new()));
     let mut res = Response::new(b.clone());
 
-    if let Err(e) = res.gen_404().append(Str!("Apples")).send() {
+    if let Err(e) = res.append(Str!("<p>Apples</p>")).append(Str!("<p>Bananas</p>")).send() {
         panic!("error writing to buffer, {}", e);
     }
 
@@ -110,6 +276,95 @@ fn test_response() {
     println!("buffer: {:?}", buf_str);
     assert_eq!(
         buf_str, 
-        "HTTP/1.1 404 Not Found\r\nTODO: HEADERS\r\n\r\n<h1>404 Not Found</h1><p>The page you requested could not be found on this server.</p>Apples"
+        "HTTP/1.1 200 Ok\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: 27\r\n\r\n<p>Apples</p><p>Bananas</p>"
     );
-} 
\ No newline at end of file
+
+    // appending to a generated page adds to the end of the whole document
+    let b = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut res = Response::new(b.clone());
+
+    if let Err(e) = res.gen_404().append(Str!("Apples")).send() {
+        panic!("error writing to buffer, {}", e);
+    }
+
+    let body_len = res.body.as_ref().map_or(0, Vec::len);
+    let buf_str = String::from_utf8_lossy(&b.borrow()).to_string();
+    assert!(buf_str.starts_with(&format!("HTTP/1.1 404 Not Found\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\r\n", body_len)));
+    assert!(buf_str.ends_with("<p>The page you requested could not be found on this server.</p></body></html>Apples"));
+}
+
+#[test]
+#[cfg(test)]
+fn test_error_response() {
+    let b = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut res = Response::new(b.clone());
+
+    if let Err(e) = res.send_404() {
+        panic!("error writing to buffer, {}", e);
+    }
+    let buf_str = String::from_utf8_lossy(&b.borrow()).to_string();
+    assert!(buf_str.starts_with("HTTP/1.1 404 Not Found\r\nContent-Type: text/html; charset=utf-8\r\n"));
+    assert!(buf_str.ends_with("<h1>404 Not Found</h1><p>The page you requested could not be found on this server.</p></body></html>"));
+
+    let b = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut res = Response::new(b.clone());
+    res.use_error_pages(Arc::new(ErrorPages::new()), Some("application/json"));
+
+    if let Err(e) = res.send_error(StatusCode::BadRequest, "Apples") {
+        panic!("error writing to buffer, {}", e);
+    }
+    let buf_str = String::from_utf8_lossy(&b.borrow()).to_string();
+    assert!(buf_str.starts_with("HTTP/1.1 400 Bad Request\r\nContent-Type: application/json\r\n"));
+    assert!(buf_str.ends_with(r#"{"status":400,"reason":"Bad Request","detail":"Apples"}"#));
+}
+
+#[test]
+#[cfg(test)]
+fn test_compressed_response() {
+    let b = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut res = Response::new(b.clone());
+
+    let body = "<p>Bananas</p>".repeat(100);
+    res.compress(Compression::default(), Some("deflate"));
+    if let Err(e) = res.ok(Some(body)) {
+        panic!("error writing to buffer, {}", e);
+    }
+
+    assert_eq!(res.headers.get("Content-Encoding"), Some("deflate"));
+    assert_eq!(res.headers.get("Vary"), Some("Accept-Encoding"));
+    let length: usize = res.headers.get("Content-Length").expect("no content length").parse().unwrap();
+    assert!(length < 1400);
+}
+
+#[test]
+#[cfg(test)]
+fn test_streamed_response() {
+    use std::io::Write;
+
+    let b = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut res = Response::new(b.clone());
+    res.set_header("Content-Type", "text/plain").set_header("Trailer", "X-Count");
+
+    let mut writer = res.stream().expect("error writing to buffer");
+    writer.write_all(b"Apples").unwrap();
+    writer.write_all(b"").unwrap();
+    write!(writer, "{} Bananas", 12).unwrap();
+    writer.set_trailer("X-Count", "2");
+    writer.finish().expect("error writing to buffer");
+
+    assert_eq!(
+        String::from_utf8_lossy(&b.borrow()),
+        "HTTP/1.1 200 Ok\r\nContent-Type: text/plain\r\nTrailer: X-Count\r\nTransfer-Encoding: chunked\r\n\r\n\
+        6\r\nApples\r\nA\r\n12 Bananas\r\n0\r\nX-Count: 2\r\n\r\n"
+    );
+
+    let b = Rc::new(RefCell::new(Vec::<u8>::new()));
+    let mut res = Response::new(b.clone());
+    res.set_client_version("HTTP/1.0");
+    let mut writer = res.stream().expect("error writing to buffer");
+    writer.write_all(b"until the end").unwrap();
+    writer.set_trailer("X-Count", "2");
+    drop(writer);
+
+    assert_eq!(String::from_utf8_lossy(&b.borrow()), "HTTP/1.1 200 Ok\r\nConnection: close\r\n\r\nuntil the end");
+}
@@ -0,0 +1,143 @@
+use std::io::Result as IoResult;
+
+use super::{Request, Response, RequestHandler};
+
+/// Dispatches requests to the handler mounted on the longest matching path prefix.
+/// A prefix only matches whole path segments, `/static` matches `/static/app.js` but not `/statics`.
+/// The handler sees [Request::path()] without the prefix, `/static/app.js` is `/app.js` and `/static` is `/`,
+/// while [Request::target()] stays as it was sent
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<(String, Box<dyn RequestHandler + Send + Sync>)>,
+    body_limits: Vec<(String, usize)>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self { routes: Vec::new(), body_limits: Vec::new() }
+    }
+
+    pub fn mount(&mut self, prefix: &str, handler: impl RequestHandler + Send + Sync + 'static) -> &mut Self {
+        let prefix = prefix.trim_end_matches('/');
+        self.routes.push((Str!(prefix), Box::new(handler)));
+        // longest prefix first so the most specific mount wins
+        self.routes.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        self
+    }
+
+    /// Sets the largest body accepted for requests under `prefix`, overriding the server's limit.
+    /// Like mounts, the longest matching prefix wins
+    pub fn set_max_body_size(&mut self, prefix: &str, max: usize) -> &mut Self {
+        self.body_limits.push((Str!(prefix.trim_end_matches('/')), max));
+        self.body_limits.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        self
+    }
+
+    /// The handler for `req` and the request it should see, with the prefix it is mounted on removed from the path
+    fn route<'rs>(&self, req: &Request<'rs>) -> Option<(&(dyn RequestHandler + Send + Sync), Request<'rs>)> {
+        let (prefix, handler) = self.routes.iter().find(|(prefix, _)| matches_prefix(prefix, req.path()))?;
+        Some((handler.as_ref(), req.without_prefix(prefix)))
+    }
+}
+
+/// Whether `path` is `prefix` or somewhere under it. `prefix` has no trailing slash, so `""` is the root
+pub fn matches_prefix(prefix: &str, path: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false
+    }
+}
+
+impl RequestHandler for Router {
+    fn handle(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+        match self.route(req) {
+            Some((handler, req)) => handler.handle(&req, res),
+            None => res.send_404()
+        }
+    }
+
+    fn max_body_size(&self, req: &Request) -> Option<usize> {
+        self.body_limits.iter()
+            .find(|(prefix, _)| matches_prefix(prefix, req.path()))
+            .map(|(_, max)| *max)
+            .or_else(|| self.route(req).and_then(|(handler, req)| handler.max_body_size(&req)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{rc::Rc, cell::RefCell};
+
+    struct Named(&'static str);
+
+    impl RequestHandler for Named {
+        fn get(&self, _req: &Request, res: &mut Response) -> IoResult<()> {
+            res.ok(some_str!(self.0))
+        }
+    }
+
+    fn get(router: &Router, path: &str) -> Response {
+        let raw = format!("GET {} HTTP/1.1\r\n\r\n", path);
+        let req = Request::try_from(raw.as_bytes()).expect("Request failed to parse");
+        let mut res = Response::new(Rc::new(RefCell::new(Vec::<u8>::new())));
+        router.handle(&req, &mut res).expect("Router failed");
+        res
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let mut router = Router::new();
+        router.mount("/", Named("root")).mount("/static/", Named("static")).mount("/static/img", Named("img"));
+
+        assert_eq!(get(&router, "/").body.as_deref(), Some("root".as_bytes()));
+        assert_eq!(get(&router, "/statics").body.as_deref(), Some("root".as_bytes()));
+        assert_eq!(get(&router, "/static").body.as_deref(), Some("static".as_bytes()));
+        assert_eq!(get(&router, "/static/app.js").body.as_deref(), Some("static".as_bytes()));
+        assert_eq!(get(&router, "/static/img/a.png").body.as_deref(), Some("img".as_bytes()));
+    }
+
+    #[test]
+    fn body_limits() {
+        struct Limited;
+        impl RequestHandler for Limited {
+            fn max_body_size(&self, _req: &Request) -> Option<usize> { Some(10) }
+        }
+
+        let mut router = Router::new();
+        router.mount("/", Named("root")).mount("/limited", Limited).set_max_body_size("/upload/", 500).set_max_body_size("/upload/small", 5);
+
+        let limit = |path: &str| {
+            let raw = format!("POST {} HTTP/1.1\r\n\r\n", path);
+            router.max_body_size(&Request::try_from(raw.as_bytes()).unwrap())
+        };
+        assert_eq!(limit("/"), None);
+        assert_eq!(limit("/upload"), Some(500));
+        assert_eq!(limit("/upload/small/a"), Some(5));
+        assert_eq!(limit("/limited/a"), Some(10));
+    }
+
+    #[test]
+    fn prefix_removed() {
+        struct Path;
+        impl RequestHandler for Path {
+            fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+                res.ok(Some(format!("{} {}", req.path(), req.target())))
+            }
+        }
+
+        let mut router = Router::new();
+        router.mount("/", Path).mount("/static/", Path);
+
+        assert_eq!(get(&router, "/static/app.js?v=1").body.as_deref(), Some("/app.js /static/app.js?v=1".as_bytes()));
+        assert_eq!(get(&router, "/static").body.as_deref(), Some("/ /static".as_bytes()));
+        assert_eq!(get(&router, "/other").body.as_deref(), Some("/other /other".as_bytes()));
+    }
+
+    #[test]
+    fn no_route() {
+        let mut router = Router::new();
+        router.mount("/api", Named("api"));
+        assert_eq!(get(&router, "/").status.code(), 404);
+    }
+}
@@ -1,21 +1,117 @@
 use std::{
-    io::{Read, Result as IoResult},
-    net::{TcpListener, TcpStream},
+    io::{ErrorKind, Read, Write, Result as IoResult},
+    net::{TcpListener, TcpStream, SocketAddr},
     rc::Rc,
     cell::RefCell,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, AtomicU64, Ordering},
+        mpsc::{self, SyncSender}
+    },
     thread,
-    num::NonZeroUsize,
-    sync::Arc
+    time::Duration
 };
 use rayon::{ThreadPoolBuilder, ThreadPool};
 
-use super::{Request, Response, RequestHandler};
+use super::{Request, Response, RequestHandler, StatusCode, Compression, ErrorPages, http2};
+#[cfg(feature = "async")]
+use super::async_server::{self, AsyncRequestHandler};
 
 pub struct Server {
     ip: String,
     port: u16,
     listener: TcpListener,
-    thread_pool: ThreadPool
+    thread_pool: Arc<ThreadPool>,
+    options: ResponseOptions,
+    limits: Limits,
+    metrics: Arc<Metrics>
+}
+
+/// How many rejected connections can wait for their 503 before further ones are just closed
+const REJECT_QUEUE: usize = 64;
+/// Request lines and headers bigger than this are answered with [RequestHandler::handle_bad()]
+pub(super) const MAX_HEAD_SIZE: usize = 16 * 1024;
+/// How much of a refused body is read and thrown away before closing, so the client gets to read the response
+pub(super) const LINGER_LIMIT: usize = 1024 * 1024;
+
+/// When to turn connections away instead of queueing them for the pool, and how long to wait on them
+#[derive(Clone)]
+struct Limits {
+    max_queued: Option<usize>,
+    max_connections: Option<usize>,
+    retry_after: Duration,
+    max_body_size: usize,
+    timeout: Option<Duration>,
+}
+
+/// Counts of the connections going through the thread pool, see [Server::metrics()]
+#[derive(Debug, Default)]
+pub struct Metrics {
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl Metrics {
+    /// Connections waiting for a free worker
+    pub fn queue_depth(&self) -> usize { self.queued.load(Ordering::SeqCst) }
+    /// Connections a worker is handling right now
+    pub fn active(&self) -> usize { self.active.load(Ordering::SeqCst) }
+    /// Connections handed to the pool since the server started
+    pub fn accepted(&self) -> u64 { self.accepted.load(Ordering::SeqCst) }
+    /// Connections turned away because the server was at its limits
+    pub fn rejected(&self) -> u64 { self.rejected.load(Ordering::SeqCst) }
+}
+
+/// Settings applied to every [Response] before it is given to the handler
+#[derive(Clone, Default)]
+pub struct ResponseOptions {
+    pub compression: Option<Compression>,
+    pub error_pages: Option<Arc<ErrorPages>>
+}
+
+impl ResponseOptions {
+    /// Applies the options to `response`, negotiating with the headers of `req` if it parsed
+    pub fn prepare(&self, response: &mut Response, req: Option<&Request>) {
+        let header = |name| req.and_then(|req| req.headers().get(name));
+
+        if let Some(req) = req {
+            response.set_client_version(req.version());
+        }
+        if let Some(pages) = &self.error_pages {
+            response.use_error_pages(pages.clone(), header("Accept"));
+        }
+        if let Some(compression) = &self.compression {
+            response.compress(compression.clone(), header("Accept-Encoding"));
+        }
+        // one request per connection, let clients know not to reuse it
+        response.set_header("Connection", "close");
+    }
+}
+
+/// Parses `bytes` and hands the request to `handler`, or to [RequestHandler::handle_bad()] if it didn't parse.
+/// This is everything the server does with a connection after reading from it
+pub fn respond(
+    handler: &impl RequestHandler,
+    bytes: &[u8],
+    remote_addr: Option<SocketAddr>,
+    response: &mut Response,
+    options: &ResponseOptions
+) -> IoResult<()> {
+    match Request::try_from(bytes) {
+        Ok(mut req) => {
+            req.set_remote_addr(remote_addr);
+            println!("Recieved a request: {:?}", req);
+            options.prepare(response, Some(&req));
+            handler.handle(&req, response)
+        },
+        Err(e) => {
+            eprintln!("Error converting bytes to result: {}", e);
+            options.prepare(response, None);
+            handler.handle_bad(response, &e.to_string())
+        }
+    }
 }
 
 impl Server {
@@ -24,12 +120,82 @@ impl Server {
             listener: TcpListener::bind(format!("{}:{}", &ip, port)).expect("Port is already in use"),
             ip,
             port,
-            thread_pool: ThreadPoolBuilder::new().build().expect("Thread pool failed to build!!!")
+            thread_pool: Arc::new(ThreadPoolBuilder::new().build().expect("Thread pool failed to build!!!")),
+            options: ResponseOptions::default(),
+            limits: Limits {
+                max_queued: None,
+                max_connections: None,
+                retry_after: Duration::from_secs(1),
+                max_body_size: 1024 * 1024,
+                timeout: Some(Duration::from_secs(30))
+            },
+            metrics: Arc::new(Metrics::default())
         }
     }
 
+    /// Sets how many threads handle connections, by default there is one per CPU
+    pub fn set_workers(&mut self, workers: usize) -> &mut Self {
+        self.thread_pool = Arc::new(ThreadPoolBuilder::new().num_threads(workers).build().expect("Thread pool failed to build!!!"));
+        self
+    }
+
+    /// Caps how many connections can wait for a free worker, more are answered with a 503.
+    /// Without it a traffic spike queues connections until clients give up on them
+    pub fn set_max_queued(&mut self, max: usize) -> &mut Self {
+        self.limits.max_queued = Some(max);
+        self
+    }
+
+    /// Caps how many connections are queued or being handled at once, more are answered with a 503.
+    /// Connections taken over by HTTP/2, a [websocket](super::websocket) or an [EventStream](super::event_stream::EventStream)
+    /// leave the pool, so they stop counting once upgraded
+    pub fn set_max_connections(&mut self, max: usize) -> &mut Self {
+        self.limits.max_connections = Some(max);
+        self
+    }
+
+    /// How long rejected clients are told to wait with `Retry-After`, rounded to seconds. 1 second by default
+    pub fn set_retry_after(&mut self, retry_after: Duration) -> &mut Self {
+        self.limits.retry_after = retry_after;
+        self
+    }
+
+    /// The largest request body accepted, bigger ones are answered with 413 Payload Too Large before they are read.
+    /// 1 MiB by default, handlers can allow more or less per request with [RequestHandler::max_body_size()]
+    pub fn set_max_body_size(&mut self, max: usize) -> &mut Self {
+        self.limits.max_body_size = max;
+        self
+    }
+
+    /// How long each read of the request and each write of the response can take, [None] waits forever.
+    /// A request that stops arriving is answered with 408 Request Timeout. 30 seconds by default, connections
+    /// taken over by HTTP/2, a [websocket](super::websocket) or an [EventStream](super::event_stream::EventStream) don't time out
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.limits.timeout = timeout.filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Live counts of queued, active and rejected connections, to export to whatever does monitoring.
+    /// Only the thread pool is counted, not [Server::run_async()]
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Compresses response bodies for clients that accept it, see [Compression]
+    pub fn set_compression(&mut self, compression: Compression) -> &mut Self {
+        self.options.compression = Some(compression);
+        self
+    }
+
+    /// Renders error responses with custom templates, see [ErrorPages]
+    pub fn set_error_pages(&mut self, pages: ErrorPages) -> &mut Self {
+        self.options.error_pages = Some(Arc::new(pages));
+        self
+    }
+
     pub fn run(&mut self, handler: Arc<impl RequestHandler + Send + Sync + 'static>) {
         println!("Listening on {} with {} threads", self.addr(), self.thread_pool.current_num_threads());
+        let rejecter = spawn_rejecter(self.options.clone(), self.limits.retry_after);
 
         loop { 
             let stream = match self.listener.accept() {
@@ -40,49 +206,408 @@ impl Server {
                 Ok((stream, _)) => stream,
             };
 
+            if self.at_limits() {
+                self.metrics.rejected.fetch_add(1, Ordering::SeqCst);
+                // if even the rejecter is backed up the connection is just closed
+                let _ = rejecter.try_send(stream);
+                continue;
+            }
             self.add_pool_task(&handler, stream);
         }
     }
 
+    /// Runs connections on async runtimes instead of the thread pool, one per pool thread, so waiting on
+    /// slow clients, keep-alive connections or I/O in the handler doesn't take up a thread. See [AsyncRequestHandler]
+    #[cfg(feature = "async")]
+    pub fn run_async(&mut self, handler: Arc<impl AsyncRequestHandler + Send + Sync + 'static>) {
+        let workers = self.thread_pool.current_num_threads();
+        println!("Listening on {} with {} async workers", self.addr(), workers);
+
+        if let Err(e) = async_server::run(&self.listener, workers, handler, &self.options, self.limits.max_body_size, self.limits.timeout) {
+            eprintln!("Async runtime failed {}", e);
+        }
+    }
+
+    /// The address the server is listening on. If it was created with port 0 this has the port the OS picked
     pub fn addr(&self) -> String {
-        format!("{}:{}", self.ip, self.port)
+        match self.listener.local_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(_) => format!("{}:{}", self.ip, self.port)
+        }
+    }
+
+    /// Whether another connection would go over [Server::set_max_queued()] or [Server::set_max_connections()].
+    /// Only the accept loop adds to the counts, so they can't grow between this check and queueing the connection
+    fn at_limits(&self) -> bool {
+        let queued = self.metrics.queue_depth();
+        let connections = queued + self.metrics.active();
+        self.limits.max_queued.is_some_and(|max| queued >= max)
+            || self.limits.max_connections.is_some_and(|max| connections >= max)
     }
 
     fn add_pool_task(&self, handler: &Arc<impl RequestHandler + Send + Sync + 'static>, stream: TcpStream) {
         let handler = handler.clone();
+        let options = self.options.clone();
+        let pool = self.thread_pool.clone();
+        let metrics = self.metrics.clone();
+        let max_body_size = self.limits.max_body_size;
+        let timeout = self.limits.timeout;
+        metrics.accepted.fetch_add(1, Ordering::SeqCst);
+        metrics.queued.fetch_add(1, Ordering::SeqCst);
         self.thread_pool.spawn(move || {
+            metrics.queued.fetch_sub(1, Ordering::SeqCst);
+            metrics.active.fetch_add(1, Ordering::SeqCst);
+            // a client that stops sending or reading would hold on to the worker forever
+            if let Err(e) = stream.set_read_timeout(timeout).and_then(|_| stream.set_write_timeout(timeout)) {
+                eprintln!("Failed to set the connection timeout {}", e);
+            }
             let stream = Rc::new(RefCell::new(stream));
-            let mut bytes = [0; (2 as usize).pow(10)];
             let mut response = Response::new(stream.clone());
-            let result = match Self::decode(stream.clone(), &mut bytes) {
-                Ok(mut req) => handler.handle(&mut req, &mut response),
-                Err(err) => handler.handle_bad(&mut response, &err.to_string())
+
+            if let Ok(connection) = stream.borrow().try_clone() {
+                response.set_connection(connection);
+            }
+            let remote_addr = stream.borrow().peer_addr().ok();
+            let read = read_request(&mut stream.borrow_mut(), handler.as_ref(), max_body_size);
+            let result = match read {
+                // HTTP/2 connections outlive this task, they are read on their own thread
+                Ok((bytes, None)) if http2::is_http2(&bytes) => match response.take_connection() {
+                    Some(connection) => http2::serve(connection, &bytes, handler, pool, options, remote_addr, max_body_size),
+                    None => Err(err!(Unsupported, "Can't upgrade", "The connection can't be handed to HTTP/2"))
+                },
+                Ok((bytes, None)) => respond(handler.as_ref(), &bytes, remote_addr, &mut response, &options),
+                Ok((bytes, Some(refusal))) => {
+                    let result = refuse(&bytes, &refusal, &mut response, &options);
+                    linger(&mut stream.borrow_mut());
+                    result
+                },
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    options.prepare(&mut response, None);
+                    response.send_error(StatusCode::RequestTimeout, "The request took too long to arrive")
+                },
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    options.prepare(&mut response, None);
+                    handler.handle_bad(&mut response, &e.to_string())
+                },
+                Err(e) => {
+                    eprintln!("Failed to read request bytes {}", e);
+                    options.prepare(&mut response, None);
+                    handler.handle_bad(&mut response, "Failed to read request bytes")
+                }
             };
 
             if let Err(e) = result {
                 eprintln!("Something went wrong sending response:{}\n{:?}", e, response);
             }
+            metrics.active.fetch_sub(1, Ordering::SeqCst);
         })
     }
+}
+
+/// A request turned away after reading its head, its body is left unread
+pub(super) struct Refusal {
+    pub status: StatusCode,
+    pub detail: String,
+}
+
+/// Decides whether the body of the request with this head can be read, returning how long the whole request is.
+/// `max_body_size` is the limit for this request, from the handler or the server
+pub(super) fn check_body(req: &Request, head_length: usize, max_body_size: usize) -> Result<usize, Refusal> {
+    let refuse = |status, detail: String| Err(Refusal { status, detail });
+    let headers = req.headers();
+
+    if headers.get("Expect").is_some_and(|expect| !expect.trim().eq_ignore_ascii_case("100-continue")) {
+        return refuse(StatusCode::ExpectationFailed, Str!("Only 100-continue is supported"));
+    }
+    if headers.contains("Transfer-Encoding") {
+        return refuse(StatusCode::LengthRequired, Str!("Request bodies need a Content-Length"));
+    }
+    let length = match headers.get("Content-Length").map(|length| length.trim().parse::<usize>()) {
+        Some(Ok(length)) => length,
+        Some(Err(_)) => return refuse(StatusCode::BadRequest, Str!("Invalid Content-Length")),
+        None => 0
+    };
+    if length > max_body_size {
+        return refuse(StatusCode::PayloadTooLarge, format!("The body can't be over {} bytes", max_body_size));
+    }
+    Ok(head_length + length)
+}
+
+/// Whether the client is waiting for `100 Continue` before sending the body. HTTP/1.0 clients don't know about it
+pub(super) fn expects_continue(req: &Request) -> bool {
+    req.version() != "HTTP/1.0" && req.headers().has_token("Expect", "100-continue")
+}
 
-    fn decode(stream: Rc<RefCell<TcpStream>>, bytes: &mut [u8]) -> IoResult<Request> {
-        let read_result = stream.borrow_mut().read(bytes);
-        if let Err(e) = read_result {
-            eprintln!("Failed to read request bytes {}", e);
-            return Err(err!(InvalidData, "Failed to read request bytes"))
+/// Reads the head of a request, then its body if [check_body()] allows it. Returns everything read, which is only the head
+/// (and whatever came with it) if the request was refused. Heads that don't parse are returned as is for [respond()] to reject
+fn read_request(stream: &mut TcpStream, handler: &impl RequestHandler, max_body_size: usize) -> IoResult<(Vec<u8>, Option<Refusal>)> {
+    let mut bytes = Vec::new();
+    let mut chunk = [0; 2_usize.pow(10)];
+    let head_length = loop {
+        let head_end = bytes.windows(4).position(|w| w == b"\r\n\r\n");
+        if head_end.unwrap_or(bytes.len()) > MAX_HEAD_SIZE {
+            return Err(err!(InvalidData, "Request head too large", "The request line and headers are over {} bytes", MAX_HEAD_SIZE));
         }
-        
-        // &bytes[..] creates a byte slice with the entire array
-        let request = match Request::try_from(&bytes[..]) {
-            Ok(request) => Ok(request),
-            Err(e) => {
-                eprintln!("Error converting bytes to result: {}", e);
-                Err(err!(InvalidData, "Error converting bytes to result!"))
-            }
-        }?;
-        println!("Recieved a request: {:?}", request);
-        
-        Ok(request)
+        if let Some(i) = head_end {
+            break i + 4;
+        }
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Ok((bytes, None));
+        }
+        bytes.extend_from_slice(&chunk[..read]);
+    };
+
+    let req = match Request::try_from(&bytes[..head_length]) {
+        Ok(req) => req,
+        Err(_) => return Ok((bytes, None))
+    };
+    let max_body_size = handler.max_body_size(&req).unwrap_or(max_body_size);
+    let length = match check_body(&req, head_length, max_body_size) {
+        Ok(length) => length,
+        Err(refusal) => return Ok((bytes, Some(refusal)))
+    };
+    if bytes.len() < length && expects_continue(&req) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
     }
+
+    while bytes.len() < length {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Err(err!(UnexpectedEof, "Body cut short", "The connection closed before the whole body was sent"));
+        }
+        bytes.extend_from_slice(&chunk[..read]);
+    }
+    // nothing after this request is read on this connection
+    bytes.truncate(length);
+    Ok((bytes, None))
+}
+
+/// Answers a request refused by [check_body()], with the options negotiated from its head
+pub(super) fn refuse(bytes: &[u8], refusal: &Refusal, response: &mut Response, options: &ResponseOptions) -> IoResult<()> {
+    let req = Request::try_from(bytes).ok();
+    options.prepare(response, req.as_ref());
+    response.send_error(refusal.status, &refusal.detail)
+}
+
+/// Reads and drops what is left of a refused body for a moment before the connection closes.
+/// Closing with unread data resets the connection, which can throw away the response before the client reads it
+fn linger(stream: &mut TcpStream) {
+    if stream.shutdown(std::net::Shutdown::Write).is_err() || stream.set_read_timeout(Some(Duration::from_millis(200))).is_err() {
+        return;
+    }
+    let mut chunk = [0; 4096];
+    let mut drained = 0;
+    while drained < LINGER_LIMIT {
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return,
+            Ok(read) => drained += read
+        }
+    }
+}
+
+/// Answers connections over the limits with a 503 on its own thread, so the accept loop never waits on them
+fn spawn_rejecter(options: ResponseOptions, retry_after: Duration) -> SyncSender<TcpStream> {
+    let (sender, receiver) = mpsc::sync_channel::<TcpStream>(REJECT_QUEUE);
+    thread::Builder::new()
+        .name(Str!("rejecter"))
+        .spawn(move || {
+            for stream in receiver {
+                if let Err(e) = reject(stream, &options, retry_after) {
+                    eprintln!("Failed to send 503 response {}", e);
+                }
+            }
+        })
+        .expect("Failed to start the rejecter thread");
+    sender
 }
 
+fn reject(stream: TcpStream, options: &ResponseOptions, retry_after: Duration) -> IoResult<()> {
+    // the request is read first, closing with it unread would reset the connection and lose the 503
+    let timeout = Some(Duration::from_millis(500));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    let mut bytes = [0; 2_usize.pow(10)];
+    let read = (&stream).read(&mut bytes).unwrap_or(0);
+    let req = Request::try_from(&bytes[..read]).ok();
+
+    let mut response = Response::new(Rc::new(RefCell::new(stream)));
+    options.prepare(&mut response, req.as_ref());
+    response.set_header("Retry-After", retry_after.as_secs().max(1).to_string())
+        .send_error(StatusCode::ServiceUnavailable, "The server is too busy, try again later")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::{BufReader, Write}, sync::{Mutex, Condvar}, time::Instant};
+    use crate::http::{Method, Router, parsed_response::ParsedResponse};
+
+    /// Holds every request until the gate opens
+    #[derive(Clone, Default)]
+    struct Gate {
+        open: Arc<(Mutex<bool>, Condvar)>
+    }
+
+    impl Gate {
+        fn open(&self) {
+            *self.open.0.lock().unwrap() = true;
+            self.open.1.notify_all();
+        }
+    }
+
+    impl RequestHandler for Gate {
+        fn get(&self, _req: &Request, res: &mut Response) -> IoResult<()> {
+            let (open, opened) = &*self.open;
+            let _open = opened.wait_while(open.lock().unwrap(), |open| !*open).unwrap();
+            res.ok(Some(Str!("done")))
+        }
+    }
+
+    fn send(addr: &str) -> BufReader<TcpStream> {
+        let mut stream = TcpStream::connect(addr).expect("failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nAccept: text/plain\r\n\r\n").unwrap();
+        BufReader::new(stream)
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn start(server: Server, gate: &Gate) -> (String, Arc<Metrics>) {
+        let mut server = server;
+        let addr = server.addr();
+        let metrics = server.metrics();
+        let gate = gate.clone();
+        thread::spawn(move || server.run(Arc::new(gate)));
+        (addr, metrics)
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let gate = Gate::default();
+        let mut server = Server::new(Str!("127.0.0.1"), 0);
+        server.set_workers(1).set_max_queued(1).set_retry_after(Duration::from_secs(7));
+        let (addr, metrics) = start(server, &gate);
+
+        let mut first = send(&addr);
+        wait_for(|| metrics.active() == 1);
+        let mut second = send(&addr);
+        wait_for(|| metrics.queue_depth() == 1);
+
+        let res = ParsedResponse::read_from(&mut send(&addr), &Method::GET).expect("no 503");
+        assert_eq!(res.status, StatusCode::ServiceUnavailable);
+        assert_eq!(res.headers.get("Retry-After"), Some("7"));
+        assert_eq!(metrics.rejected(), 1);
+
+        gate.open();
+        for conn in [&mut first, &mut second] {
+            let res = ParsedResponse::read_from(conn, &Method::GET).unwrap();
+            assert_eq!(res.text(), "done");
+        }
+        wait_for(|| metrics.active() == 0);
+        assert_eq!((metrics.accepted(), metrics.queue_depth()), (2, 0));
+    }
+
+    struct Echo;
+
+    impl RequestHandler for Echo {
+        fn post(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            res.ok(Some(req.body().unwrap_or(&[]).len().to_string()))
+        }
+    }
+
+    fn start_limited() -> String {
+        let mut router = Router::new();
+        router.mount("/", Echo).set_max_body_size("/upload", 5000);
+        let mut server = Server::new(Str!("127.0.0.1"), 0);
+        server.set_max_body_size(100);
+        let addr = server.addr();
+        thread::spawn(move || server.run(Arc::new(router)));
+        addr
+    }
+
+    fn post(addr: &str, path: &str, headers: &str, body: &[u8]) -> ParsedResponse {
+        let mut stream = TcpStream::connect(addr).expect("failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut request = format!("POST {} HTTP/1.1\r\nContent-Length: {}\r\n{}\r\n", path, body.len(), headers).into_bytes();
+        request.extend_from_slice(body);
+        stream.write_all(&request).unwrap();
+        ParsedResponse::read_from(&mut BufReader::new(stream), &Method::POST).expect("failed to read response")
+    }
+
+    #[test]
+    fn body_limits() {
+        let addr = start_limited();
+        let body = vec![b'a'; 3000];
+
+        assert_eq!(post(&addr, "/", "", &body[..100]).text(), "100");
+        assert_eq!(post(&addr, "/", "", &body).status, StatusCode::PayloadTooLarge);
+        // past the first read, the whole body arrives
+        assert_eq!(post(&addr, "/upload/file", "", &body).text(), "3000");
+        assert_eq!(post(&addr, "/upload", "", &[b'a'; 6000]).status, StatusCode::PayloadTooLarge);
+
+        let res = post(&addr, "/", "Transfer-Encoding: chunked\r\n", b"");
+        assert_eq!(res.status, StatusCode::LengthRequired);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut server = Server::new(Str!("127.0.0.1"), 0);
+        server.set_timeout(Some(Duration::from_millis(100)));
+        let addr = server.addr();
+        thread::spawn(move || server.run(Arc::new(Echo)));
+
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel").unwrap();
+        let res = ParsedResponse::read_from(&mut BufReader::new(stream), &Method::POST).expect("no 408");
+        assert_eq!(res.status, StatusCode::RequestTimeout);
+    }
+
+    #[test]
+    fn expect_continue() {
+        let addr = start_limited();
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let interim = ParsedResponse::read_head(&mut reader).expect("no 100 Continue");
+        assert_eq!(interim.status, StatusCode::Continue);
+        reader.get_mut().write_all(b"hello").unwrap();
+        assert_eq!(ParsedResponse::read_from(&mut reader, &Method::POST).unwrap().text(), "5");
+
+        // refused straight away instead of asking for the body
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.write_all(b"POST / HTTP/1.1\r\nContent-Length: 500\r\nExpect: 100-continue\r\n\r\n").unwrap();
+        let res = ParsedResponse::read_from(&mut BufReader::new(stream), &Method::POST).unwrap();
+        assert_eq!(res.status, StatusCode::PayloadTooLarge);
+
+        assert_eq!(post(&addr, "/", "Expect: cookies\r\n", b"hello").status, StatusCode::ExpectationFailed);
+    }
+
+    #[test]
+    fn max_connections() {
+        let gate = Gate::default();
+        let mut server = Server::new(Str!("127.0.0.1"), 0);
+        server.set_workers(2).set_max_connections(1);
+        let (addr, metrics) = start(server, &gate);
+
+        let mut first = send(&addr);
+        wait_for(|| metrics.active() == 1);
+        let res = ParsedResponse::read_from(&mut send(&addr), &Method::GET).unwrap();
+        assert_eq!(res.status, StatusCode::ServiceUnavailable);
+        assert_eq!(res.headers.get("Retry-After"), Some("1"));
+
+        gate.open();
+        assert_eq!(ParsedResponse::read_from(&mut first, &Method::GET).unwrap().text(), "done");
+        wait_for(|| metrics.active() == 0);
+        assert_eq!(ParsedResponse::read_from(&mut send(&addr), &Method::GET).unwrap().text(), "done");
+    }
+}
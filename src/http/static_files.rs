@@ -0,0 +1,460 @@
+use std::{
+    fs,
+    io::{ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH
+};
+
+use super::{
+    RequestHandler,
+    Request,
+    Response,
+    StatusCode,
+    date::http_date,
+    router::matches_prefix,
+    cache_rules::{CacheRule, find_rule}
+};
+
+/// What to do with requests for files or directories whose name starts with a `.`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HiddenFiles {
+    /// Respond with 403
+    Deny,
+    /// Respond with 404 as if the file did not exist
+    Ignore,
+    /// Serve them like any other file
+    Allow,
+}
+
+/// What to do when the requested path goes through a symbolic link
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symlinks {
+    /// Follow links as long as they end up inside the root directory
+    FollowWithinRoot,
+    /// Follow links anywhere on the filesystem
+    Follow,
+    /// Respond with 403 to any path that contains a link
+    Deny,
+}
+
+/// Serves a single file (usually `index.html`) for unknown paths so client-side routing can handle them
+#[derive(Clone, Debug)]
+pub struct SpaFallback {
+    /// File to serve, relative to the root directory
+    pub file: String,
+    /// Paths starting with any of these still get a 404, ex `/api/`
+    pub excluded_prefixes: Vec<String>,
+    /// If true, paths whose last segment has an extension (`/app.js`) are treated as missing assets and get a 404
+    pub exclude_extensions: bool,
+}
+
+impl Default for SpaFallback {
+    fn default() -> Self {
+        Self {
+            file: Str!("index.html"),
+            excluded_prefixes: vec![Str!("/api/")],
+            exclude_extensions: true
+        }
+    }
+}
+
+impl SpaFallback {
+    /// Whether a missing `path` should be answered with the fallback file
+    pub fn applies_to(&self, path: &str) -> bool {
+        if self.excluded_prefixes.iter().any(|prefix| path.starts_with(prefix.as_str())) {
+            return false;
+        }
+        let last_segment = path.rsplit('/').next().unwrap_or("");
+        !(self.exclude_extensions && last_segment.contains('.'))
+    }
+}
+
+/// Serves files from a directory on disk
+pub struct StaticFiles {
+    root: String,
+    mount_prefix: String,
+    index_files: Vec<String>,
+    hidden_files: HiddenFiles,
+    symlinks: Symlinks,
+    cache_control: Option<String>,
+    cache_rules: Vec<CacheRule>,
+    spa_fallback: Option<SpaFallback>
+}
+
+impl StaticFiles {
+    pub fn new(root: String) -> Self {
+        Self {
+            root,
+            mount_prefix: String::new(),
+            index_files: vec![Str!("index.html")],
+            hidden_files: HiddenFiles::Deny,
+            symlinks: Symlinks::FollowWithinRoot,
+            cache_control: None,
+            cache_rules: Vec::new(),
+            spa_fallback: None
+        }
+    }
+
+    /// Only serve requests under `prefix`, which is removed before looking up the file.
+    /// Not needed when mounted in a [Router](super::Router), which already removes the prefix it is mounted on
+    pub fn set_mount_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.mount_prefix = Str!(prefix.trim_end_matches('/'));
+        self
+    }
+
+    /// Files to look for, in order, when a directory is requested
+    pub fn set_index_files(&mut self, index_files: Vec<String>) -> &mut Self {
+        self.index_files = index_files;
+        self
+    }
+
+    pub fn set_hidden_files(&mut self, hidden_files: HiddenFiles) -> &mut Self {
+        self.hidden_files = hidden_files;
+        self
+    }
+
+    pub fn set_symlinks(&mut self, symlinks: Symlinks) -> &mut Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// `Cache-Control` value sent with files that don't match any of the cache rules, ex `public, max-age=3600`
+    pub fn set_cache_control(&mut self, cache_control: &str) -> &mut Self {
+        self.cache_control = some_str!(cache_control);
+        self
+    }
+
+    /// Adds a rule setting the caching headers for some files. Rules are checked in the order
+    /// they were added against the path of the file relative to the root, the first match wins
+    pub fn add_cache_rule(&mut self, rule: CacheRule) -> &mut Self {
+        self.cache_rules.push(rule);
+        self
+    }
+
+    /// Serve [SpaFallback::file] instead of a 404 for unknown paths
+    pub fn set_spa_fallback(&mut self, fallback: SpaFallback) -> &mut Self {
+        self.spa_fallback = Some(fallback);
+        self
+    }
+
+    /// Maps a request path onto a file under the root, enforcing the hidden file and symlink policies
+    fn resolve(&self, path: &str) -> IoResult<PathBuf> {
+        let root = fs::canonicalize(&self.root)?;
+        let mut file = root.clone();
+
+        for segment in path.split('/').filter(|s| !s.is_empty() && *s != ".") {
+            if segment == ".." {
+                return Err(err!(PermissionDenied, "Directory traversal attack attempted", "Attempted Path: {}", path));
+            }
+            if segment.starts_with('.') {
+                match self.hidden_files {
+                    HiddenFiles::Deny => return Err(err!(PermissionDenied, "Hidden file requested", "Attempted Path: {}", path)),
+                    HiddenFiles::Ignore => return Err(err!(NotFound, "Hidden file requested", "Attempted Path: {}", path)),
+                    HiddenFiles::Allow => {}
+                }
+            }
+            file.push(segment);
+        }
+
+        if file.is_dir() {
+            file = self.index_files.iter()
+                .map(|index| file.join(index))
+                .find(|candidate| candidate.is_file())
+                .ok_or_else(|| err!(NotFound, "Directory has no index file", "Directory: {}", file.display()))?;
+        }
+
+        match self.symlinks {
+            Symlinks::Follow => {},
+            Symlinks::FollowWithinRoot => {
+                if !fs::canonicalize(&file)?.starts_with(&root) {
+                    return Err(err!(PermissionDenied, "Directory traversal attack attempted", "Attempted Path: {}", path));
+                }
+            },
+            Symlinks::Deny => {
+                let mut current = root.clone();
+                for component in file.strip_prefix(&root).unwrap_or(&file).components() {
+                    current.push(component);
+                    if fs::symlink_metadata(&current)?.file_type().is_symlink() {
+                        return Err(err!(PermissionDenied, "Symbolic link requested", "Attempted Path: {}", path));
+                    }
+                }
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Reads the file at `path` relative to the root, with the same checks as a request would get
+    pub fn read_file(&self, path: &str) -> IoResult<Vec<u8>> {
+        fs::read(self.resolve(path)?)
+    }
+
+    fn send_file(&self, req: &Request, res: &mut Response, file: &Path) -> IoResult<()> {
+        let metadata = fs::metadata(file)?;
+        let modified = metadata.modified().ok();
+        let etag = format!(
+            "\"{:x}-{:x}\"",
+            metadata.len(),
+            modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs())
+        );
+
+        res.set_header("Content-Type", content_type(file));
+        res.set_header("ETag", etag.as_str());
+        if let Some(modified) = modified {
+            res.set_header("Last-Modified", http_date(modified));
+        }
+        match find_rule(&self.cache_rules, &self.relative_path(file)) {
+            Some(rule) => rule.apply(&mut res.headers),
+            None => if let Some(cache_control) = &self.cache_control {
+                res.set_header("Cache-Control", cache_control.as_str());
+            }
+        }
+
+        if req.headers().get_all("If-None-Match").flat_map(|v| v.split(',')).any(|tag| tag.trim() == etag || tag.trim() == "*") {
+            res.status = StatusCode::NotModified;
+            res.body = None;
+            return res.send();
+        }
+
+        let body = fs::read(file)?;
+        res.status = StatusCode::Ok;
+        res.set_body(body).send()
+    }
+
+    /// The path of `file` under the root as it would be requested, ex `/js/app.js`
+    fn relative_path(&self, file: &Path) -> String {
+        let relative = fs::canonicalize(&self.root).ok()
+            .and_then(|root| file.strip_prefix(root).ok().map(Path::to_path_buf))
+            .unwrap_or_default();
+        let segments: Vec<_> = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect();
+        format!("/{}", segments.join("/"))
+    }
+
+    fn send_fallback(&self, req: &Request, res: &mut Response, path: &str) -> IoResult<()> {
+        match &self.spa_fallback {
+            Some(fallback) if fallback.applies_to(path) => match self.resolve(&fallback.file) {
+                Ok(file) => self.send_file(req, res, &file),
+                Err(_) => res.send_404()
+            },
+            _ => res.send_404()
+        }
+    }
+}
+
+impl RequestHandler for StaticFiles {
+    fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+        if !matches_prefix(&self.mount_prefix, req.path()) {
+            return res.send_404();
+        }
+        let path = &req.path()[self.mount_prefix.len()..];
+
+        match self.resolve(path) {
+            Ok(file) => self.send_file(req, res, &file),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => res.send_403(),
+            Err(_) => self.send_fallback(req, res, path)
+        }
+    }
+
+    fn head(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+        res.set_omit_body(true);
+        self.get(req, res)
+    }
+}
+
+/// Guesses the `Content-Type` from the file extension
+pub fn content_type(file: &Path) -> &'static str {
+    let extension = file.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript",
+        "json" | "map" => "application/json",
+        "txt" | "rs" | "md" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{rc::Rc, cell::RefCell};
+
+    // uses src/http as the root so it doesn't rely on any actual files being in the public dir
+    fn handler() -> StaticFiles {
+        StaticFiles::new(format!("{}/src/http", env!("CARGO_MANIFEST_DIR")))
+    }
+
+    fn get(handler: &StaticFiles, path: &str) -> Response {
+        let raw = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        let req = Request::try_from(raw.as_bytes()).expect("Request failed to parse");
+        let mut res = Response::new(Rc::new(RefCell::new(Vec::<u8>::new())));
+        handler.get(&req, &mut res).expect("Handler failed");
+        res
+    }
+
+    #[test]
+    fn read_existing_file() {
+        match handler().read_file("server.rs") {
+            Ok(s) => assert!(!s.is_empty()),
+            Err(e) => panic!("Error reading server.rs file! {}", e),
+        }
+    }
+
+    #[test]
+    fn file_not_found() {
+        match handler().read_file("invalid_file") {
+            Err(e) => assert_eq!(e.kind(), ErrorKind::NotFound),
+            Ok(_) => panic!("invalid_file read returned Ok"),
+        }
+    }
+
+    #[test]
+    fn directory_traversal() {
+        match handler().read_file("../main.rs") {
+            Err(e) => assert_eq!(e.kind(), ErrorKind::PermissionDenied),
+            Ok(_) => panic!("DIRECTORY TRAVERSAL ATTACK WAS SUCCESSFUL"),
+        }
+        assert_eq!(get(&handler(), "/../main.rs").status.code(), 403);
+    }
+
+    #[test]
+    fn hidden_files() {
+        assert_eq!(get(&handler(), "/.git/config").status.code(), 403);
+
+        let mut handler = handler();
+        handler.set_hidden_files(HiddenFiles::Ignore);
+        assert_eq!(get(&handler, "/.git/config").status.code(), 404);
+    }
+
+    #[test]
+    fn serve_with_headers() {
+        let mut handler = handler();
+        handler.set_mount_prefix("/src/").set_cache_control("public, max-age=60");
+
+        let res = get(&handler, "/src/server.rs");
+        assert_eq!(res.status.code(), 200);
+        assert_eq!(res.headers.get("Content-Type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(res.headers.get("Cache-Control"), Some("public, max-age=60"));
+        assert!(res.headers.get("Last-Modified").is_some_and(|d| d.ends_with(" GMT")));
+
+        assert_eq!(get(&handler, "/server.rs").status.code(), 404);
+    }
+
+    #[test]
+    fn head() {
+        let mut router = super::super::Router::new();
+        router.mount("/src", handler());
+
+        let send = |method: &str, path: &str| {
+            let raw = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n\r\n", method, path);
+            let req = Request::try_from(raw.as_bytes()).expect("Request failed to parse");
+            let buffer = Rc::new(RefCell::new(Vec::<u8>::new()));
+            router.handle(&req, &mut Response::new(buffer.clone())).expect("Handler failed");
+            let sent = buffer.borrow().clone();
+            sent
+        };
+
+        // the router removed /src, the handler has no mount prefix of its own
+        let get = send("GET", "/src/server.rs");
+        assert!(get.starts_with(b"HTTP/1.1 200"));
+        let head_end = get.windows(4).position(|w| w == b"\r\n\r\n").expect("no end of head") + 4;
+        assert!(get.len() > head_end);
+        assert_eq!(send("HEAD", "/src/server.rs"), &get[..head_end]);
+
+        let missing = send("HEAD", "/src/missing.rs");
+        assert!(missing.starts_with(b"HTTP/1.1 404") && missing.ends_with(b"\r\n\r\n"));
+    }
+
+    #[test]
+    fn cache_rules() {
+        use super::super::cache_rules::PathPattern;
+
+        let mut handler = handler();
+        handler.set_cache_control("public, max-age=60")
+            .add_cache_rule(CacheRule::no_cache(PathPattern::Glob(Str!("mod.rs"))))
+            .add_cache_rule(CacheRule::immutable(PathPattern::Prefix(Str!("/ser"))));
+
+        assert_eq!(get(&handler, "/mod.rs").headers.get("Cache-Control"), Some("no-cache"));
+        let res = get(&handler, "/server.rs");
+        assert_eq!(res.headers.get("Cache-Control"), Some("public, max-age=31536000, immutable"));
+        assert!(res.headers.contains("Expires"));
+        assert_eq!(get(&handler, "/request.rs").headers.get("Cache-Control"), Some("public, max-age=60"));
+    }
+
+    #[test]
+    fn not_modified() {
+        let handler = handler();
+        let etag = get(&handler, "/mod.rs").headers.get("ETag").map(String::from).expect("no etag");
+
+        let raw = format!("GET /mod.rs HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag);
+        let req = Request::try_from(raw.as_bytes()).expect("Request failed to parse");
+        let mut res = Response::new(Rc::new(RefCell::new(Vec::<u8>::new())));
+        handler.get(&req, &mut res).expect("Handler failed");
+        assert_eq!(res.status.code(), 304);
+        assert_eq!(res.body, None);
+    }
+
+    #[test]
+    fn index_files() {
+        let mut handler = StaticFiles::new(format!("{}/src", env!("CARGO_MANIFEST_DIR")));
+        handler.set_index_files(vec![Str!("index.html"), Str!("mod.rs")]);
+        let res = get(&handler, "/http/");
+        assert_eq!(res.status.code(), 200);
+        assert!(String::from_utf8_lossy(&res.body.expect("index has no body")).contains("pub mod server;"));
+
+        assert_eq!(get(&handler, "/").status.code(), 404);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let root = std::env::temp_dir().join(format!("static_files_symlinks_{}", std::process::id()));
+        fs::create_dir_all(&root).expect("failed to create temp dir");
+        let link = root.join("linked.rs");
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(format!("{}/src/main.rs", env!("CARGO_MANIFEST_DIR")), &link).expect("failed to link");
+
+        let mut handler = StaticFiles::new(root.to_string_lossy().to_string());
+        assert_eq!(get(&handler, "/linked.rs").status.code(), 403);
+        handler.set_symlinks(Symlinks::Deny);
+        assert_eq!(get(&handler, "/linked.rs").status.code(), 403);
+        handler.set_symlinks(Symlinks::Follow);
+        assert_eq!(get(&handler, "/linked.rs").status.code(), 200);
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn spa_fallback() {
+        let mut handler = handler();
+        handler.set_spa_fallback(SpaFallback { file: Str!("server.rs"), ..Default::default() });
+
+        let res = get(&handler, "/dashboard/settings");
+        assert_eq!(res.status.code(), 200);
+        assert!(String::from_utf8_lossy(&res.body.expect("fallback has no body")).contains("pub struct Server"));
+
+        assert_eq!(get(&handler, "/api/users").status.code(), 404);
+        assert_eq!(get(&handler, "/missing.js").status.code(), 404);
+        assert_eq!(get(&handler, "/../main.rs").status.code(), 403);
+    }
+
+    #[test]
+    fn spa_fallback_rules() {
+        let fallback = SpaFallback { exclude_extensions: false, ..Default::default() };
+        assert!(fallback.applies_to("/users/10"));
+        assert!(fallback.applies_to("/report.pdf"));
+        assert!(!fallback.applies_to("/api/report"));
+        assert!(!SpaFallback::default().applies_to("/report.pdf"));
+    }
+}
@@ -1,26 +1,119 @@
 use std::fmt::{Display, Formatter, Result as FmtResult};
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum StatusCode {
+    Continue = 100,
+    SwitchingProtocols = 101,
     Ok = 200,
+    Created = 201,
+    Accepted = 202,
+    NoContent = 204,
+    PartialContent = 206,
+    MovedPermanently = 301,
+    Found = 302,
+    SeeOther = 303,
+    NotModified = 304,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
     BadRequest = 400,
+    Unauthorized = 401,
     PermissionDenied = 403,
     NotFound = 404,
+    MethodNotAllowed = 405,
+    RequestTimeout = 408,
+    Conflict = 409,
+    Gone = 410,
+    LengthRequired = 411,
+    PayloadTooLarge = 413,
+    UnsupportedMediaType = 415,
+    ExpectationFailed = 417,
+    UnprocessableEntity = 422,
+    UpgradeRequired = 426,
+    TooManyRequests = 429,
+    InternalServerError = 500,
+    NotImplemented = 501,
+    BadGateway = 502,
+    ServiceUnavailable = 503,
+    GatewayTimeout = 504,
 }
 
 impl StatusCode {
+    const ALL: &'static [StatusCode] = &[
+        Self::Continue, Self::SwitchingProtocols,
+        Self::Ok, Self::Created, Self::Accepted, Self::NoContent, Self::PartialContent,
+        Self::MovedPermanently, Self::Found, Self::SeeOther, Self::NotModified, Self::TemporaryRedirect, Self::PermanentRedirect,
+        Self::BadRequest, Self::Unauthorized, Self::PermissionDenied, Self::NotFound, Self::MethodNotAllowed,
+        Self::RequestTimeout, Self::Conflict, Self::Gone, Self::LengthRequired, Self::PayloadTooLarge,
+        Self::UnsupportedMediaType, Self::ExpectationFailed, Self::UnprocessableEntity, Self::UpgradeRequired, Self::TooManyRequests,
+        Self::InternalServerError, Self::NotImplemented, Self::BadGateway, Self::ServiceUnavailable, Self::GatewayTimeout,
+    ];
+
     /** Returns the numerical status code as u16 */
     pub fn code(&self) -> u16 {
         *self as u16
     }
+
+    /** Returns the [StatusCode] for `code`, or [None] if it isn't one of the known codes */
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.iter().find(|status| status.code() == code).copied()
+    }
+
+    /**
+     * Like [StatusCode::from_code()], but an unknown code is treated as the x00 code of its class
+     * (ex 418 becomes 400) the way clients are meant to handle unrecognized codes
+     */
+    pub fn from_code_or_class(code: u16) -> Option<Self> {
+        Self::from_code(code).or_else(|| Self::from_code(code / 100 * 100))
+    }
+
+    /** 1xx codes, 204 and 304 never have a body */
+    pub fn allows_body(&self) -> bool {
+        !(self.code() < 200 || matches!(self, Self::NoContent | Self::NotModified))
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        matches!(
+            self,
+            Self::MovedPermanently | Self::Found | Self::SeeOther | Self::TemporaryRedirect | Self::PermanentRedirect
+        )
+    }
 }
 impl Display for StatusCode {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         let reason = match self {
+            Self::Continue => "Continue",
+            Self::SwitchingProtocols => "Switching Protocols",
             Self::Ok => "Ok",
+            Self::Created => "Created",
+            Self::Accepted => "Accepted",
+            Self::NoContent => "No Content",
+            Self::PartialContent => "Partial Content",
+            Self::MovedPermanently => "Moved Permanently",
+            Self::Found => "Found",
+            Self::SeeOther => "See Other",
+            Self::NotModified => "Not Modified",
+            Self::TemporaryRedirect => "Temporary Redirect",
+            Self::PermanentRedirect => "Permanent Redirect",
             Self::BadRequest => "Bad Request",
+            Self::Unauthorized => "Unauthorized",
             Self::NotFound => "Not Found",
-            Self::PermissionDenied => "Permission Denied"
+            Self::PermissionDenied => "Permission Denied",
+            Self::MethodNotAllowed => "Method Not Allowed",
+            Self::RequestTimeout => "Request Timeout",
+            Self::Conflict => "Conflict",
+            Self::Gone => "Gone",
+            Self::LengthRequired => "Length Required",
+            Self::PayloadTooLarge => "Payload Too Large",
+            Self::UnsupportedMediaType => "Unsupported Media Type",
+            Self::ExpectationFailed => "Expectation Failed",
+            Self::UnprocessableEntity => "Unprocessable Entity",
+            Self::UpgradeRequired => "Upgrade Required",
+            Self::TooManyRequests => "Too Many Requests",
+            Self::InternalServerError => "Internal Server Error",
+            Self::NotImplemented => "Not Implemented",
+            Self::BadGateway => "Bad Gateway",
+            Self::ServiceUnavailable => "Service Unavailable",
+            Self::GatewayTimeout => "Gateway Timeout",
         };
         f.write_str(reason)
     }
-}
\ No newline at end of file
+}
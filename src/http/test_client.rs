@@ -0,0 +1,171 @@
+use std::{
+    rc::Rc,
+    cell::RefCell,
+    io::Result as IoResult,
+    sync::Arc
+};
+
+use super::{
+    Method,
+    Headers,
+    Response,
+    RequestHandler,
+    Compression,
+    ErrorPages,
+    parsed_response::ParsedResponse,
+    server::{respond, ResponseOptions}
+};
+
+/// Runs requests through a [RequestHandler] without a socket, the same way [Server](super::Server) does:
+/// the raw bytes are parsed, handled and the response is written into a buffer and parsed back into a [ParsedResponse]
+pub struct TestClient<H: RequestHandler> {
+    handler: H,
+    options: ResponseOptions
+}
+
+/// A request being built by [TestClient::request()]
+pub struct TestRequest<'c, H: RequestHandler> {
+    client: &'c TestClient<H>,
+    method: Method,
+    path: String,
+    headers: Headers,
+    body: Vec<u8>
+}
+
+impl<H: RequestHandler> TestClient<H> {
+    pub fn new(handler: H) -> Self {
+        Self { handler, options: ResponseOptions::default() }
+    }
+
+    /// See [Server::set_compression()](super::Server::set_compression)
+    pub fn set_compression(&mut self, compression: Compression) -> &mut Self {
+        self.options.compression = Some(compression);
+        self
+    }
+
+    /// See [Server::set_error_pages()](super::Server::set_error_pages)
+    pub fn set_error_pages(&mut self, pages: ErrorPages) -> &mut Self {
+        self.options.error_pages = Some(Arc::new(pages));
+        self
+    }
+
+    /// Sends `raw` exactly as given, for testing how malformed requests are handled
+    pub fn send_raw(&self, raw: &[u8]) -> IoResult<ParsedResponse> {
+        let buffer = Rc::new(RefCell::new(Vec::<u8>::new()));
+        let mut response = Response::new(buffer.clone());
+        respond(&self.handler, raw, None, &mut response, &self.options)?;
+
+        let bytes = buffer.borrow();
+        Ok(ParsedResponse::parse(&bytes)?)
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> TestRequest<'_, H> {
+        TestRequest { client: self, method, path: Str!(path), headers: Headers::new(), body: Vec::new() }
+    }
+
+    pub fn get(&self, path: &str) -> IoResult<ParsedResponse> {
+        self.request(Method::GET, path).send()
+    }
+
+    pub fn post(&self, path: &str, body: &str) -> IoResult<ParsedResponse> {
+        self.request(Method::POST, path).body(body).send()
+    }
+}
+
+impl<'c, H: RequestHandler> TestRequest<'c, H> {
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.add(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Builds the raw request, adding `Host` and `Content-Length` if they weren't set, and sends it
+    pub fn send(mut self) -> IoResult<ParsedResponse> {
+        if !self.headers.contains("Host") {
+            self.headers.add("Host", "localhost");
+        }
+        if !self.body.is_empty() && !self.headers.contains("Content-Length") {
+            self.headers.add("Content-Length", self.body.len().to_string());
+        }
+
+        let mut raw = format!("{} {} HTTP/1.1\r\n{}\r\n", self.method, self.path, self.headers).into_bytes();
+        raw.extend_from_slice(&self.body);
+        self.client.send_raw(&raw)
+    }
+}
+
+/// Runs `handler` on a [Server](super::Server) listening on a free local port, returning its address
+#[cfg(test)]
+pub(crate) fn spawn_test_server(handler: impl RequestHandler + Send + Sync + 'static) -> String {
+    let mut server = super::Server::new(Str!("127.0.0.1"), 0);
+    let addr = server.addr();
+    std::thread::spawn(move || server.run(Arc::new(handler)));
+    addr
+}
+
+/// [spawn_test_server()] for an [AsyncRequestHandler](super::async_server::AsyncRequestHandler)
+#[cfg(all(test, feature = "async"))]
+pub(crate) fn spawn_async_test_server(handler: impl super::async_server::AsyncRequestHandler + Send + Sync + 'static) -> String {
+    let mut server = super::Server::new(Str!("127.0.0.1"), 0);
+    let addr = server.addr();
+    std::thread::spawn(move || server.run_async(Arc::new(handler)));
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Request, StatusCode};
+
+    struct Echo;
+
+    impl RequestHandler for Echo {
+        fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            res.set_header("X-Path", req.path());
+            res.ok(req.headers().get("X-Echo").map(String::from))
+        }
+        fn post(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            res.ok(req.body_str().map(String::from))
+        }
+    }
+
+    #[test]
+    fn structured_request() {
+        let client = TestClient::new(Echo);
+
+        let res = client.request(Method::GET, "/echo?a=1").header("X-Echo", "Bananas").send().expect("request failed");
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(res.reason, "Ok");
+        assert_eq!(res.headers.get("X-Path"), Some("/echo"));
+        assert_eq!(res.headers.get("Content-Length"), Some("7"));
+        assert_eq!(res.text(), "Bananas");
+
+        let res = client.post("/", "some body").expect("request failed");
+        assert_eq!(res.text(), "some body");
+        assert_eq!(client.request(Method::DELETE, "/").send().expect("request failed").status, StatusCode::NotFound);
+    }
+
+    #[test]
+    fn raw_request() {
+        let mut client = TestClient::new(Echo);
+        client.set_error_pages(ErrorPages::new());
+
+        let res = client.send_raw(b"GET / HTTP/1.2\r\nAccept: application/json\r\n\r\n").expect("request failed");
+        assert_eq!(res.status, StatusCode::BadRequest);
+        assert!(res.text().contains("Invalid Protocol"));
+    }
+
+    #[test]
+    fn compression() {
+        let mut client = TestClient::new(Echo);
+        client.set_compression(Compression::new(0, 6));
+
+        let res = client.request(Method::GET, "/").header("X-Echo", &"a".repeat(500)).header("Accept-Encoding", "gzip").send().expect("request failed");
+        assert_eq!(res.headers.get("Content-Encoding"), Some("gzip"));
+        assert!(res.body.len() < 500);
+    }
+}
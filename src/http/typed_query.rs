@@ -0,0 +1,390 @@
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io
+};
+use serde::de::{
+    self,
+    DeserializeOwned,
+    DeserializeSeed,
+    Deserializer,
+    IntoDeserializer,
+    MapAccess,
+    SeqAccess,
+    Visitor
+};
+
+use super::{
+    Request,
+    QueryString,
+    StatusCode,
+    query_string::{Value, decode},
+    request::FormError
+};
+
+/*
+EXAMPLE:
+
+#[derive(Deserialize)]
+struct Search {
+    q: String,             // ?q=apples           required
+    page: Option<u32>,     // &page=2             None if missing or empty
+    #[serde(default)]
+    exact: bool,           // &exact              a flag without a value is true, `exact=` false
+    #[serde(default)]
+    tag: Vec<String>,      // &tag=red&tag=green  any number of values
+}
+
+let search: Search = match req.query_as() {
+    Ok(search) => search,
+    Err(e) => return self.handle_bad(res, &e.to_string())
+};
+*/
+
+/// Why a query string couldn't be turned into the requested type. The message names the parameter,
+/// so it can be sent back as is with [RequestHandler::handle_bad()](super::RequestHandler::handle_bad)
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    message: String,
+    status: StatusCode,
+}
+
+impl QueryError {
+    fn new(message: String) -> Self {
+        Self { message, status: StatusCode::BadRequest }
+    }
+
+    /// 400 Bad Request, or for [Request::form_as()] whatever [FormError::status()] gives
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Puts the parameter the error is about in front of it
+    fn in_param(self, key: &str) -> Self {
+        Self::new(format!("Invalid parameter `{}`: {}", key, self.message))
+    }
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for QueryError {}
+
+impl de::Error for QueryError {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self::new(msg.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self::new(format!("Missing parameter `{}`", field))
+    }
+}
+
+impl From<FormError> for QueryError {
+    fn from(e: FormError) -> Self {
+        Self { message: e.to_string(), status: e.status() }
+    }
+}
+
+impl From<QueryError> for io::Error {
+    fn from(e: QueryError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, e)
+    }
+}
+
+impl QueryString<'_> {
+    /// Builds `T` from the parameters, decoding `+` and `%XX`. Structs take their fields from parameters of the same name,
+    /// `Vec` fields collect every value given, `Option` fields are None when the parameter is missing or empty
+    /// and `bool` fields are true when the parameter is given without a value, false when it is empty
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, QueryError> {
+        let entries = self.iter().map(|(key, value)| (decode(key).into_owned(), value)).collect();
+        T::deserialize(QueryDeserializer { entries, next_value: None })
+    }
+}
+
+impl Request<'_> {
+    /// The query string as `T`, see [QueryString::deserialize()]. A request without one is treated as an empty query
+    pub fn query_as<T: DeserializeOwned>(&self) -> Result<T, QueryError> {
+        match self.query() {
+            Some(query) => query.deserialize(),
+            None => QueryString::from("").deserialize()
+        }
+    }
+
+    /// The `application/x-www-form-urlencoded` body as `T`, with the same rules as [Request::query_as()].
+    /// When there is no form the error has the status of the [FormError], see [Request::form()]
+    pub fn form_as<T: DeserializeOwned>(&self) -> Result<T, QueryError> {
+        QueryString::from(self.form_body()?).deserialize()
+    }
+}
+
+/// Deserializes the whole query string as a map of parameters
+struct QueryDeserializer<'a> {
+    entries: Vec<(String, &'a Value<'a>)>,
+    next_value: Option<(String, &'a Value<'a>)>,
+}
+
+impl<'de> Deserializer<'de> for QueryDeserializer<'_> {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_map(self)
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+impl<'de> MapAccess<'de> for QueryDeserializer<'_> {
+    type Error = QueryError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, QueryError> {
+        match self.entries.pop() {
+            Some((key, value)) => {
+                let deserializer: de::value::StrDeserializer<QueryError> = key.as_str().into_deserializer();
+                let deserialized = seed.deserialize(deserializer)?;
+                self.next_value = Some((key, value));
+                Ok(Some(deserialized))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, QueryError> {
+        let (key, value) = self.next_value.take().expect("next_value_seed called before next_key_seed");
+        seed.deserialize(ValueDeserializer(value)).map_err(|e| e.in_param(&key))
+    }
+}
+
+/// Deserializes the value of one parameter
+struct ValueDeserializer<'a>(&'a Value<'a>);
+
+impl ValueDeserializer<'_> {
+    /// The value of a parameter that takes just one, a flag without a value is an empty string
+    fn single(&self) -> Result<String, QueryError> {
+        match self.0 {
+            Value::One(value) => Ok(decode(value).into_owned()),
+            Value::None => Ok(String::new()),
+            Value::Multiple(values) => Err(QueryError::new(format!("expected one value, got {}", values.len())))
+        }
+    }
+
+    fn parse<T: std::str::FromStr>(&self, expected: &str) -> Result<T, QueryError> {
+        let value = self.single()?;
+        value.parse().map_err(|_| QueryError::new(format!("expected {}, got `{}`", expected, value)))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident, $expected:literal;)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                visitor.$visit(self.parse($expected)?)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for ValueDeserializer<'_> {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self.0 {
+            Value::Multiple(_) => self.deserialize_seq(visitor),
+            _ => visitor.visit_string(self.single()?)
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        // a flag without a value turns it on, `exact=` is empty and turns it off
+        if let Value::None = self.0 {
+            return visitor.visit_bool(true);
+        }
+        match self.single()?.as_str() {
+            "true" | "1" | "on" | "yes" => visitor.visit_bool(true),
+            "" | "false" | "0" | "off" | "no" => visitor.visit_bool(false),
+            other => Err(QueryError::new(format!("expected true or false, got `{}`", other)))
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8, "an integer";
+        deserialize_i16 => visit_i16, "an integer";
+        deserialize_i32 => visit_i32, "an integer";
+        deserialize_i64 => visit_i64, "an integer";
+        deserialize_i128 => visit_i128, "an integer";
+        deserialize_u8 => visit_u8, "a positive integer";
+        deserialize_u16 => visit_u16, "a positive integer";
+        deserialize_u32 => visit_u32, "a positive integer";
+        deserialize_u64 => visit_u64, "a positive integer";
+        deserialize_u128 => visit_u128, "a positive integer";
+        deserialize_f32 => visit_f32, "a number";
+        deserialize_f64 => visit_f64, "a number";
+        deserialize_char => visit_char, "a single character";
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_string(self.single()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_string(self.single()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self.0 {
+            Value::None | Value::One("") => visitor.visit_none(),
+            _ => visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        let values = match self.0 {
+            Value::One(value) => vec![*value],
+            Value::Multiple(values) => values.clone(),
+            Value::None => Vec::new()
+        };
+        visitor.visit_seq(ValueSeq(values.into_iter()))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V
+    ) -> Result<V::Value, QueryError> {
+        // only unit variants can be written in a query string, ex ?sort=newest
+        visitor.visit_enum(self.single()?.into_deserializer())
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier
+    }
+}
+
+/// The values of a repeated parameter, ex `?tag=red&tag=green`
+struct ValueSeq<'a>(std::vec::IntoIter<&'a str>);
+
+impl<'de> SeqAccess<'de> for ValueSeq<'_> {
+    type Error = QueryError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, QueryError> {
+        match self.0.next() {
+            Some(value) => seed.deserialize(ValueDeserializer(&Value::One(value))).map(Some),
+            None => Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Sort {
+        Newest,
+        Oldest,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Search {
+        q: String,
+        page: Option<u32>,
+        #[serde(default)]
+        exact: bool,
+        #[serde(default)]
+        tag: Vec<String>,
+        sort: Option<Sort>,
+        min_price: Option<f64>,
+    }
+
+    fn search(query: &str) -> Result<Search, QueryError> {
+        QueryString::from(query).deserialize()
+    }
+
+    #[test]
+    fn typed_fields() {
+        let parsed = search("q=red+apples%21&page=2&exact&tag=fruit&tag=red&sort=newest&min_price=1.5&unknown=1").unwrap();
+        assert_eq!(parsed, Search {
+            q: Str!("red apples!"),
+            page: Some(2),
+            exact: true,
+            tag: vec![Str!("fruit"), Str!("red")],
+            sort: Some(Sort::Newest),
+            min_price: Some(1.5)
+        });
+
+        let parsed = search("q=&page=&tag=one&exact=false").unwrap();
+        assert_eq!(parsed, Search { q: Str!(""), page: None, exact: false, tag: vec![Str!("one")], sort: None, min_price: None });
+        assert!(search("q=a&exact").unwrap().exact);
+        assert!(!search("q=a&exact=").unwrap().exact);
+    }
+
+    #[test]
+    fn descriptive_errors() {
+        let error = |query| search(query).unwrap_err().to_string();
+        assert_eq!(error("page=1"), "Missing parameter `q`");
+        assert_eq!(error("q=a&page=two"), "Invalid parameter `page`: expected a positive integer, got `two`");
+        assert_eq!(error("q=a&page=-1"), "Invalid parameter `page`: expected a positive integer, got `-1`");
+        assert_eq!(error("q=a&q=b"), "Invalid parameter `q`: expected one value, got 2");
+        assert_eq!(error("q=a&exact=maybe"), "Invalid parameter `exact`: expected true or false, got `maybe`");
+        assert!(error("q=a&sort=random").starts_with("Invalid parameter `sort`: unknown variant `random`"));
+    }
+
+    #[test]
+    fn from_request() {
+        #[derive(Deserialize)]
+        struct Page {
+            page: Option<u32>,
+        }
+
+        let req = Request::try_from(&b"GET /list HTTP/1.1\r\n\r\n"[..]).unwrap();
+        assert_eq!(req.query_as::<Page>().unwrap().page, None);
+        let req = Request::try_from(&b"GET /list?page=3 HTTP/1.1\r\n\r\n"[..]).unwrap();
+        assert_eq!(req.query_as::<Page>().unwrap().page, Some(3));
+    }
+
+    #[test]
+    fn from_form() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Signup {
+            name: String,
+            age: u8,
+            #[serde(default)]
+            interests: Vec<String>,
+        }
+
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nname=Jo+Smith&age=31&interests=a%26b&interests=c";
+        let req = Request::try_from(&raw[..]).unwrap();
+        assert_eq!(req.form_as::<Signup>().unwrap(), Signup { name: Str!("Jo Smith"), age: 31, interests: vec![Str!("a&b"), Str!("c")] });
+
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nname=Jo&age=old";
+        let error = Request::try_from(&raw[..]).unwrap().form_as::<Signup>().unwrap_err();
+        assert_eq!(error.to_string(), "Invalid parameter `age`: expected a positive integer, got `old`");
+
+        assert_eq!(error.status(), StatusCode::BadRequest);
+
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{}";
+        let error = Request::try_from(&raw[..]).unwrap().form_as::<Signup>().unwrap_err();
+        assert_eq!(error.status(), StatusCode::UnsupportedMediaType);
+        let raw = b"POST /signup HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nname=\xff&age=1";
+        let error = Request::try_from(&raw[..]).unwrap().form_as::<Signup>().unwrap_err();
+        assert_eq!(error.status(), StatusCode::BadRequest);
+    }
+}
@@ -0,0 +1,702 @@
+use std::{
+    io::{self, Read, Write, Result as IoResult},
+    net::{TcpStream, SocketAddr},
+    thread,
+    time::Duration
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use sha1::{Digest, Sha1};
+
+use super::{Method, Request, Response, StatusCode};
+
+/*
+EXAMPLE HANDSHAKE:
+
+GET /live HTTP/1.1\r\n
+Upgrade: websocket\r\n
+Connection: Upgrade\r\n
+Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n
+Sec-WebSocket-Version: 13\r\n
+\r\n
+
+HTTP/1.1 101 Switching Protocols\r\n
+Upgrade: websocket\r\n
+Connection: Upgrade\r\n
+Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n
+\r\n
+*/
+
+/// Appended to the client's key before hashing it, fixed by RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Whether `req` asks to switch the connection to a WebSocket
+pub fn is_upgrade(req: &Request) -> bool {
+    req.headers().has_token("Upgrade", "websocket") && req.headers().has_token("Connection", "upgrade")
+}
+
+/// The `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key.as_bytes());
+    sha.update(ACCEPT_GUID.as_bytes());
+    BASE64.encode(sha.finalize())
+}
+
+/// Completes the WebSocket handshake for `req` and runs `on_open` with the connection on a thread of its own,
+/// so an open WebSocket doesn't hold on to one of the server's pool threads. The handler returns as soon as
+/// the handshake is sent. Requests that aren't a valid upgrade get a 400 (426 for an unsupported version)
+/// and `on_open` isn't called
+pub fn accept(req: &Request, res: &mut Response, on_open: impl FnOnce(WebSocket) + Send + 'static) -> IoResult<()> {
+    if *req.method() != Method::GET || !is_upgrade(req) {
+        return res.send_error(StatusCode::BadRequest, "Expected a WebSocket upgrade request.");
+    }
+    if req.headers().get("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        res.set_header("Sec-WebSocket-Version", "13");
+        return res.send_error(StatusCode::UpgradeRequired, "Only version 13 of the WebSocket protocol is supported.");
+    }
+    // the key is 16 random bytes in base64
+    let key = match req.headers().get("Sec-WebSocket-Key").map(str::trim) {
+        Some(key) if BASE64.decode(key).is_ok_and(|bytes| bytes.len() == 16) => key,
+        _ => return res.send_error(StatusCode::BadRequest, "Missing or invalid Sec-WebSocket-Key.")
+    };
+    let connection = res.take_connection()
+        .ok_or_else(|| err!(Unsupported, "Can't upgrade", "The response isn't written to a socket that can be upgraded"))?;
+
+    res.status = StatusCode::SwitchingProtocols;
+    res.body = None;
+    res.set_header("Upgrade", "websocket")
+        .set_header("Connection", "Upgrade")
+        .set_header("Sec-WebSocket-Accept", accept_key(key))
+        .send_head()?;
+
+    let socket = WebSocket::new(connection)?;
+    thread::Builder::new()
+        .name(Str!("websocket"))
+        .spawn(move || on_open(socket))?;
+    Ok(())
+}
+
+/// A complete message, reassembled from its fragments
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Pings are answered with a pong automatically, they are passed on in case the handler cares
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The other side started or answered the closing handshake
+    Close(Option<CloseFrame>),
+}
+
+/// The status code and reason of a close frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+/// Status codes for closing a WebSocket, see RFC 6455 section 7.4
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CloseCode {
+    Normal,
+    GoingAway,
+    ProtocolError,
+    /// The message type isn't one the endpoint accepts
+    Unsupported,
+    /// Text that isn't UTF-8
+    InvalidPayload,
+    PolicyViolation,
+    TooBig,
+    MissingExtension,
+    InternalError,
+    /// Application codes (3000-4999) and registered codes without a variant
+    Other(u16),
+}
+
+impl CloseCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::Normal => 1000,
+            Self::GoingAway => 1001,
+            Self::ProtocolError => 1002,
+            Self::Unsupported => 1003,
+            Self::InvalidPayload => 1007,
+            Self::PolicyViolation => 1008,
+            Self::TooBig => 1009,
+            Self::MissingExtension => 1010,
+            Self::InternalError => 1011,
+            Self::Other(code) => *code
+        }
+    }
+
+    /// Returns [None] for codes that can't appear in a close frame, like 1005 and 1006 which are only for reporting
+    pub fn from_code(code: u16) -> Option<Self> {
+        let close_code = match code {
+            1000 => Self::Normal,
+            1001 => Self::GoingAway,
+            1002 => Self::ProtocolError,
+            1003 => Self::Unsupported,
+            1007 => Self::InvalidPayload,
+            1008 => Self::PolicyViolation,
+            1009 => Self::TooBig,
+            1010 => Self::MissingExtension,
+            1011 => Self::InternalError,
+            1012..=1014 | 3000..=4999 => Self::Other(code),
+            _ => return None
+        };
+        Some(close_code)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xA,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        let opcode = match bits {
+            0x0 => Self::Continuation,
+            0x1 => Self::Text,
+            0x2 => Self::Binary,
+            0x8 => Self::Close,
+            0x9 => Self::Ping,
+            0xA => Self::Pong,
+            _ => return None
+        };
+        Some(opcode)
+    }
+
+    fn is_control(&self) -> bool {
+        matches!(self, Self::Close | Self::Ping | Self::Pong)
+    }
+}
+
+struct Frame {
+    fin: bool,
+    opcode: Opcode,
+    payload: Vec<u8>,
+}
+
+/// Why reading a message failed, protocol errors close the connection with their code
+enum ReadError {
+    Io(io::Error),
+    Protocol(CloseCode, &'static str),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// The server side of a WebSocket connection, usually created by [accept()]
+pub struct WebSocket {
+    stream: TcpStream,
+    /// Bytes read from the stream that don't make up a whole frame yet
+    buffer: Vec<u8>,
+    /// The type and data so far of a fragmented message
+    fragments: Option<(Opcode, Vec<u8>)>,
+    max_message_size: usize,
+    max_frame_size: usize,
+    close_sent: bool,
+    /// The connection is closed, either by the closing handshake or because it dropped
+    closed: bool,
+}
+
+impl WebSocket {
+    /// Wraps a connection that has already completed the handshake
+    pub fn new(stream: TcpStream) -> IoResult<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            buffer: Vec::new(),
+            fragments: None,
+            max_message_size: 16 * 2_usize.pow(20),
+            max_frame_size: usize::MAX,
+            close_sent: false,
+            closed: false
+        })
+    }
+
+    /// Messages (after reassembling fragments) bigger than this close the connection with [CloseCode::TooBig], 16MiB by default
+    pub fn set_max_message_size(&mut self, size: usize) -> &mut Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Text and binary messages longer than `size` are sent as several fragments. Unlimited by default
+    pub fn set_max_frame_size(&mut self, size: usize) -> &mut Self {
+        self.max_frame_size = size.max(1);
+        self
+    }
+
+    /// How long [WebSocket::recv()] waits for data, [None] waits forever.
+    /// A timed out `recv` can be retried, partially received frames are kept
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr().ok()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Waits for the next message. Returns [None] once the connection is closed, after the
+    /// [Message::Close] of a closing handshake or if it dropped without one.
+    /// A protocol error from the client closes the connection with the matching [CloseCode] and returns an error
+    pub fn recv(&mut self) -> IoResult<Option<Message>> {
+        if self.closed {
+            return Ok(None);
+        }
+        match self.read_message() {
+            Ok(message) => Ok(message),
+            Err(ReadError::Io(e)) => Err(e),
+            Err(ReadError::Protocol(code, reason)) => {
+                eprintln!("Closing WebSocket after a protocol error: {}", reason);
+                // the connection is unusable either way, failing to say why doesn't matter
+                let _ = self.send_close(Some(CloseFrame { code, reason: Str!(reason) }));
+                self.closed = true;
+                Err(err!(InvalidData, "WebSocket protocol error", "{}", reason))
+            }
+        }
+    }
+
+    /// Sends `message`, fragmenting text and binary messages longer than the max frame size
+    pub fn send(&mut self, message: Message) -> IoResult<()> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (Opcode::Text, text.into_bytes()),
+            Message::Binary(data) => (Opcode::Binary, data),
+            Message::Ping(data) => return self.send_control(Opcode::Ping, &data),
+            Message::Pong(data) => return self.send_control(Opcode::Pong, &data),
+            Message::Close(frame) => return self.send_close(frame)
+        };
+
+        // an empty message is still one frame
+        let mut chunks = payload.chunks(self.max_frame_size).peekable();
+        if chunks.peek().is_none() {
+            return self.send_frame(true, opcode, &[]);
+        }
+        let mut opcode = opcode;
+        while let Some(chunk) = chunks.next() {
+            self.send_frame(chunks.peek().is_none(), opcode, chunk)?;
+            opcode = Opcode::Continuation;
+        }
+        Ok(())
+    }
+
+    pub fn send_text(&mut self, text: &str) -> IoResult<()> {
+        self.send(Message::Text(Str!(text)))
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> IoResult<()> {
+        self.send(Message::Binary(data.to_vec()))
+    }
+
+    pub fn ping(&mut self, data: &[u8]) -> IoResult<()> {
+        self.send_control(Opcode::Ping, data)
+    }
+
+    /// Starts the closing handshake. Keep calling [WebSocket::recv()] until it returns [None] to wait for the client's answer
+    pub fn close(&mut self, code: CloseCode, reason: &str) -> IoResult<()> {
+        self.send_close(Some(CloseFrame { code, reason: Str!(reason) }))
+    }
+
+    fn send_close(&mut self, frame: Option<CloseFrame>) -> IoResult<()> {
+        if self.close_sent {
+            return Ok(());
+        }
+        let payload = match frame {
+            Some(frame) => {
+                let mut payload = frame.code.code().to_be_bytes().to_vec();
+                payload.extend_from_slice(frame.reason.as_bytes());
+                payload
+            },
+            None => Vec::new()
+        };
+        self.send_control(Opcode::Close, &payload)?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    fn send_control(&mut self, opcode: Opcode, payload: &[u8]) -> IoResult<()> {
+        if payload.len() > 125 {
+            return Err(err!(InvalidInput, "Control frame too long", "Control frames can carry at most 125 bytes, got {}", payload.len()));
+        }
+        self.send_frame(true, opcode, payload)
+    }
+
+    fn send_frame(&mut self, fin: bool, opcode: Opcode, payload: &[u8]) -> IoResult<()> {
+        if self.close_sent {
+            return Err(err!(NotConnected, "WebSocket closed", "Can't send after the WebSocket was closed"));
+        }
+        self.stream.write_all(&encode_frame(fin, opcode, payload, None))?;
+        self.stream.flush()
+    }
+
+    /// Reads frames until a whole message is in, answering pings and close frames on the way
+    fn read_message(&mut self) -> Result<Option<Message>, ReadError> {
+        loop {
+            let frame = match parse_frame(&self.buffer, self.max_message_size)? {
+                Some((frame, used)) => {
+                    self.buffer.drain(..used);
+                    frame
+                },
+                None => {
+                    if self.fill()? == 0 {
+                        // dropped without a closing handshake
+                        self.closed = true;
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            };
+
+            match frame.opcode {
+                Opcode::Ping => {
+                    self.send_control(Opcode::Pong, &frame.payload)?;
+                    return Ok(Some(Message::Ping(frame.payload)));
+                },
+                Opcode::Pong => return Ok(Some(Message::Pong(frame.payload))),
+                Opcode::Close => {
+                    let close_frame = parse_close(&frame.payload)?;
+                    // echo the code back to finish the handshake, unless we started it
+                    self.send_close(close_frame.clone().map(|frame| CloseFrame { reason: String::new(), ..frame }))?;
+                    self.closed = true;
+                    return Ok(Some(Message::Close(close_frame)));
+                },
+                Opcode::Text | Opcode::Binary => {
+                    if self.fragments.is_some() {
+                        return Err(ReadError::Protocol(CloseCode::ProtocolError, "Expected the continuation of a fragmented message"));
+                    }
+                    if frame.fin {
+                        return to_message(frame.opcode, frame.payload).map(Some);
+                    }
+                    self.fragments = Some((frame.opcode, frame.payload));
+                },
+                Opcode::Continuation => {
+                    let (opcode, mut data) = self.fragments.take()
+                        .ok_or(ReadError::Protocol(CloseCode::ProtocolError, "Continuation frame without a message to continue"))?;
+                    if data.len() + frame.payload.len() > self.max_message_size {
+                        return Err(ReadError::Protocol(CloseCode::TooBig, "Message is too big"));
+                    }
+                    data.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        return to_message(opcode, data).map(Some);
+                    }
+                    self.fragments = Some((opcode, data));
+                }
+            }
+        }
+    }
+
+    /// Reads whatever is available into the buffer, returns 0 when the connection closed
+    fn fill(&mut self) -> IoResult<usize> {
+        let mut chunk = [0; 4096];
+        let read = self.stream.read(&mut chunk)?;
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(read)
+    }
+}
+
+/// Says goodbye to the client if the handler is done with the connection without closing it
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.close(CloseCode::GoingAway, "");
+        }
+    }
+}
+
+/// Parses one client frame from the start of `buffer`, returning it and how many bytes it took up,
+/// or [None] if the buffer doesn't hold all of it yet
+fn parse_frame(buffer: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>, ReadError> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let fin = buffer[0] & 0x80 != 0;
+    // the reserved bits are only used by extensions, and we don't negotiate any
+    if buffer[0] & 0x70 != 0 {
+        return Err(ReadError::Protocol(CloseCode::ProtocolError, "Reserved bits are set"));
+    }
+    let opcode = Opcode::from_bits(buffer[0] & 0x0F)
+        .ok_or(ReadError::Protocol(CloseCode::ProtocolError, "Unknown opcode"))?;
+    if buffer[1] & 0x80 == 0 {
+        return Err(ReadError::Protocol(CloseCode::ProtocolError, "Frames from the client must be masked"));
+    }
+
+    let (length, start) = match buffer[1] & 0x7F {
+        126 if buffer.len() < 4 => return Ok(None),
+        126 => (u16::from_be_bytes([buffer[2], buffer[3]]) as u64, 4),
+        127 if buffer.len() < 10 => return Ok(None),
+        127 => (u64::from_be_bytes(buffer[2..10].try_into().unwrap()), 10),
+        length => (length as u64, 2)
+    };
+    if opcode.is_control() && (!fin || length > 125) {
+        return Err(ReadError::Protocol(CloseCode::ProtocolError, "Control frames can't be fragmented or longer than 125 bytes"));
+    }
+    if length > max_size as u64 {
+        return Err(ReadError::Protocol(CloseCode::TooBig, "Message is too big"));
+    }
+
+    let length = length as usize;
+    let end = start + 4 + length;
+    if buffer.len() < end {
+        return Ok(None);
+    }
+    let mask = &buffer[start..start + 4];
+    let payload = buffer[start + 4..end].iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ mask[i % 4])
+        .collect();
+
+    Ok(Some((Frame { fin, opcode, payload }, end)))
+}
+
+/// Encodes a frame, clients have to `mask` what they send and servers must not
+fn encode_frame(fin: bool, opcode: Opcode, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(if fin { 0x80 } else { 0 } | opcode as u8);
+
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        length @ 0..=125 => frame.push(mask_bit | length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        },
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+
+    match mask {
+        Some(mask) => {
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        },
+        None => frame.extend_from_slice(payload)
+    }
+    frame
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, ReadError> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(ReadError::Protocol(CloseCode::ProtocolError, "Close frame with a partial status code")),
+        [high, low, reason @ ..] => {
+            let code = CloseCode::from_code(u16::from_be_bytes([*high, *low]))
+                .ok_or(ReadError::Protocol(CloseCode::ProtocolError, "Invalid close code"))?;
+            let reason = String::from_utf8(reason.to_vec())
+                .map_err(|_| ReadError::Protocol(CloseCode::InvalidPayload, "Close reason isn't UTF-8"))?;
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}
+
+fn to_message(opcode: Opcode, data: Vec<u8>) -> Result<Message, ReadError> {
+    match opcode {
+        Opcode::Text => String::from_utf8(data)
+            .map(Message::Text)
+            .map_err(|_| ReadError::Protocol(CloseCode::InvalidPayload, "Text message isn't UTF-8")),
+        _ => Ok(Message::Binary(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+    use crate::http::{
+        RequestHandler,
+        parsed_response::ParsedResponse,
+        test_client::{TestClient, spawn_test_server},
+        client::Client
+    };
+
+    struct Echo;
+
+    impl RequestHandler for Echo {
+        fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+            if req.path() == "/plain" {
+                return res.ok(Some(Str!("plain")));
+            }
+            let fragmented = req.path() == "/fragmented";
+            accept(req, res, move |mut socket| {
+                if fragmented {
+                    socket.set_max_frame_size(4);
+                }
+                while let Ok(Some(message)) = socket.recv() {
+                    if matches!(message, Message::Text(_) | Message::Binary(_)) && socket.send(message).is_err() {
+                        break;
+                    }
+                }
+            })
+        }
+    }
+
+    struct TestSocket {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl TestSocket {
+        fn connect(addr: &str, path: &str) -> Self {
+            let mut stream = TcpStream::connect(addr).expect("failed to connect");
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            // in one write, the server reads the request with a single read
+            let handshake = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+                path, addr
+            );
+            stream.write_all(handshake.as_bytes()).unwrap();
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let head = ParsedResponse::read_head(&mut reader).expect("failed to read handshake");
+            assert_eq!(head.status, StatusCode::SwitchingProtocols);
+            assert_eq!(head.headers.get("Sec-WebSocket-Accept"), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+            Self { stream, reader }
+        }
+
+        fn send(&mut self, fin: bool, opcode: Opcode, payload: &[u8]) {
+            self.stream.write_all(&encode_frame(fin, opcode, payload, Some([0x37, 0xfa, 0x21, 0x3d]))).unwrap();
+        }
+
+        fn read(&mut self) -> (bool, Opcode, Vec<u8>) {
+            let mut head = [0; 2];
+            self.reader.read_exact(&mut head).expect("failed to read frame");
+            assert_eq!(head[1] & 0x80, 0, "server frames aren't masked");
+            let length = match head[1] & 0x7F {
+                126 => {
+                    let mut length = [0; 2];
+                    self.reader.read_exact(&mut length).unwrap();
+                    u16::from_be_bytes(length) as usize
+                },
+                length => length as usize
+            };
+            let mut payload = vec![0; length];
+            self.reader.read_exact(&mut payload).unwrap();
+            (head[0] & 0x80 != 0, Opcode::from_bits(head[0] & 0x0F).unwrap(), payload)
+        }
+    }
+
+    #[test]
+    fn handshake_key() {
+        // the example from RFC 6455
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn rejected_upgrades() {
+        let client = TestClient::new(Echo);
+        let res = client.get("/").expect("request failed");
+        assert_eq!(res.status, StatusCode::BadRequest);
+
+        let res = client.request(Method::GET, "/")
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .header("Sec-WebSocket-Version", "8")
+            .send().expect("request failed");
+        assert_eq!(res.status, StatusCode::UpgradeRequired);
+        assert_eq!(res.headers.get("Sec-WebSocket-Version"), Some("13"));
+
+        let res = client.request(Method::GET, "/")
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Key", "short")
+            .header("Sec-WebSocket-Version", "13")
+            .send().expect("request failed");
+        assert_eq!(res.status, StatusCode::BadRequest);
+
+        // valid, but there's no socket to hand over
+        let res = client.request(Method::GET, "/")
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade")
+            .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+            .header("Sec-WebSocket-Version", "13")
+            .send();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn echo() {
+        let mut socket = TestSocket::connect(&spawn_test_server(Echo), "/");
+
+        socket.send(true, Opcode::Text, b"Bananas");
+        assert_eq!(socket.read(), (true, Opcode::Text, b"Bananas".to_vec()));
+
+        let big = vec![7; 300];
+        socket.send(true, Opcode::Binary, &big);
+        assert_eq!(socket.read(), (true, Opcode::Binary, big));
+
+        // fragments with a ping in the middle
+        socket.send(false, Opcode::Text, b"App");
+        socket.send(true, Opcode::Ping, b"still there?");
+        socket.send(true, Opcode::Continuation, b"les");
+        assert_eq!(socket.read(), (true, Opcode::Pong, b"still there?".to_vec()));
+        assert_eq!(socket.read(), (true, Opcode::Text, b"Apples".to_vec()));
+
+        socket.send(true, Opcode::Close, &[0x03, 0xE8]);
+        assert_eq!(socket.read(), (true, Opcode::Close, vec![0x03, 0xE8]));
+    }
+
+    #[test]
+    fn fragmented_send() {
+        let mut socket = TestSocket::connect(&spawn_test_server(Echo), "/fragmented");
+        socket.send(true, Opcode::Text, b"Bananas!!");
+
+        assert_eq!(socket.read(), (false, Opcode::Text, b"Bana".to_vec()));
+        assert_eq!(socket.read(), (false, Opcode::Continuation, b"nas!".to_vec()));
+        assert_eq!(socket.read(), (true, Opcode::Continuation, b"!".to_vec()));
+    }
+
+    #[test]
+    fn protocol_errors() {
+        let addr = spawn_test_server(Echo);
+
+        let mut socket = TestSocket::connect(&addr, "/");
+        socket.stream.write_all(&encode_frame(true, Opcode::Text, b"unmasked", None)).unwrap();
+        let (_, opcode, payload) = socket.read();
+        assert_eq!(opcode, Opcode::Close);
+        assert_eq!(payload[..2], 1002_u16.to_be_bytes());
+
+        let mut socket = TestSocket::connect(&addr, "/");
+        socket.send(true, Opcode::Text, &[0xC3, 0x28]);
+        let (_, opcode, payload) = socket.read();
+        assert_eq!(opcode, Opcode::Close);
+        assert_eq!(payload[..2], 1007_u16.to_be_bytes());
+
+        let mut socket = TestSocket::connect(&addr, "/");
+        socket.send(true, Opcode::Continuation, b"nothing to continue");
+        assert_eq!(socket.read().2[..2], 1002_u16.to_be_bytes());
+    }
+
+    #[test]
+    fn leaves_pool_threads() {
+        let addr = spawn_test_server(Echo);
+        // more open sockets than the pool has threads
+        let sockets: Vec<TestSocket> = (0..rayon::current_num_threads() + 2)
+            .map(|_| TestSocket::connect(&addr, "/"))
+            .collect();
+
+        let client = Client::new();
+        let res = client.get(&format!("http://{}/plain", addr)).expect("request failed");
+        assert_eq!(res.text(), "plain");
+
+        for mut socket in sockets {
+            socket.send(true, Opcode::Text, b"still open");
+            assert_eq!(socket.read().2, b"still open");
+        }
+    }
+}
@@ -17,7 +17,7 @@ macro_rules! Str {
 
 mod website_handler;
 mod http;
-use http::Server;
+use http::{Server, Compression, ErrorPages};
 use website_handler::WebsiteHandler;
 use std::{env, sync::Arc};
 
@@ -26,6 +26,11 @@ fn main() {
     let default_public = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
     let pub_dir = env::var("default_public").unwrap_or(default_public);
     println!("Public path set to: {}", pub_dir);
+    server.set_compression(Compression::default());
+    match ErrorPages::load_dir(&pub_dir) {
+        Ok(pages) => { server.set_error_pages(pages); },
+        Err(e) => eprintln!("Failed to load error pages from {}: {}", pub_dir, e)
+    }
     let handler = WebsiteHandler::new(pub_dir);
     server.run(Arc::new(handler));
 }
@@ -1,100 +1,72 @@
-use std::io::{
-    ErrorKind, 
-    Result as IoResult
-};
-use std::fs;
+use std::io::Result as IoResult;
 use super::http::{
     RequestHandler,
     Request,
     Response,
+    Router,
+    StaticFiles,
+    cache_rules::{CacheRule, PathPattern},
 };
 
+/// File names with an 8 character hex hash between dots, ex `app.3f9a1c2e.js`. Shorter runs of hex letters
+/// are often plain words (`app.facade.js`), and those files must not be cached forever
+const FINGERPRINTED: &str = "*.[0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f][0-9a-f].*";
+
+/// The handler for this website, serves everything in the public directory
 pub struct WebsiteHandler {
-    public_dir: String
+    router: Router
 }
 
 impl WebsiteHandler {
+    /// Fingerprinted assets (`app.3f9a1c2e.js`) are cached forever, pages are revalidated on every request
     pub fn new(public_dir: String) -> Self {
-        Self { public_dir }
+        let mut files = StaticFiles::new(public_dir);
+        files
+            .add_cache_rule(CacheRule::immutable(PathPattern::Glob(Str!(FINGERPRINTED))))
+            .add_cache_rule(CacheRule::no_cache(PathPattern::Glob(Str!("*.html"))));
+        Self::with_static_files(files)
     }
-    fn read_file(&self, file_path: &str) -> IoResult<String> {
-        let path = format!("{}/{}", self.public_dir, file_path);
 
-        match fs::canonicalize(&path) {
-            Ok(pb) => {
-                if pb.starts_with(&self.public_dir) {
-                    fs::read_to_string(pb)
-                } else {
-                    Err(err!(PermissionDenied, "Directory traversal attack attempted", "Attempted Path: {}", path))
-                }
-            },
-            Err(e) => Err(e)
-        }
+    /// Creates a handler that serves `files`, for when the defaults of [StaticFiles] need changing
+    pub fn with_static_files(files: StaticFiles) -> Self {
+        let mut router = Router::new();
+        router.mount("/", files);
+        Self { router }
     }
 }
 
 impl RequestHandler for WebsiteHandler {
-
-    fn get(&self, req: &Request, res: &mut Response) -> IoResult<()> {
-        match req.path() {
-            "/" => {
-                match self.read_file("index.html") {
-                    Ok(body) => {
-                        res.ok(Some(body))
-                    },
-                    Err(e) => {
-                        res.gen_404().append(format!("<p>{}</p>",e.to_string())).send()
-                    }
-                }
-            },
-            "/apples" => res.gen_404().append(Str!("We only have bananas")).send(),
-            path => {
-                match self.read_file(path) {
-                    Ok(body) => res.ok(/*Now you're just*/Some(body)/*That I used to know*/),
-                    Err(e) => {
-                        if e.kind() == ErrorKind::PermissionDenied {
-                            return res.send_403();
-                        }
-                        res.send_404()
-                    }
-                }
-            }
-        }
-    }
-
-    fn post(&self, _req: &Request, res: &mut Response) -> IoResult<()> {
-        res.gen_404().append(Str!("We don't recieve mail")).send()
+    fn handle(&self, req: &Request, res: &mut Response) -> IoResult<()> {
+        self.router.handle(req, res)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::http::{StatusCode, test_client::TestClient};
 
-    // uses src/http as public so it doesn't rely on any actual files being in the public dir
     #[test]
-    fn read_existing_file() {
-        let handler = WebsiteHandler::new(format!("{}/src/http", env!("CARGO_MANIFEST_DIR")));
-        match handler.read_file("server.rs") {
-            Ok(s) => assert!(s.len() > 0),
-            Err(e) => panic!("Error reading server.rs file! {}", e),
-        }
-    }
-    
-    #[test]
-    fn file_not_found() {
-        let handler = WebsiteHandler::new(format!("{}/src/http", env!("CARGO_MANIFEST_DIR")));
-        match handler.read_file("invalid_file") {
-            Err(e) => assert_eq!(e.kind(), ErrorKind::NotFound),
-            Ok(_) => panic!("invalid_file read returned Ok"),
-        }
+    fn serves_public_dir() {
+        let client = TestClient::new(WebsiteHandler::new(format!("{}/public", env!("CARGO_MANIFEST_DIR"))));
+
+        let res = client.get("/").expect("request failed");
+        assert_eq!(res.status, StatusCode::Ok);
+        assert!(res.text().contains("<html>"));
+        assert_eq!(res.headers.get("Cache-Control"), Some("no-cache"));
+
+        let res = client.get("/style.css").expect("request failed");
+        assert_eq!(res.headers.get("Content-Type"), Some("text/css; charset=utf-8"));
+        assert_eq!(client.get("/apples").expect("request failed").status, StatusCode::NotFound);
     }
+
     #[test]
-    fn directory_traversal() {
-        let handler = WebsiteHandler::new(format!("{}/src/http", env!("CARGO_MANIFEST_DIR")));
-        match handler.read_file("../main.rs") {
-            Err(e) => assert_eq!(e.kind(), ErrorKind::PermissionDenied),
-            Ok(_) => panic!("DIRECTORY TRAVERSAL ATTACK WAS SUCCESSFUL"),
+    fn fingerprinted() {
+        let pattern = PathPattern::Glob(Str!(FINGERPRINTED));
+        assert!(pattern.matches("/js/app.3f9a1c2e.js"));
+        assert!(pattern.matches("/app.0123abcd.min.css"));
+        for name in ["/app.facade.js", "/x.decade.css", "/site.beaded.png", "/app.abc123.js", "/app.3f9a1c2e0.js", "/app.js"] {
+            assert!(!pattern.matches(name), "{} is not fingerprinted", name);
         }
     }
-}
\ No newline at end of file
+}